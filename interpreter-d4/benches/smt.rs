@@ -0,0 +1,61 @@
+//! Insert and proof throughput of the collapsed tree against the layout it replaced, which kept all
+//! 257 nodes of every path. That both agree on every root and proof is checked by the tests.
+//!
+//! `cargo bench -- 2000` sets the number of values, 1000 by default.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use interpreter::{Field, GoldilocksField, Hash, Interpreter, NodeStore, PoseidonHash};
+use plonky2::plonk::config::Hasher;
+
+/// The previous layout, kept as the baseline
+#[path = "../src/test/legacy.rs"]
+#[allow(dead_code)]
+mod legacy;
+
+use legacy::Legacy;
+
+/// Every seventh value is zero
+fn value(i: usize) -> GoldilocksField {
+    GoldilocksField::from_canonical_u64(i as u64 % 7)
+}
+
+fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    (f(), start.elapsed())
+}
+
+/// Fills a collapsed tree and proves every address
+fn compare<S: NodeStore>(name: &str, mut tree: Interpreter<S>, addrs: &[Hash], baseline: (Duration, Duration)) -> Result<()> {
+    let (inserted, insert) = timed(|| addrs.iter().enumerate().try_for_each(|(i, &addr)| tree.insert(addr, value(i))));
+    inserted?;
+    let (proofs, prove) = timed(|| addrs.iter().map(|&addr| tree.prove(addr)).collect::<Result<Vec<_>>>());
+    proofs?;
+    report(name, addrs.len(), (insert, prove), baseline);
+    Ok(())
+}
+
+fn report(name: &str, count: usize, (insert, prove): (Duration, Duration), baseline: (Duration, Duration)) {
+    let rate = |d: Duration| count as f64 / d.as_secs_f64();
+    let speedup = |d: Duration, base: Duration| base.as_secs_f64() / d.as_secs_f64();
+    println!("{:<8} {:>10.0} {:>10.0} {:>8.2}x {:>8.2}x", name, rate(insert), rate(prove), speedup(insert, baseline.0), speedup(prove, baseline.1));
+}
+
+fn main() -> Result<()> {
+    let count = std::env::args().skip(1).find_map(|a| a.parse().ok()).unwrap_or(1000u64);
+    // The last eighth of the writes update earlier addresses
+    let addrs: Vec<Hash> = (0..count).map(|i| PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(i % (count - count / 8))])).collect();
+
+    let mut legacy = Legacy::default();
+    let (_, insert) = timed(|| addrs.iter().enumerate().for_each(|(i, &addr)| legacy.insert(addr, value(i))));
+    let (_, prove) = timed(|| addrs.iter().map(|addr| legacy.get_merkle_proof(&Legacy::get_path(addr))).collect::<Vec<_>>());
+    println!("{} writes\n{:<8} {:>10} {:>10} {:>9} {:>9}", count, "tree", "inserts/s", "proofs/s", "inserts", "proofs");
+    report("legacy", addrs.len(), (insert, prove), (insert, prove));
+
+    compare("memory", Interpreter::new(), &addrs, (insert, prove))?;
+    let dir = std::env::temp_dir().join(format!("interpreter-bench-{}", std::process::id()));
+    compare("file", Interpreter::open(&dir)?, &addrs, (insert, prove))?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
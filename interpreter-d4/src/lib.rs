@@ -1,7 +1,10 @@
+mod store;
 mod transaction;
 mod zk;
-pub mod test;
+#[cfg(test)]
+mod test;
 
+pub use crate::store::{FileStore, MemoryStore, NodeStore};
 pub use crate::transaction::Transaction;
 pub use crate::zk::Circuit;
 pub use crate::zk::Field;
@@ -13,90 +16,254 @@ pub use crate::zk::PoseidonHash;
 pub use crate::zk::WitnessWrite;
 pub use crate::zk::HashOut;
 use std::array::from_fn;
-use std::iter::once;
 
 use anyhow::Result;
 use plonky2::plonk::config::Hasher;
-use std::collections::HashMap;
 use std::sync::LazyLock;
 
-pub struct Interpreter {
-    s: HashMap<Hash, GoldilocksField>,
-    smt: SparseMerkleTree,
+/// The state lives only in the tree, whose leaves keep their values
+pub struct Interpreter<S: NodeStore = MemoryStore> {
+    smt: SparseMerkleTree<S>,
+}
 
+impl Default for Interpreter {
+    fn default() -> Self { Self::new() }
 }
 
 impl Interpreter {
-    pub fn new() -> Self { 
+    pub fn new() -> Self { Self::with_store(MemoryStore::default()) }
+}
+
+impl Interpreter<FileStore> {
+    /// Opens the state kept in `dir`, creating it if needed
+    pub fn open(dir: impl AsRef<std::path::Path>) -> Result<Self> { Ok(Self::with_store(FileStore::open(dir)?)) }
+}
+
+impl<S: NodeStore> Interpreter<S> {
+    pub fn with_store(store: S) -> Self { 
         Self{
-            s: HashMap::new(),
-            smt: SparseMerkleTree::new(),
+            smt: SparseMerkleTree { nodes: store },
         }
      }
-    pub fn prove(&mut self, addr: Hash) -> (GoldilocksField, [Hash; 256]) {
-        let proof = self.smt.get_merkle_proof(&SparseMerkleTree::get_path(&addr));
-        let value = self.s.entry(addr).or_default();
-        (*value, proof)
+    pub fn prove(&self, addr: Hash) -> Result<(GoldilocksField, [Hash; 256])> {
+        self.smt.prove(addr)
     }
-    pub fn root(&self) -> Hash { self.smt.root() }
+    pub fn root(&self) -> Result<Hash> { self.smt.root() }
     pub fn transit(&mut self, tx: Transaction) -> Result<()> {
-        tx.vk.verify(self.root(), tx.new, tx.proof)?;
-        Ok(self.insert(tx.vk.address(), tx.new))
+        tx.vk.verify(self.root()?, tx.new, tx.proof)?;
+        self.insert(tx.vk.address(), tx.new)
     }
-    pub fn insert(&mut self, addr: Hash, value: GoldilocksField) { 
-        println!("addr: {:?}, value: {:?}", addr, value);
-        self.s.insert(addr, value);
-        self.smt.insert(addr, value);
+    pub fn insert(&mut self, addr: Hash, value: GoldilocksField) -> Result<()> { 
+        self.smt.insert(addr, value)
      }
-     pub fn get_path(&mut self, addr: Hash)-> [bool; 256] { SparseMerkleTree::get_path(&addr) }
+     pub fn get_path(&mut self, addr: Hash)-> [bool; 256] { SparseMerkleTree::<S>::get_path(&addr) }
 }
 
 const DEPTH: usize = 256;
-struct SparseMerkleTree {
-    nodes: HashMap<Vec<bool>, Hash>,
+/// A leaf keeps its subtree's hashes every `STRIDE` levels from the root down to `(CHECKPOINTS - 1) * STRIDE`,
+/// so moving it between the levels collapsed trees use rehashes fewer than `STRIDE` of them
+const STRIDE: usize = 8;
+const CHECKPOINTS: usize = 9;
+
+static DEFAULT_HASHS: LazyLock<[Hash; DEPTH + 1]> = LazyLock::new(|| {
+    let mut default_hashes = [PoseidonHash::hash_or_noop(&[GoldilocksField::ZERO;1]); DEPTH + 1];
+    (0..256).rev().for_each(|i| default_hashes[i]=PoseidonHash::two_to_one(default_hashes[i+1], default_hashes[i+1]));
+    default_hashes
+});
+
+/// A node's position: the first `len` bits of a path, packed as `get_path` reads them from an address
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeKey {
+    bits: [u64; 4],
+    len: u16,
 }
 
-impl  SparseMerkleTree {
+impl NodeKey {
+    const ROOT: Self = Self { bits: [0; 4], len: 0 };
 
-    fn get_path(hash: &Hash) -> [bool; 256] {    
-        from_fn(|i| hash.elements[3 - i / 64].0 >> (63 - i % 64) & 1 > 0)
+    fn path(addr: &Hash) -> Self { Self { bits: from_fn(|i| addr.elements[3 - i].0), len: DEPTH as u16 } }
+    fn depth(&self) -> usize { self.len as usize }
+    fn bit(&self, i: usize) -> bool { self.bits[i / 64] >> (63 - i % 64) & 1 > 0 }
+
+    fn prefix(&self, depth: usize) -> Self {
+        let mask = |w: usize| match depth.saturating_sub(64 * w).min(64) { 0 => 0, kept => u64::MAX << (64 - kept) };
+        Self { bits: from_fn(|w| self.bits[w] & mask(w)), len: depth as u16 }
+    }
+    fn parent(&self) -> Self { self.prefix(self.depth() - 1) }
+    fn child(&self, bit: bool) -> Self {
+        let mut child = Self { len: self.len + 1, ..*self };
+        child.bits[self.depth() / 64] |= (bit as u64) << (63 - self.depth() % 64);
+        child
     }
+    fn sibling(&self) -> Self { self.parent().child(!self.bit(self.depth() - 1)) }
 
-    fn new() -> Self {
-      
-        SparseMerkleTree {
-            nodes: HashMap::new(),
+    /// The number of leading bits shared with `other`
+    fn common(&self, other: &Self) -> usize {
+        let shared = (0..4).find(|&w| self.bits[w] != other.bits[w]).map_or(DEPTH, |w| 64 * w + (self.bits[w] ^ other.bits[w]).leading_zeros() as usize);
+        shared.min(self.depth()).min(other.depth())
+    }
+}
+
+/// Hashes the subtree holding only `path`'s leaf from its hash at depth `from` up to depth `to`
+fn fold(path: &NodeKey, hash: Hash, from: usize, to: usize) -> Hash {
+    (to..from).rev().fold(hash, |hash, i| match path.bit(i) {
+        true => PoseidonHash::two_to_one(DEFAULT_HASHS[i + 1], hash),
+        false => PoseidonHash::two_to_one(hash, DEFAULT_HASHS[i + 1]),
+    })
+}
+
+/// A subtree holding a single non-zero value, collapsed into one node
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leaf {
+    addr: Hash,
+    value: GoldilocksField,
+    /// The subtree's hash at the depth it is stored at
+    hash: Hash,
+    checkpoints: [Hash; CHECKPOINTS],
+}
+
+impl Leaf {
+    fn new(addr: Hash, value: GoldilocksField, depth: usize) -> Box<Self> {
+        let path = NodeKey::path(&addr);
+        let mut checkpoints = [fold(&path, PoseidonHash::hash_or_noop(&[value]), DEPTH, (CHECKPOINTS - 1) * STRIDE); CHECKPOINTS];
+        (0..CHECKPOINTS - 1).rev().for_each(|j| checkpoints[j] = fold(&path, checkpoints[j + 1], (j + 1) * STRIDE, j * STRIDE));
+        Box::new(Self { addr, value, hash: checkpoints[0], checkpoints }).at(depth)
+    }
+
+    /// The leaf as stored at `depth`
+    fn at(mut self: Box<Self>, depth: usize) -> Box<Self> {
+        self.hash = self.hash_at(depth);
+        self
+    }
+
+    fn hash_at(&self, depth: usize) -> Hash {
+        let path = NodeKey::path(&self.addr);
+        match depth.div_ceil(STRIDE) {
+            j if j < CHECKPOINTS => fold(&path, self.checkpoints[j], j * STRIDE, depth),
+            _ => fold(&path, PoseidonHash::hash_or_noop(&[self.value]), DEPTH, depth),
         }
     }
+}
 
-    fn root(&self) -> Hash {
-        self.get_digest(&vec![])
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// The root of a subtree holding at least two non-zero values
+    Branch(Hash),
+    Leaf(Box<Leaf>),
+}
+
+impl Node {
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Branch(hash) => *hash,
+            Node::Leaf(leaf) => leaf.hash,
+        }
     }
+}
 
-   
-    fn insert(&mut self, key: Hash, value: GoldilocksField) {
-        let mut path: Vec<bool> = Self::get_path(&key).to_vec();
+/// Keeps a branch for each subtree holding two or more non-zero values, a leaf for each largest subtree
+/// holding one and nothing for subtrees of zeros, which hash to `DEFAULT_HASHS`
+struct SparseMerkleTree<S> {
+    nodes: S,
+}
+
+impl<S: NodeStore> SparseMerkleTree<S> {
+
+    fn get_path(hash: &Hash) -> [bool; 256] {    
+        from_fn(|i| hash.elements[3 - i / 64].0 >> (63 - i % 64) & 1 > 0)
+    }
 
-       self.nodes.insert(path.clone(), PoseidonHash::hash_or_noop(&[value]));
+    fn root(&self) -> Result<Hash> {
+        self.get_digest(&NodeKey::ROOT)
+    }
+
+    /// The first node on `path` that is not a branch, and its position
+    fn find(&self, path: &NodeKey) -> Result<(NodeKey, Option<Node>)> {
+        let mut key = NodeKey::ROOT;
+        loop {
+            match self.nodes.get(&key)? {
+                Some(Node::Branch(_)) => key = path.prefix(key.depth() + 1),
+                node => return Ok((key, node)),
+            }
+        }
+    }
 
-       for _ in 0..256 {
-            path.pop();
-            
-            let [left, right] = [false, true].map(|v| self.get_digest(&path.iter().cloned().chain(once(v)).collect::<Vec<_>>()));
-            self.nodes.insert(path.clone(),  PoseidonHash::two_to_one(left, right));
+    fn insert(&mut self, addr: Hash, value: GoldilocksField) -> Result<()> {
+        let path = NodeKey::path(&addr);
+        let (key, node) = self.find(&path)?;
+        let mut key = match node {
+            Some(Node::Leaf(leaf)) if leaf.addr == addr && value == GoldilocksField::ZERO => {
+                self.nodes.remove(&key)?;
+                self.collapse(key)?
+            }
+            _ if value == GoldilocksField::ZERO => return Ok(()),
+            Some(Node::Leaf(leaf)) if leaf.addr != addr => {
+                // Both leaves move to where their paths part
+                let other = NodeKey::path(&leaf.addr);
+                let depth = path.common(&other) + 1;
+                self.nodes.put(other.prefix(depth), Node::Leaf(leaf.at(depth)))?;
+                self.nodes.put(path.prefix(depth), Node::Leaf(Leaf::new(addr, value, depth)))?;
+                path.prefix(depth)
+            }
+            _ => {
+                self.nodes.put(key, Node::Leaf(Leaf::new(addr, value, key.depth())))?;
+                key
+            }
         };
+        while key.depth() > 0 {
+            key = key.parent();
+            let [left, right] = [false, true].map(|bit| self.get_digest(&key.child(bit)));
+            self.nodes.put(key, Node::Branch(PoseidonHash::two_to_one(left?, right?)))?;
+        }
+        // A crash part way through would leave a leaf moved but not written, or stale branches
+        self.nodes.commit()
     }
 
-    fn get_digest(&self, index: &[bool]) -> Hash {
-        static DEFAULT_HASHS: LazyLock<[Hash; 257]> = LazyLock::new(|| {
-            let mut default_hashes = [PoseidonHash::hash_or_noop(&[GoldilocksField::ZERO;1]); DEPTH + 1];
-            (0..256).rev().for_each(|i| default_hashes[i]=PoseidonHash::two_to_one(default_hashes[i+1], default_hashes[i+1]));
-            default_hashes
-        });
-        self.nodes.get(index).cloned().unwrap_or(DEFAULT_HASHS[index.len()])
+    /// Moves a leaf left alone by emptying `key` up to the largest subtree it is alone in, returning its position
+    fn collapse(&mut self, mut key: NodeKey) -> Result<NodeKey> {
+        let mut alone: Option<Box<Leaf>> = None;
+        while key.depth() > 0 {
+            let sibling = key.sibling();
+            let leaf = match (alone, self.nodes.get(&sibling)?) {
+                (None, Some(Node::Leaf(leaf))) => {
+                    self.nodes.remove(&sibling)?;
+                    leaf
+                }
+                (Some(leaf), None) => {
+                    self.nodes.remove(&key)?;
+                    leaf
+                }
+                _ => break,
+            };
+            key = key.parent();
+            let leaf = leaf.at(key.depth());
+            self.nodes.put(key, Node::Leaf(leaf.clone()))?;
+            alone = Some(leaf);
+        }
+        Ok(key)
+    }
+
+    fn get_digest(&self, key: &NodeKey) -> Result<Hash> {
+        Ok(self.nodes.get(key)?.map_or(DEFAULT_HASHS[key.depth()], |node| node.hash()))
     }
    
-    pub fn get_merkle_proof(&self, key: &[bool]) -> [Hash; 256] {
-       from_fn(|i| self.get_digest(&key[0..255 - i].iter().cloned().chain(once(!key[255 - i])).collect::<Vec<_>>()))
+    /// The value at `addr` and its siblings from the leaf up
+    fn prove(&self, addr: Hash) -> Result<(GoldilocksField, [Hash; 256])> {
+        let path = NodeKey::path(&addr);
+        let (key, node) = self.find(&path)?;
+        let mut siblings: [Hash; 256] = from_fn(|i| DEFAULT_HASHS[DEPTH - i]);
+        for depth in 1..=key.depth() {
+            siblings[DEPTH - depth] = self.get_digest(&path.prefix(depth).sibling())?;
+        }
+        match node {
+            Some(Node::Leaf(leaf)) if leaf.addr == addr => Ok((leaf.value, siblings)),
+            Some(Node::Leaf(leaf)) => {
+                // Below a leaf for another address, the only sibling that is not all zeros is that leaf's subtree
+                let depth = path.common(&NodeKey::path(&leaf.addr)) + 1;
+                siblings[DEPTH - depth] = leaf.hash_at(depth);
+                Ok((GoldilocksField::ZERO, siblings))
+            }
+            _ => Ok((GoldilocksField::ZERO, siblings)),
+        }
     }
-}
\ No newline at end of file
+}
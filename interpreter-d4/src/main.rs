@@ -10,7 +10,7 @@ pub use interpreter::Hash;
 
 
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let c = Circuit::new(|builder| {
         let this = builder.add_virtual_hash_public_input();
         let root = builder.add_virtual_hash_public_input();
@@ -27,12 +27,12 @@ fn main() {
     let vk = c.vk();
     let mut s = Interpreter::new();
     for i in 0..16 {
-        let (old, path) = s.prove(vk.address());
+        let (old, path) = s.prove(vk.address())?;
         let new = old.add_one();
         println!("old:{:?}, new:{:?}", old, new);
         let proof_result = c.prove(|w, t| {
             w.set_hash_target(t.0, vk.address())?;
-            w.set_hash_target(t.1, s.root())?;
+            w.set_hash_target(t.1, s.root()?)?;
             (0..256).try_for_each(|i| {
                 w.set_hash_target(t.2.siblings[i], path[i])
             })?;
@@ -42,7 +42,7 @@ fn main() {
 
         match proof_result {
             Ok((proof, _)) => {
-                print!("Transitioning\n");
+                println!("Transitioning");
                 let tx: Transaction = Transaction { new, proof, vk: vk.clone() };
                 eprintln!("transaction[{i}]: {:?}", s.transit(tx));
             }
@@ -52,4 +52,5 @@ fn main() {
             }
         } 
     }
+    Ok(())
 }
\ No newline at end of file
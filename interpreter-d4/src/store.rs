@@ -0,0 +1,267 @@
+use std::array::from_fn;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::zk::{GoldilocksField, Hash};
+use crate::{Leaf, Node, NodeKey, CHECKPOINTS};
+
+/// Where a sparse Merkle tree keeps its nodes; positions without one hold only zeros
+pub trait NodeStore {
+    fn get(&self, key: &NodeKey) -> Result<Option<Node>>;
+    fn put(&mut self, key: NodeKey, node: Node) -> Result<()>;
+    fn remove(&mut self, key: &NodeKey) -> Result<()>;
+    /// Ends a batch of writes, which a store that outlives the process keeps all or none of
+    fn commit(&mut self) -> Result<()>;
+}
+
+#[derive(Default)]
+pub struct MemoryStore(HashMap<NodeKey, Node>);
+
+impl NodeStore for MemoryStore {
+    fn get(&self, key: &NodeKey) -> Result<Option<Node>> { Ok(self.0.get(key).cloned()) }
+    fn put(&mut self, key: NodeKey, node: Node) -> Result<()> {
+        self.0.insert(key, node);
+        Ok(())
+    }
+    fn remove(&mut self, key: &NodeKey) -> Result<()> {
+        self.0.remove(key);
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<()> { Ok(()) }
+}
+
+/// A record starts with the key's length and bits, then a tag for what follows: nothing for a removed
+/// node, a branch's hash, or a leaf's address, hash, checkpoints and value. A commit record ending
+/// a batch has a zero key and nothing after its tag.
+const KEY: usize = 2 + 32;
+const BRANCH: usize = 32;
+const LEAF: usize = 32 * (2 + CHECKPOINTS) + 8;
+const COMMIT: u8 = 3;
+/// The index starts with its capacity, its number of used slots and the node file length it covers
+const HEADER: u64 = 24;
+const MIN_CAPACITY: u64 = 1 << 10;
+
+/// Nodes appended to a `nodes` file as they are written, found through an `index` file: an open
+/// addressing table from key to the offset of the key's last record, plus one.
+/// A commit syncs the batch of records before it to disk, then the index, whose header then
+/// covers the batch. After a crash the node file is cut back to the last commit on open, and
+/// the index rebuilt from it.
+/// A `lock` file is held while open, so only one store at a time writes to a directory.
+pub struct FileStore {
+    nodes: File,
+    index: File,
+    _lock: File,
+    capacity: u64,
+    count: u64,
+    len: u64,
+    /// The node file length at the last commit
+    committed: u64,
+}
+
+/// What a record of the node file holds
+enum Record {
+    /// The node at a key, or its removal
+    Node(NodeKey, Option<Node>),
+    Commit,
+}
+
+impl FileStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let open = |name| OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.as_ref().join(name));
+        let lock = open("lock")?;
+        if let Err(e) = lock.try_lock() {
+            anyhow::bail!("Store at {} is already open: {}", dir.as_ref().display(), e);
+        }
+        let (nodes, index) = (open("nodes")?, open("index")?);
+        let len = nodes.metadata()?.len();
+        let mut store = Self { nodes, index, _lock: lock, capacity: 0, count: 0, len, committed: len };
+        let mut header = [0u8; HEADER as usize];
+        let index_len = store.index.metadata()?.len();
+        if index_len >= HEADER { read_at(&store.index, &mut header, 0)?; }
+        let [capacity, count, covered] = from_fn(|i| u64::from_le_bytes(header[8 * i..8 * i + 8].try_into().unwrap()));
+        // A corrupt capacity may not fit the index length at all
+        if capacity.is_power_of_two() && covered == len && capacity.checked_mul(8).and_then(|n| n.checked_add(HEADER)) == Some(index_len) {
+            (store.capacity, store.count) = (capacity, count);
+        } else {
+            store.recover()?;
+            // At most one slot per record
+            store.rebuild(MIN_CAPACITY.max((2 * store.len / (KEY as u64 + 1)).next_power_of_two()))?;
+        }
+        Ok(store)
+    }
+
+    /// Cuts the node file back to its last commit, dropping the batch a crash interrupted
+    fn recover(&mut self) -> Result<()> {
+        let (mut offset, mut committed) = (0, 0);
+        while offset < self.len {
+            match self.read(offset) {
+                Ok((record, len)) => {
+                    offset += len;
+                    if let Record::Commit = record { committed = offset; }
+                }
+                // Past the last commit, a record may be torn or never have been written
+                Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() != ErrorKind::UnexpectedEof) => return Err(e),
+                Err(_) => break,
+            }
+        }
+        self.nodes.set_len(committed)?;
+        (self.len, self.committed) = (committed, committed);
+        Ok(())
+    }
+
+    /// Rewrites the index with `capacity` slots from every record of the node file
+    fn rebuild(&mut self, capacity: u64) -> Result<()> {
+        self.index.set_len(0)?;
+        self.index.set_len(HEADER + 8 * capacity)?;
+        (self.capacity, self.count) = (capacity, 0);
+        let mut offset = 0;
+        while offset < self.len {
+            let (record, len) = self.read(offset)?;
+            if let Record::Node(key, _) = record { self.point(&key, offset)?; }
+            offset += len;
+        }
+        self.write_header()
+    }
+
+    fn encode(record: &Record) -> Vec<u8> {
+        let (key, node) = match record {
+            Record::Node(key, node) => (*key, node.as_ref()),
+            Record::Commit => (NodeKey::ROOT, None),
+        };
+        let mut bytes = key.len.to_le_bytes().to_vec();
+        key.bits.iter().for_each(|w| bytes.extend(w.to_le_bytes()));
+        let hashes = |bytes: &mut Vec<u8>, hashes: &[Hash]| hashes.iter().flat_map(|h| h.elements).for_each(|e| bytes.extend(e.0.to_le_bytes()));
+        match (record, node) {
+            (Record::Commit, _) => bytes.push(COMMIT),
+            (_, None) => bytes.push(0),
+            (_, Some(Node::Branch(hash))) => {
+                bytes.push(1);
+                hashes(&mut bytes, &[*hash]);
+            }
+            (_, Some(Node::Leaf(leaf))) => {
+                bytes.push(2);
+                hashes(&mut bytes, &[leaf.addr, leaf.hash]);
+                hashes(&mut bytes, &leaf.checkpoints);
+                bytes.extend(leaf.value.0.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// The record at `offset`, and its length
+    fn read(&self, offset: u64) -> Result<(Record, u64)> {
+        let mut head = [0u8; KEY + 1];
+        read_at(&self.nodes, &mut head, offset)?;
+        let key = NodeKey { len: u16::from_le_bytes([head[0], head[1]]), bits: from_fn(|w| u64::from_le_bytes(head[2 + 8 * w..10 + 8 * w].try_into().unwrap())) };
+        let mut body = vec![0u8; match head[KEY] {
+            0 | COMMIT => 0,
+            1 => BRANCH,
+            2 => LEAF,
+            tag => anyhow::bail!("Unknown node tag {} at offset {}", tag, offset),
+        }];
+        read_at(&self.nodes, &mut body, offset + head.len() as u64)?;
+        // Fields are stored as is, as paths are read from their representation
+        let mut words = body.chunks(8).map(|b| GoldilocksField(u64::from_le_bytes(b.try_into().unwrap())));
+        let mut hash = || Hash { elements: from_fn(|_| words.next().unwrap()) };
+        let record = match head[KEY] {
+            COMMIT => Record::Commit,
+            0 => Record::Node(key, None),
+            1 => Record::Node(key, Some(Node::Branch(hash()))),
+            _ => {
+                let (addr, hash, checkpoints) = (hash(), hash(), from_fn(|_| hash()));
+                Record::Node(key, Some(Node::Leaf(Box::new(Leaf { addr, hash, checkpoints, value: words.next().unwrap() }))))
+            }
+        };
+        Ok((record, (head.len() + body.len()) as u64))
+    }
+
+    /// The slot holding `key`, or the empty slot it would go in, and the offset of its record
+    fn find(&self, key: &NodeKey) -> Result<(u64, Option<u64>)> {
+        // FNV-1a, which unlike the std hasher is fixed across Rust versions
+        let hash = key.bits.iter().fold(0xcbf29ce484222325u64 ^ key.len as u64, |h, &w| (h ^ w).wrapping_mul(0x100000001b3));
+        let mut slot = hash & (self.capacity - 1);
+        loop {
+            let mut entry = [0u8; 8];
+            read_at(&self.index, &mut entry, HEADER + 8 * slot)?;
+            match u64::from_le_bytes(entry) {
+                0 => return Ok((slot, None)),
+                entry if matches!(self.read(entry - 1)?.0, Record::Node(found, _) if found == *key) => return Ok((slot, Some(entry - 1))),
+                _ => slot = (slot + 1) & (self.capacity - 1),
+            }
+        }
+    }
+
+    /// Points the slot of `key` at the record at `offset`
+    fn point(&mut self, key: &NodeKey, offset: u64) -> Result<()> {
+        let (slot, previous) = self.find(key)?;
+        write_at(&self.index, &(offset + 1).to_le_bytes(), HEADER + 8 * slot)?;
+        self.count += previous.is_none() as u64;
+        Ok(())
+    }
+
+    /// Writes the header, covering the node file up to the last commit
+    fn write_header(&self) -> Result<()> {
+        let header: Vec<u8> = [self.capacity, self.count, self.committed].iter().flat_map(|v| v.to_le_bytes()).collect();
+        write_at(&self.index, &header, 0)
+    }
+
+    fn append(&mut self, record: Record) -> Result<()> {
+        let key = match record {
+            Record::Node(key, _) => Some(key),
+            Record::Commit => None,
+        };
+        if key.is_some() && 2 * (self.count + 1) > self.capacity { self.rebuild(2 * self.capacity)?; }
+        let bytes = Self::encode(&record);
+        // The record goes first, so the index never points past the node file
+        write_at(&self.nodes, &bytes, self.len)?;
+        let offset = self.len;
+        self.len += bytes.len() as u64;
+        if let Some(key) = key { self.point(&key, offset)?; }
+        Ok(())
+    }
+}
+
+/// Positioned reads and writes through the file cursor, which unlike `FileExt` work on every platform
+fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file.read_exact(buf)?)
+}
+
+fn write_at(mut file: &File, buf: &[u8], offset: u64) -> Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file.write_all(buf)?)
+}
+
+impl NodeStore for FileStore {
+    fn get(&self, key: &NodeKey) -> Result<Option<Node>> {
+        match self.find(key)?.1 {
+            Some(offset) => match self.read(offset)?.0 {
+                Record::Node(_, node) => Ok(node),
+                Record::Commit => anyhow::bail!("Index points at a commit record at offset {}", offset),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, key: NodeKey, node: Node) -> Result<()> { self.append(Record::Node(key, Some(node))) }
+
+    fn remove(&mut self, key: &NodeKey) -> Result<()> {
+        // Only tombstone what is there, so zero values cost nothing
+        if self.get(key)?.is_some() { self.append(Record::Node(*key, None))?; }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.append(Record::Commit)?;
+        // The header must not cover records that may not have reached the disk
+        self.nodes.sync_data()?;
+        self.index.sync_data()?;
+        self.committed = self.len;
+        self.write_header()
+    }
+}
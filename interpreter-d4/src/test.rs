@@ -0,0 +1,157 @@
+use crate::zk::{Circuit, Field, GoldilocksField, WitnessWrite};
+
+mod legacy;
+
+fn increment() -> Circuit<(plonky2::iop::target::Target, plonky2::iop::target::Target)> {
+    Circuit::new(|builder| {
+        let old = builder.add_virtual_target();
+        let new = builder.add_virtual_public_input();
+        let one = builder.sub(new, old);
+        builder.assert_one(one);
+        (old, new)
+    })
+}
+
+#[test]
+fn check_witness_without_proving() {
+    let c = increment();
+    let witness = |old: u64, new: u64| move |w: &mut plonky2::iop::witness::PartialWitness<GoldilocksField>, t: &(_, _)| {
+        w.set_target(t.0, GoldilocksField::from_canonical_u64(old))?;
+        w.set_target(t.1, GoldilocksField::from_canonical_u64(new))
+    };
+    assert!(c.check_witness(witness(3, 4)).is_ok());
+    assert!(c.check_witness(witness(3, 5)).is_err());
+
+    // Poseidon's degree splits the gates over several selector groups
+    let hash = Circuit::new(|builder| {
+        let input = builder.add_virtual_target();
+        let digest = builder.hash_n_to_hash_no_pad::<crate::PoseidonHash>(vec![input]);
+        builder.register_public_inputs(&digest.elements);
+        input
+    });
+    assert!(hash.check_witness(|w, &t| w.set_target(t, GoldilocksField::ONE)).is_ok());
+}
+
+#[test]
+fn file_store_survives_reopening() -> anyhow::Result<()> {
+    use crate::{Interpreter, PoseidonHash};
+    use plonky2::plonk::config::Hasher;
+    use std::io::Write;
+
+    let dir = std::env::temp_dir().join(format!("interpreter-store-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let addr = |i: u64| PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(i)]);
+    let (mut memory, mut disk) = (Interpreter::new(), Interpreter::open(&dir)?);
+    // Another store cannot open the directory while it is held
+    assert!(Interpreter::open(&dir).is_err());
+    // Enough nodes to grow the index past its initial 1024 slots, which hold half as many keys
+    let count = 300;
+    for i in 0..count {
+        memory.insert(addr(i), GoldilocksField::from_canonical_u64(i + 1))?;
+        disk.insert(addr(i), GoldilocksField::from_canonical_u64(i + 1))?;
+    }
+    memory.insert(addr(3), GoldilocksField::ZERO)?;
+    disk.insert(addr(3), GoldilocksField::ZERO)?;
+    assert!(std::fs::metadata(dir.join("index"))?.len() > 24 + 8 * 1024, "the index did not grow");
+    let root = memory.root()?;
+    assert_eq!(disk.root()?, root);
+    drop(disk);
+
+    let reopened = Interpreter::open(&dir)?;
+    assert_eq!(reopened.root()?, root);
+    for i in (0..count).chain([count + 99]) {
+        assert_eq!(reopened.prove(addr(i))?, memory.prove(addr(i))?);
+    }
+    assert_eq!(reopened.prove(addr(5))?.0, GoldilocksField::from_canonical_u64(6));
+    drop(reopened);
+
+    // A record torn by a crash is dropped, and a lost index rebuilt from the node file
+    std::fs::OpenOptions::new().append(true).open(dir.join("nodes"))?.write_all(&[1; 10])?;
+    assert_eq!(Interpreter::open(&dir)?.root()?, root);
+    // So is a whole batch without its commit, here removing the root
+    std::fs::OpenOptions::new().append(true).open(dir.join("nodes"))?.write_all(&[0; 35])?;
+    assert_eq!(Interpreter::open(&dir)?.root()?, root);
+    std::fs::remove_file(dir.join("index"))?;
+    assert_eq!(Interpreter::open(&dir)?.prove(addr(7))?, memory.prove(addr(7))?);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn collapsed_tree_prunes_zeros() -> anyhow::Result<()> {
+    use crate::{Interpreter, PoseidonHash};
+    use plonky2::plonk::config::Hasher;
+
+    let addr = |i: u64| PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(i)]);
+    // Folding a proof from its leaf must give the root the tree reports
+    let check = |tree: &Interpreter, i: u64| -> anyhow::Result<GoldilocksField> {
+        let (value, siblings) = tree.prove(addr(i))?;
+        let path = crate::SparseMerkleTree::<crate::MemoryStore>::get_path(&addr(i));
+        let root = (0..256).fold(PoseidonHash::hash_or_noop(&[value]), |hash, i| match path[255 - i] {
+            true => PoseidonHash::two_to_one(siblings[i], hash),
+            false => PoseidonHash::two_to_one(hash, siblings[i]),
+        });
+        assert_eq!(root, tree.root()?);
+        Ok(value)
+    };
+    let empty = Interpreter::new().root()?;
+    let mut tree = Interpreter::new();
+    for i in 0..6 {
+        tree.insert(addr(i), GoldilocksField::from_canonical_u64(i + 1))?;
+    }
+    for i in 0..8 {
+        assert_eq!(check(&tree, i)?, GoldilocksField::from_canonical_u64(if i < 6 { i + 1 } else { 0 }));
+    }
+
+    // Zeroing values collapses the tree back to the one holding the rest
+    let mut rest = Interpreter::new();
+    for i in [1, 4] {
+        rest.insert(addr(i), GoldilocksField::from_canonical_u64(i + 1))?;
+    }
+    for i in [0, 2, 3, 5, 7] {
+        tree.insert(addr(i), GoldilocksField::ZERO)?;
+    }
+    assert_eq!(tree.root()?, rest.root()?);
+    assert_eq!(check(&tree, 4)?, GoldilocksField::from_canonical_u64(5));
+    assert_eq!(check(&tree, 0)?, GoldilocksField::ZERO);
+    for i in [1, 4] {
+        tree.insert(addr(i), GoldilocksField::ZERO)?;
+    }
+    assert_eq!(tree.root()?, empty);
+    Ok(())
+}
+
+#[test]
+fn collapsed_tree_matches_the_full_layout() -> anyhow::Result<()> {
+    use crate::{Hash, Interpreter, NodeStore, PoseidonHash};
+    use legacy::Legacy;
+    use plonky2::plonk::config::Hasher;
+    use std::collections::HashMap;
+
+    /// Writes to `tree`, then checks its root and the proofs of the written and an unused address
+    fn agrees<S: NodeStore>(mut tree: Interpreter<S>, writes: &[(Hash, GoldilocksField)], legacy: &Legacy) -> anyhow::Result<()> {
+        writes.iter().try_for_each(|&(addr, value)| tree.insert(addr, value))?;
+        assert_eq!(tree.root()?, legacy.root());
+        let latest: HashMap<Hash, GoldilocksField> = writes.iter().cloned().collect();
+        let unused = PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(99)]);
+        for addr in latest.keys().chain([&unused]) {
+            let value = latest.get(addr).copied().unwrap_or(GoldilocksField::ZERO);
+            assert_eq!(tree.prove(*addr)?, (value, legacy.get_merkle_proof(&Legacy::get_path(addr))));
+        }
+        Ok(())
+    }
+
+    // The last two writes update earlier addresses, the last one to zero
+    let writes: Vec<(Hash, GoldilocksField)> = (0..8)
+        .map(|i| (PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(i % 6)]), GoldilocksField::from_canonical_u64(i % 7)))
+        .collect();
+    let mut legacy = Legacy::default();
+    writes.iter().for_each(|&(addr, value)| legacy.insert(addr, value));
+
+    agrees(Interpreter::new(), &writes, &legacy)?;
+    let dir = std::env::temp_dir().join(format!("interpreter-legacy-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    agrees(Interpreter::open(&dir)?, &writes, &legacy)?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! The layout the collapsed tree replaced, which kept all 257 nodes of every path. The `smt`
+//! bench times the tree against it, and the tests check that both agree.
+
+use std::array::from_fn;
+use std::collections::HashMap;
+use std::iter::once;
+use std::sync::LazyLock;
+
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+type Hash = HashOut<GoldilocksField>;
+
+#[derive(Default)]
+pub struct Legacy {
+    nodes: HashMap<Vec<bool>, Hash>,
+}
+
+impl Legacy {
+    pub fn get_path(hash: &Hash) -> [bool; 256] {
+        from_fn(|i| hash.elements[3 - i / 64].0 >> (63 - i % 64) & 1 > 0)
+    }
+
+    pub fn root(&self) -> Hash {
+        self.get_digest(&[])
+    }
+
+    pub fn insert(&mut self, key: Hash, value: GoldilocksField) {
+        let mut path: Vec<bool> = Self::get_path(&key).to_vec();
+        self.nodes.insert(path.clone(), PoseidonHash::hash_or_noop(&[value]));
+        for _ in 0..256 {
+            path.pop();
+            let [left, right] = [false, true].map(|v| self.get_digest(&path.iter().cloned().chain(once(v)).collect::<Vec<_>>()));
+            self.nodes.insert(path.clone(), PoseidonHash::two_to_one(left, right));
+        }
+    }
+
+    fn get_digest(&self, index: &[bool]) -> Hash {
+        static DEFAULT_HASHS: LazyLock<[Hash; 257]> = LazyLock::new(|| {
+            let mut default_hashes = [PoseidonHash::hash_or_noop(&[GoldilocksField::ZERO; 1]); 257];
+            (0..256).rev().for_each(|i| default_hashes[i] = PoseidonHash::two_to_one(default_hashes[i + 1], default_hashes[i + 1]));
+            default_hashes
+        });
+        self.nodes.get(index).cloned().unwrap_or(DEFAULT_HASHS[index.len()])
+    }
+
+    pub fn get_merkle_proof(&self, key: &[bool]) -> [Hash; 256] {
+        from_fn(|i| self.get_digest(&key[0..255 - i].iter().cloned().chain(once(!key[255 - i])).collect::<Vec<_>>()))
+    }
+}
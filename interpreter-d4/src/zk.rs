@@ -1,17 +1,23 @@
 use anyhow::Result;
+use std::ops::Range;
 pub use plonky2::field::goldilocks_field::GoldilocksField;
 pub use plonky2::field::types::Field;
 pub use plonky2::field::types::Field64;
 pub use plonky2::hash::hash_types::HashOut;
 pub use plonky2::hash::merkle_proofs::MerkleProofTarget;
 pub use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::generator::generate_partial_witness;
+use plonky2::iop::target::Target;
 use plonky2::iop::witness::PartialWitness;
+use plonky2::iop::witness::Witness;
 pub use plonky2::iop::witness::WitnessWrite;
 use plonky2::plonk::circuit_builder::CircuitBuilder;
 use plonky2::plonk::circuit_data::CircuitConfig;
 use plonky2::plonk::circuit_data::CircuitData;
+use plonky2::plonk::circuit_data::CommonCircuitData;
 pub use plonky2::plonk::config::Hasher;
 use plonky2::plonk::proof::ProofWithPublicInputs;
+use plonky2::plonk::vars::EvaluationVarsBaseBatch;
 type Config = plonky2::plonk::config::PoseidonGoldilocksConfig;
 pub type Hash = HashOut<GoldilocksField>;
 pub type Proof = plonky2::plonk::proof::Proof<GoldilocksField, Config, 2>;
@@ -26,7 +32,7 @@ impl VerifyingKey {
         let [[a, b, c, d], [x, y, z, w]] = [self.address().elements, root.elements];
         self.0.verify(ProofWithPublicInputs { proof, public_inputs: vec![a, b, c, d, x, y, z, w, new] })
     }
-    pub fn address(&self) -> Hash { PoseidonHash::hash_no_pad(&self.0.verifier_only.constants_sigmas_cap.0.iter().map(|v| v.elements).flatten().chain(self.0.verifier_only.circuit_digest.elements).collect::<Vec<_>>()) }
+    pub fn address(&self) -> Hash { PoseidonHash::hash_no_pad(&self.0.verifier_only.constants_sigmas_cap.0.iter().flat_map(|v| v.elements).chain(self.0.verifier_only.circuit_digest.elements).collect::<Vec<_>>()) }
 }
 impl<T> Circuit<T> {
     pub fn new<Func>(f: Func) -> Self
@@ -42,5 +48,47 @@ impl<T> Circuit<T> {
         let pi: ProofWithPublicInputs<GoldilocksField, plonky2::plonk::config::PoseidonGoldilocksConfig, 2> = self.c.prove(w)?;
         Ok((pi.proof, pi.public_inputs))
     }
+    /// Runs witness generation and evaluates every gate constraint natively, without proving.
+    /// Lookup arguments are not checked, as they depend on the verifier's challenges.
+    pub fn check_witness<Func>(&self, f: Func) -> Result<()>
+    where Func: FnOnce(&mut PartialWitness<GoldilocksField>, &T) -> Result<()> {
+        let (prover, common) = (&self.c.prover_only, &self.c.common);
+        let groups = selector_groups(common);
+        anyhow::ensure!(groups.len() == common.selectors_info.num_selectors(), "circuit has {} selectors, expected {}", common.selectors_info.num_selectors(), groups.len());
+        let mut w = PartialWitness::<GoldilocksField>::new();
+        f(&mut w, &self.t)?;
+        let w = generate_partial_witness(w, prover, common)?;
+        let pi = prover.public_inputs.iter().map(|&t| w.try_get_target(t).unwrap_or_default()).collect::<Vec<_>>();
+        let pi_hash = PoseidonHash::hash_no_pad(&pi);
+        let constants = prover.constants_sigmas_commitment.polynomials[..common.num_constants].iter().map(|p| p.clone().fft().values).collect::<Vec<_>>();
+        for row in 0..common.degree() {
+            let local_constants = constants.iter().map(|v| v[row]).collect::<Vec<_>>();
+            let local_wires = (0..common.config.num_wires).map(|c| w.try_get_target(Target::wire(row, c)).unwrap_or_default()).collect::<Vec<_>>();
+            for (i, gate) in common.gates.iter().enumerate() {
+                let s = groups.iter().position(|g| g.contains(&i)).unwrap_or_default();
+                let vars = EvaluationVarsBaseBatch::new(1, &local_constants, &local_wires, &pi_hash);
+                let values = gate.0.eval_filtered_base_batch(vars, i, s, groups[s].clone(), groups.len(), common.num_lookup_selectors);
+                if let Some((j, v)) = values.iter().enumerate().find(|(_, v)| !v.is_zero()) {
+                    anyhow::bail!("constraint {j} of {} on row {row} evaluates to {v}", gate.0.id());
+                }
+            }
+        }
+        Ok(())
+    }
     pub fn vk(&self) -> VerifyingKey { VerifyingKey(self.c.verifier_data()) }
 }
+/// Selector groups as plonky2 builds them: gates, sorted by degree, share a selector while
+/// the filtered constraints stay within `quotient_degree_factor + 1`
+fn selector_groups(common: &CommonCircuitData<GoldilocksField, 2>) -> Vec<Range<usize>> {
+    let degrees = common.gates.iter().map(|g| g.0.degree()).collect::<Vec<_>>();
+    let max_degree = common.quotient_degree_factor + 1;
+    let single = degrees.last().copied().unwrap_or_default() + degrees.len() <= max_degree + 1;
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < degrees.len() {
+        let size = if single { degrees.len() } else { (0..degrees.len() - start).take_while(|&size| size + degrees[start + size] < max_degree).count().max(1) };
+        groups.push(start..start + size);
+        start += size;
+    }
+    groups
+}
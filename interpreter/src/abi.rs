@@ -0,0 +1,137 @@
+use std::fmt;
+use std::str::FromStr;
+
+use plonky2::field::types::{Field, PrimeField64};
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::RejectReason;
+use crate::GoldilocksField;
+
+type F = GoldilocksField;
+
+/// What a public input of a transaction proof means to the ledger
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    /// Must equal the circuit's state when the transaction applies
+    Precondition,
+    /// Becomes the circuit's state
+    NewState,
+    /// Left to the circuit, the ledger ignores it
+    Parameter,
+}
+
+impl Role {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Precondition => "precondition",
+            Role::NewState => "new_state",
+            Role::Parameter => "parameter",
+        }
+    }
+}
+
+/// The roles of a circuit's public inputs, by index; inputs past the listed ones are parameters.
+///
+/// A transaction applies when every precondition equals the stored state, and moves the
+/// state to its new state input, if the circuit has one. Without preconditions, a proof
+/// applies to any state, and may be replayed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Abi {
+    roles: Vec<Role>,
+}
+
+/// The layout the ledger has always assumed: the current state, then the next one
+impl Default for Abi {
+    fn default() -> Self {
+        Self { roles: vec![Role::Precondition, Role::NewState] }
+    }
+}
+
+impl Abi {
+    /// Checks that at most one public input carries the new state
+    pub fn new(roles: Vec<Role>) -> Result<Self, anyhow::Error> {
+        if roles.iter().filter(|role| **role == Role::NewState).count() > 1 {
+            anyhow::bail!("An ABI has at most one new state input");
+        }
+        Ok(Self { roles })
+    }
+
+    pub fn roles(&self) -> &[Role] {
+        &self.roles
+    }
+
+    /// The state a transaction with these public inputs moves `old` to, or why it does not apply
+    pub fn transition(&self, public_inputs: &[F], old: u64) -> Result<u64, (RejectReason, anyhow::Error)> {
+        if public_inputs.len() < self.roles.len() {
+            let e = anyhow::anyhow!("Expected at least {} public inputs, got: {}", self.roles.len(), public_inputs.len());
+            return Err((RejectReason::PublicInputs, e));
+        }
+        let mut new = old;
+        for (role, input) in self.roles.iter().zip(public_inputs) {
+            match role {
+                Role::Precondition if input.to_canonical_u64() != old => {
+                    let e = anyhow::anyhow!("Stale state, expected: {}, got: {}", old, input);
+                    return Err((RejectReason::StaleState, e));
+                }
+                Role::NewState => new = input.to_canonical_u64(),
+                _ => {}
+            }
+        }
+        Ok(new)
+    }
+
+    /// Encodes the ABI for commitments, its length first
+    pub fn to_elements(&self) -> Vec<F> {
+        std::iter::once(F::from_canonical_usize(self.roles.len()))
+            .chain(self.roles.iter().map(|role| F::from_canonical_usize(*role as usize)))
+            .collect()
+    }
+}
+
+/// The role labels separated by commas, as in `precondition,new_state`
+impl fmt::Display for Abi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels: Vec<&str> = self.roles.iter().map(Role::label).collect();
+        write!(f, "{}", labels.join(","))
+    }
+}
+
+impl FromStr for Abi {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let roles = s
+            .split(',')
+            .filter(|label| !label.is_empty())
+            .map(|label| match label {
+                "precondition" => Ok(Role::Precondition),
+                "new_state" => Ok(Role::NewState),
+                "parameter" => Ok(Role::Parameter),
+                _ => Err(anyhow::anyhow!("Unknown public input role {}", label)),
+            })
+            .collect::<Result<_, _>>()?;
+        Self::new(roles)
+    }
+}
+
+#[test]
+fn abi_transitions_and_round_trips() -> Result<(), anyhow::Error> {
+    let inputs = [F::from_canonical_u64(9), F::from_canonical_u64(4), F::from_canonical_u64(3)];
+    let apply = |abi: &Abi, inputs: &[F], old| abi.transition(inputs, old).map_err(|(_, e)| e);
+
+    assert_eq!(apply(&Abi::default(), &inputs[1..], 4)?, 3);
+    let abi: Abi = "parameter,new_state,precondition".parse()?;
+    assert_eq!(apply(&abi, &inputs, 3)?, 4);
+    assert_eq!(abi.transition(&inputs, 4).unwrap_err().0, RejectReason::StaleState);
+    assert_eq!(abi.transition(&inputs[..2], 3).unwrap_err().0, RejectReason::PublicInputs);
+    assert_eq!(abi.to_string().parse::<Abi>()?, abi);
+
+    // Without a new state input the state is left as it is
+    let check: Abi = "parameter,precondition".parse()?;
+    assert_eq!(apply(&check, &inputs, 4)?, 4);
+    assert_eq!(apply(&"".parse()?, &[], 7)?, 7);
+
+    assert!("new_state,new_state".parse::<Abi>().is_err());
+    assert!("precondition,state".parse::<Abi>().is_err());
+    Ok(())
+}
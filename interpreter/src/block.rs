@@ -0,0 +1,262 @@
+use plonky2::field::types::Field;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Ledger, Receipt};
+use crate::{digest_to_hex, hash_bytes, GoldilocksField, HashOut};
+
+type F = GoldilocksField;
+
+/// Everything a block commits to, apart from the transactions themselves
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    /// Hash of the previous block's header, zero for the genesis block
+    pub parent: HashOut<F>,
+    /// Merkle root of the hashes of the block's transactions
+    pub tx_root: HashOut<F>,
+    /// Ledger commitment after applying the block's transactions
+    pub state_commitment: HashOut<F>,
+}
+
+impl BlockHeader {
+    pub fn hash(&self) -> HashOut<F> {
+        let elements: Vec<F> = [F::from_canonical_u64(self.height)]
+            .into_iter()
+            .chain(self.parent.elements)
+            .chain(self.tx_root.elements)
+            .chain(self.state_commitment.elements)
+            .collect();
+        PoseidonHash::hash_no_pad(&elements)
+    }
+}
+
+/// A header and the serialized transactions it commits to, in the order they were applied
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Vec<u8>>,
+}
+
+impl Block {
+    /// The block every chain starts from, committing to the initial ledger
+    pub fn genesis(ledger: &Ledger) -> Self {
+        Self {
+            header: BlockHeader {
+                height: 0,
+                parent: HashOut::ZERO,
+                tx_root: tx_root(&[]),
+                state_commitment: ledger.commitment(),
+            },
+            transactions: Vec::new(),
+        }
+    }
+
+    pub fn hash(&self) -> HashOut<F> {
+        self.header.hash()
+    }
+}
+
+/// Poseidon Merkle root over the transaction hashes, padded with zero digests to a power
+/// of two. An empty block has the zero root.
+pub fn tx_root(transactions: &[Vec<u8>]) -> HashOut<F> {
+    if transactions.is_empty() {
+        return HashOut::ZERO;
+    }
+    let mut layer: Vec<HashOut<F>> = transactions.iter().map(|tx| hash_bytes(tx)).collect();
+    layer.resize(layer.len().next_power_of_two(), HashOut::ZERO);
+    while layer.len() > 1 {
+        layer = layer.chunks(2).map(|pair| PoseidonHash::two_to_one(pair[0], pair[1])).collect();
+    }
+    layer[0]
+}
+
+/// Assembles the next block by applying transactions to a copy of the ledger.
+/// Only transactions that apply are included.
+pub struct BlockBuilder {
+    parent: BlockHeader,
+    ledger: Ledger,
+    transactions: Vec<Vec<u8>>,
+}
+
+impl BlockBuilder {
+    /// Starts a block on top of `parent`, whose post-state is `ledger`
+    pub fn new(parent: &BlockHeader, ledger: Ledger) -> Self {
+        Self { parent: parent.clone(), ledger, transactions: Vec::new() }
+    }
+
+    /// Applies a transaction, including it in the block if it was accepted
+    pub fn push(&mut self, tx: &[u8]) -> Receipt {
+        let receipt = self.ledger.apply_bytes(tx);
+        if receipt.is_applied() {
+            self.transactions.push(tx.to_vec());
+            self.ledger.metrics().set_pending(self.transactions.len());
+        }
+        receipt
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Seals the block, returning it with the ledger it leads to
+    pub fn build(self) -> (Block, Ledger) {
+        self.ledger.metrics().set_pending(0);
+        let header = BlockHeader {
+            height: self.parent.height + 1,
+            parent: self.parent.hash(),
+            tx_root: tx_root(&self.transactions),
+            state_commitment: self.ledger.commitment(),
+        };
+        (Block { header, transactions: self.transactions }, self.ledger)
+    }
+}
+
+/// Checks that `block` extends `parent`, re-applying its transactions to `ledger`, the
+/// state after `parent`. Returns the ledger after the block.
+/// The transactions were counted in the ledger's metrics when they were first applied,
+/// so re-applying them here is not.
+pub fn validate_block(parent: &BlockHeader, ledger: &Ledger, block: &Block) -> Result<Ledger, anyhow::Error> {
+    let header = &block.header;
+    if header.height != parent.height + 1 {
+        anyhow::bail!("Block height {} does not follow parent height {}", header.height, parent.height);
+    }
+    if header.parent != parent.hash() {
+        anyhow::bail!("Block {} parent {} does not match {}", header.height, digest_to_hex(&header.parent), digest_to_hex(&parent.hash()));
+    }
+    let root = tx_root(&block.transactions);
+    if header.tx_root != root {
+        anyhow::bail!("Block {} transaction root {} does not match computed {}", header.height, digest_to_hex(&header.tx_root), digest_to_hex(&root));
+    }
+
+    let metrics = ledger.metrics().clone();
+    let mut ledger = ledger.clone_detached();
+    for (i, tx) in block.transactions.iter().enumerate() {
+        let receipt = ledger.apply_bytes(tx);
+        if !receipt.is_applied() {
+            anyhow::bail!("Block {} transaction {} does not apply: {:?}", header.height, i, receipt.status);
+        }
+    }
+    let commitment = ledger.commitment();
+    if header.state_commitment != commitment {
+        anyhow::bail!(
+            "Block {} state commitment {} does not match computed {}",
+            header.height,
+            digest_to_hex(&header.state_commitment),
+            digest_to_hex(&commitment)
+        );
+    }
+    Ok(ledger.with_metrics(metrics))
+}
+
+/// A hash-linked sequence of validated blocks, from a genesis ledger to the current one
+pub struct Chain {
+    genesis: Ledger,
+    blocks: Vec<Block>,
+    ledger: Ledger,
+}
+
+impl Chain {
+    pub fn new(genesis: Ledger) -> Self {
+        Self { blocks: vec![Block::genesis(&genesis)], ledger: genesis.clone(), genesis }
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        &self.blocks[self.blocks.len() - 1].header
+    }
+
+    pub fn height(&self) -> u64 {
+        self.tip().height
+    }
+
+    pub fn block(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(usize::try_from(height).ok()?)
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// The ledger after the tip
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Starts the next block on top of the tip
+    pub fn builder(&self) -> BlockBuilder {
+        BlockBuilder::new(self.tip(), self.ledger.clone())
+    }
+
+    /// Validates a block against the tip and appends it
+    pub fn append(&mut self, block: Block) -> Result<(), anyhow::Error> {
+        self.ledger = validate_block(self.tip(), &self.ledger, &block)?;
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Replays every block from the genesis ledger, checking each link and commitment
+    pub fn verify(&self) -> Result<(), anyhow::Error> {
+        if self.blocks[0] != Block::genesis(&self.genesis) {
+            anyhow::bail!("Genesis block does not match the genesis ledger");
+        }
+        let mut ledger = self.genesis.clone_detached();
+        for pair in self.blocks.windows(2) {
+            ledger = validate_block(&pair[0].header, &ledger, &pair[1])?;
+        }
+        if ledger.commitment() != self.ledger.commitment() {
+            anyhow::bail!("Chain ledger does not match the replayed blocks");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "prover")]
+#[test]
+fn chain_builds_validates_and_detects_tampering() -> Result<(), anyhow::Error> {
+    use crate::counter::counter_zkp_circuit;
+
+    let circuit = counter_zkp_circuit();
+    let tx = |old: u64| -> Result<Vec<u8>, anyhow::Error> {
+        let proof = circuit.prove(vec![old, old + 1])?;
+        Ok(circuit.transaction(&proof).serialize())
+    };
+    let mut genesis = Ledger::new();
+    let vk_id = genesis.register(&circuit.registration(), 0)?;
+    let mut chain = Chain::new(genesis);
+
+    let mut builder = chain.builder();
+    assert!(builder.push(&tx(0)?).is_applied());
+    assert!(!builder.push(&tx(0)?).is_applied(), "stale transaction was accepted");
+    assert!(builder.push(&tx(1)?).is_applied());
+    let (block, ledger) = builder.build();
+    assert_eq!(block.transactions.len(), 2);
+    assert_eq!(ledger.state(&vk_id), Some(2));
+    chain.append(block.clone())?;
+    assert_eq!(chain.ledger().state(&vk_id), Some(2));
+
+    // A block replayed on the wrong parent, or with forged contents, is refused
+    assert!(chain.append(block.clone()).is_err());
+    let mut builder = chain.builder();
+    builder.push(&tx(2)?);
+    let (next, _) = builder.build();
+    for forged in [
+        Block { header: BlockHeader { state_commitment: HashOut::ZERO, ..next.header.clone() }, ..next.clone() },
+        Block { header: BlockHeader { parent: block.header.parent, ..next.header.clone() }, ..next.clone() },
+        Block { transactions: Vec::new(), ..next.clone() },
+    ] {
+        assert!(chain.append(forged).is_err());
+    }
+    chain.append(next)?;
+    assert_eq!(chain.height(), 2);
+    chain.verify()?;
+
+    // Rewriting history breaks the chain even when the tip is untouched
+    chain.blocks[1].transactions.pop();
+    assert!(chain.verify().is_err());
+    Ok(())
+}
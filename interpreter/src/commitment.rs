@@ -0,0 +1,60 @@
+#[cfg(feature = "prover")]
+use std::ops::Range;
+
+use plonky2::field::types::{Field, PrimeField64, Sample};
+#[cfg(feature = "prover")]
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+use crate::{digest_to_hex, GoldilocksField, HashOut};
+#[cfg(feature = "prover")]
+use crate::{CircuitBuilder, Target};
+
+type F = GoldilocksField;
+#[cfg(feature = "prover")]
+const D: usize = 2;
+
+/// Field elements of blinding hashed into every commitment
+pub const BLINDING_LEN: usize = 4;
+
+/// Random elements hiding the committed values, as canonical `u64`s
+pub type Blinding = [u64; BLINDING_LEN];
+
+/// Draws a fresh blinding; reusing one across commitments links them
+pub fn random_blinding() -> Blinding {
+    F::rand_array().map(|e| e.to_canonical_u64())
+}
+
+/// Computes `Poseidon(values || blinding)`, the commitment a circuit exposes for committed inputs
+pub fn commit(values: &[u64], blinding: &Blinding) -> HashOut<F> {
+    let elements: Vec<F> = values.iter().chain(blinding).map(|&v| F::from_noncanonical_u64(v)).collect();
+    PoseidonHash::hash_no_pad(&elements)
+}
+
+/// Checks that `values` and `blinding` open `commitment`
+pub fn check_commitment(commitment: &HashOut<F>, values: &[u64], blinding: &Blinding) -> Result<(), anyhow::Error> {
+    let computed = commit(values, blinding);
+    if computed != *commitment {
+        anyhow::bail!("Values open to commitment {}, expected: {}", digest_to_hex(&computed), digest_to_hex(commitment));
+    }
+    Ok(())
+}
+
+/// Hashes the inputs at `committed` with a blinding into a commitment target.
+///
+/// The blinding is `BLINDING_LEN` new private inputs appended to `targets`, so the
+/// circuit's inputs are followed by one blinding per commitment, in the order the
+/// commitments are made.
+///
+/// The circuit must use a zero-knowledge config such as `standard_recursion_zk_config`:
+/// without one, the proof's openings leak information about the witness, committed inputs included.
+#[cfg(feature = "prover")]
+pub fn commit_targets(builder: &mut CircuitBuilder<F, D>, targets: &mut Vec<Target>, committed: Range<usize>) -> HashOutTarget {
+    assert!(builder.config.zero_knowledge, "Committed inputs need a zero-knowledge circuit config");
+    assert!(committed.end <= targets.len(), "Committed inputs {:?} out of range, circuit has {} inputs", committed, targets.len());
+    let blinding = builder.add_virtual_targets(BLINDING_LEN);
+    let preimage: Vec<Target> = targets[committed].iter().chain(&blinding).copied().collect();
+    targets.extend(blinding);
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>(preimage)
+}
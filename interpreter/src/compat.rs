@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "prover")]
+use crate::counter::{counter_zkp_circuit, CounterCircuit};
+#[cfg(feature = "prover")]
+use crate::history::HistoryCircuit;
+#[cfg(feature = "prover")]
+use crate::templates::{HashPreimage, Membership, Range};
+use crate::abi::Abi;
+use crate::db::Ledger;
+use crate::txn::Registration;
+use crate::{circuit_id, circuit_id_from_vk_hash, digest_from_hex, digest_to_hex, hash_bytes, GoldilocksField, HashOut};
+
+type F = GoldilocksField;
+
+/// The golden digests checked in with the library, keyed by circuit name
+pub const GOLDEN_DIGESTS: &str = include_str!("../golden/vk_digests.json");
+
+/// Environment variable that makes the stability test rewrite the golden file
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN_DIGESTS";
+
+/// The registration of every circuit the library defines, by the name its golden digests are recorded
+/// under. Templates are recorded at one size each, and the history step over the `counter_zkp` circuit.
+#[cfg(feature = "prover")]
+pub fn library_circuits() -> Result<Vec<(&'static str, Registration)>, anyhow::Error> {
+    let counter_zkp = counter_zkp_circuit();
+    let history = HistoryCircuit::new(&counter_zkp.circuit_data.common)?;
+    let history_step = Registration {
+        vk: history.circuit_data.verifier_only.to_bytes().map_err(|e| anyhow::anyhow!("Failed to serialize vk: {:?}", e))?,
+        common: history
+            .circuit_data
+            .common
+            .to_bytes(&plonky2::util::serialization::DefaultGateSerializer)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize common data: {:?}", e))?,
+    };
+    Ok(vec![
+        ("counter", CounterCircuit::new().registration()),
+        ("counter_zkp", counter_zkp.registration()),
+        ("hash_preimage_4", HashPreimage::new(4).circuit().registration()),
+        ("range_32", Range::new(32).circuit().registration()),
+        ("membership_16", Membership::new(16).circuit().registration()),
+        ("history_step", history_step),
+    ])
+}
+
+/// The recorded vk id and common data digest of one circuit, with the vk ids it had before.
+/// A vk id is the circuit's id under the default ABI; the circuit under any other ABI has
+/// the id `circuit_id_from_vk_hash` derives from it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoldenEntry {
+    pub vk_id: String,
+    /// Ledgers pin the common data a circuit is registered with, so a change re-pins it
+    pub common_id: String,
+    /// Retired vk ids, oldest first; state stored under them is migrated to `vk_id`
+    #[serde(default)]
+    pub previous: Vec<String>,
+}
+
+/// How a freshly built circuit compares to its golden digest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Compatibility {
+    Unchanged,
+    /// No digest is recorded under the circuit's name
+    Unrecorded { current: HashOut<F> },
+    /// The circuit builds to a different vk, so its state would be forked
+    Changed { golden: HashOut<F>, current: HashOut<F> },
+    /// The circuit builds to the same vk with other common data, which ledgers would reject
+    CommonChanged { golden: HashOut<F>, current: HashOut<F> },
+}
+
+/// State moved from a retired vk id to the current one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Migration {
+    pub circuit: String,
+    pub from: HashOut<F>,
+    pub to: HashOut<F>,
+    pub value: u64,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Migrated {} state {} from {} to {}", self.circuit, self.value, digest_to_hex(&self.from), digest_to_hex(&self.to))
+    }
+}
+
+/// Golden vk ids of registered circuits.
+///
+/// The ledger keys state by vk id, so a circuit that silently builds to a different vk
+/// loses its state. Comparing every build against the recorded ids catches that, and
+/// recording a new id keeps the old one so stored state can be migrated.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GoldenDigests {
+    circuits: BTreeMap<String, GoldenEntry>,
+}
+
+impl GoldenDigests {
+    pub fn from_json(json: &str) -> Result<Self, anyhow::Error> {
+        let golden: Self = serde_json::from_str(json)?;
+        for (name, entry) in &golden.circuits {
+            for id in entry.previous.iter().chain([&entry.vk_id, &entry.common_id]) {
+                digest_from_hex(id).map_err(|e| anyhow::anyhow!("Invalid golden digest for {}: {}", name, e))?;
+            }
+        }
+        Ok(golden)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).map(|json| json + "\n").expect("Failed to serialize golden digests")
+    }
+
+    /// Reads golden digests from a file; a missing file holds no digests
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        Ok(std::fs::write(path, self.to_json())?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&GoldenEntry> {
+        self.circuits.get(name)
+    }
+
+    /// Compares a circuit's vk and common data against the digests recorded under `name`
+    pub fn check(&self, name: &str, registration: &Registration) -> Compatibility {
+        let current = circuit_id(&registration.vk, &Abi::default());
+        let recorded = self.circuits.get(name).and_then(|entry| Some((digest_from_hex(&entry.vk_id).ok()?, digest_from_hex(&entry.common_id).ok()?)));
+        match recorded {
+            None => Compatibility::Unrecorded { current },
+            Some((golden, _)) if golden != current => Compatibility::Changed { golden, current },
+            Some((_, golden)) if golden != hash_bytes(&registration.common) => {
+                Compatibility::CommonChanged { golden, current: hash_bytes(&registration.common) }
+            }
+            Some(_) => Compatibility::Unchanged,
+        }
+    }
+
+    /// Checks every library circuit, failing with the migration steps if any changed
+    pub fn check_all(&self, circuits: &[(&str, Registration)]) -> Result<(), anyhow::Error> {
+        let mismatches: Vec<String> = circuits
+            .iter()
+            .filter_map(|(name, registration)| match self.check(name, registration) {
+                Compatibility::Unchanged => None,
+                Compatibility::Unrecorded { current } => Some(format!("{} has no golden digest, builds to {}", name, digest_to_hex(&current))),
+                Compatibility::Changed { golden, current } => {
+                    Some(format!("{} changed from {} to {}", name, digest_to_hex(&golden), digest_to_hex(&current)))
+                }
+                Compatibility::CommonChanged { golden, current } => {
+                    Some(format!("{} common data changed from {} to {}", name, digest_to_hex(&golden), digest_to_hex(&current)))
+                }
+            })
+            .collect();
+        if !mismatches.is_empty() {
+            anyhow::bail!(
+                "Circuit vks differ from their golden digests:\n{}\nIf the change is intended, rerun with {}=1 to record the new digests; \
+                 nodes then migrate state stored under the old vk ids when they start",
+                mismatches.join("\n"),
+                UPDATE_ENV
+            );
+        }
+        Ok(())
+    }
+
+    /// Records the current vk and common data of a circuit, retiring its previous vk digest
+    pub fn record(&mut self, name: &str, registration: &Registration) {
+        let current = digest_to_hex(&circuit_id(&registration.vk, &Abi::default()));
+        let entry = self.circuits.entry(name.to_string()).or_default();
+        entry.common_id = digest_to_hex(&hash_bytes(&registration.common));
+        if entry.vk_id == current {
+            return;
+        }
+        let retired = std::mem::replace(&mut entry.vk_id, current);
+        if !retired.is_empty() {
+            entry.previous.push(retired);
+        }
+    }
+
+    /// Moves state stored under a retired vk of any recorded circuit to its current vk, under
+    /// each ABI the circuit is registered with, and re-pins circuits registered with other
+    /// common data than recorded
+    pub fn migrate_ledger(&self, ledger: &mut Ledger) -> Result<Vec<Migration>, anyhow::Error> {
+        let mut migrations = Vec::new();
+        for (name, entry) in &self.circuits {
+            let current = digest_from_hex(&entry.vk_id)?;
+            let common = digest_from_hex(&entry.common_id)?;
+            for id in entry.previous.iter().rev().chain([&entry.vk_id]) {
+                let vk_id = digest_from_hex(id)?;
+                // State is keyed by circuit id, which covers the ABI, so each circuit of the vk moves under its own ABI
+                let circuits: Vec<(HashOut<F>, HashOut<F>)> = ledger
+                    .states()
+                    .into_iter()
+                    .filter_map(|(from, _)| {
+                        let abi = ledger.abi(&from).cloned().unwrap_or_default();
+                        (circuit_id_from_vk_hash(vk_id, &abi) == from).then(|| (from, circuit_id_from_vk_hash(current, &abi)))
+                    })
+                    .collect();
+                for (from, to) in circuits {
+                    if from == to && ledger.common(&from) == Some(common) {
+                        continue;
+                    }
+                    let value = ledger.migrate(&from, &to, common)?;
+                    migrations.push(Migration { circuit: name.clone(), from, to, value });
+                }
+            }
+        }
+        Ok(migrations)
+    }
+}
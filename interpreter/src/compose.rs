@@ -0,0 +1,136 @@
+use crate::diagnostics::Checks;
+use crate::outputs::Outputs;
+use crate::{CircuitBuilder, CircuitConfig, GoldilocksField, Target, ZKPCircuit};
+
+type F = GoldilocksField;
+const D: usize = 2;
+
+type Constraints = Box<dyn Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>, &mut Outputs, &mut Checks)>;
+
+/// A circuit definition with named inputs that has not been built yet, so it can be
+/// composed with others before becoming a `ZKPCircuit`.
+///
+/// The constraint closures are those `ZKPCircuit` takes; `targets` holds one target per
+/// named input, in order.
+pub struct Stage {
+    inputs: Vec<String>,
+    constraints: Constraints,
+}
+
+impl Stage {
+    pub fn new(inputs: &[&str], constraint_fn: impl Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>) + 'static) -> Result<Self, anyhow::Error> {
+        Self::with_checks(inputs, move |builder, targets, _, _| constraint_fn(builder, targets))
+    }
+
+    pub fn with_outputs(inputs: &[&str], constraint_fn: impl Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>, &mut Outputs) + 'static) -> Result<Self, anyhow::Error> {
+        Self::with_checks(inputs, move |builder, targets, outputs, _| constraint_fn(builder, targets, outputs))
+    }
+
+    /// Fails if an input name is declared twice
+    pub fn with_checks(
+        inputs: &[&str],
+        constraint_fn: impl Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>, &mut Outputs, &mut Checks) + 'static,
+    ) -> Result<Self, anyhow::Error> {
+        let mut names: Vec<&str> = inputs.to_vec();
+        names.sort_unstable();
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            anyhow::bail!("Input {} declared twice", pair[0]);
+        }
+        Ok(Self { inputs: inputs.iter().map(|name| name.to_string()).collect(), constraints: Box::new(constraint_fn) })
+    }
+
+    /// The input names, in the order `prove` takes their values
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Orders named input values as `prove` takes them, failing on missing or unknown names
+    pub fn assign(&self, values: &[(&str, u64)]) -> Result<Vec<u64>, anyhow::Error> {
+        if let Some((name, _)) = values.iter().find(|(name, _)| !self.inputs.iter().any(|input| input == name)) {
+            anyhow::bail!("Unknown input {}", name);
+        }
+        self.inputs
+            .iter()
+            .map(|input| {
+                let mut matching = values.iter().filter(|(name, _)| name == input);
+                match (matching.next(), matching.next()) {
+                    (Some((_, value)), None) => Ok(*value),
+                    (None, _) => anyhow::bail!("Missing input {}", input),
+                    (Some(_), Some(_)) => anyhow::bail!("Input {} assigned twice", input),
+                }
+            })
+            .collect()
+    }
+
+    /// Runs `next` after this stage, feeding each `(output, input)` pair's named output of
+    /// this stage into the named input of `next`. An output of several elements, such as a
+    /// hash, feeds that many consecutive inputs starting at the named one.
+    ///
+    /// The composed stage takes this stage's inputs followed by the unwired inputs of
+    /// `next`, and declares the public outputs of both, which must have different names.
+    pub fn then(self, next: Stage, wiring: &[(&str, &str)]) -> Result<Stage, anyhow::Error> {
+        let declared = self.declared_outputs();
+        if let Some(spec) = next.declared_outputs().specs().iter().find(|spec| declared.targets(&spec.name).is_some()) {
+            anyhow::bail!("Output {} is declared by both stages", spec.name);
+        }
+        let mut wired: Vec<(String, usize)> = Vec::new();
+        let mut covered = vec![false; next.inputs.len()];
+        for (output, input) in wiring {
+            let len = declared.targets(output).ok_or_else(|| anyhow::anyhow!("Unknown output {}", output))?.len();
+            let index = next.inputs.iter().position(|name| name == input).ok_or_else(|| anyhow::anyhow!("Unknown input {}", input))?;
+            let span = covered.get_mut(index..index + len).ok_or_else(|| anyhow::anyhow!("Output {} does not fit the inputs from {}", output, input))?;
+            if span.iter().any(|&c| c) {
+                anyhow::bail!("Input {} is wired twice", input);
+            }
+            span.fill(true);
+            wired.push((output.to_string(), index));
+        }
+        let free: Vec<usize> = (0..next.inputs.len()).filter(|&i| !covered[i]).collect();
+        let inputs: Vec<String> = self.inputs.iter().chain(free.iter().map(|&i| &next.inputs[i])).cloned().collect();
+        if let Some(name) = free.iter().map(|&i| &next.inputs[i]).find(|name| self.inputs.contains(name)) {
+            anyhow::bail!("Input {} is declared by both stages", name);
+        }
+
+        let (first, second) = (self, next);
+        let num_first = first.inputs.len();
+        let num_second = second.inputs.len();
+        let constraints: Constraints = Box::new(move |builder, targets, outputs, checks| {
+            let mut first_targets = targets[..num_first].to_vec();
+            (first.constraints)(builder, &mut first_targets, outputs, checks);
+
+            let mut second_targets = vec![None; num_second];
+            for (&index, &target) in free.iter().zip(&targets[num_first..]) {
+                second_targets[index] = Some(target);
+            }
+            for (output, index) in &wired {
+                let output_targets = outputs.targets(output).expect("Wired outputs were checked when composing");
+                for (offset, &target) in output_targets.iter().enumerate() {
+                    second_targets[index + offset] = Some(target);
+                }
+            }
+            let mut second_targets: Vec<Target> = second_targets.into_iter().map(|target| target.expect("Every input is wired or free")).collect();
+            (second.constraints)(builder, &mut second_targets, outputs, checks);
+
+            // Targets the stages appended, such as commitment blindings, follow the named inputs
+            let mut composed = targets[..num_first + free.len()].to_vec();
+            composed.extend_from_slice(&first_targets[num_first..]);
+            composed.extend_from_slice(&second_targets[num_second..]);
+            *targets = composed;
+        });
+        Ok(Stage { inputs, constraints })
+    }
+
+    /// The outputs the stage declares, found by running its constraints on a scratch builder
+    fn declared_outputs(&self) -> Outputs {
+        // Stages may commit to inputs, which only a zero-knowledge config allows
+        let mut builder = CircuitBuilder::new(CircuitConfig::standard_recursion_zk_config());
+        let mut targets = builder.add_virtual_targets(self.inputs.len());
+        let mut outputs = Outputs::default();
+        (self.constraints)(&mut builder, &mut targets, &mut outputs, &mut Checks::default());
+        outputs
+    }
+
+    pub fn build(self, config: CircuitConfig) -> ZKPCircuit {
+        ZKPCircuit::with_checks(config, self.inputs.len(), self.constraints)
+    }
+}
@@ -10,21 +10,29 @@ use plonky2::{
         config::PoseidonGoldilocksConfig,
         proof::ProofWithPublicInputs,
     },
+    util::serialization::DefaultGateSerializer,
 };
 
-use plonky2::util::serialization::DefaultGateSerializer;
+use crate::diagnostics::{self, Checks};
+use crate::ZKPCircuit;
 
 
 const D: usize = 2;
 type C = PoseidonGoldilocksConfig;
 type F = GoldilocksField;
 
-struct CounterCircuit {
+pub struct CounterCircuit {
     circuit_data: CircuitData<F, C, D>,
     current_target: Target,
     next_target: Target,
 }
 
+impl Default for CounterCircuit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CounterCircuit {
     /// Build the counter circuit, returning the circuit data and input targets
     pub fn new() -> Self {
@@ -51,6 +59,29 @@ impl CounterCircuit {
         }
     }
 
+    pub fn get_vk(&self) -> Vec<u8> {
+        self.circuit_data.verifier_only.clone().to_bytes().unwrap_or_else(|_| vec![])
+    }
+
+    /// The vk and common data a ledger registers the circuit with
+    pub fn registration(&self) -> crate::txn::Registration {
+        let common = self.circuit_data.common.to_bytes(&DefaultGateSerializer).unwrap_or_else(|_| vec![]);
+        crate::txn::Registration { vk: self.get_vk(), common }
+    }
+
+    /// Check that `next` follows `current` without generating a proof
+    pub fn check_witness(&self, current_val: u64, next_val: u64) -> Result<(), anyhow::Error> {
+        let mut pw = PartialWitness::new();
+        pw.set_target(self.current_target, F::from_canonical_u64(current_val))?;
+        pw.set_target(self.next_target, F::from_canonical_u64(next_val))?;
+
+        let diagnosis = diagnostics::diagnose(pw, &self.circuit_data.prover_only, &self.circuit_data.common, &Checks::default());
+        if !diagnosis.is_satisfied() {
+            anyhow::bail!("Witness does not satisfy the counter circuit:\n{}", diagnosis);
+        }
+        Ok(())
+    }
+
      /// Generate the counter increment proof
     pub fn prove(&self, current_val: u64) -> Result<ProofWithPublicInputs<F, C, D>, anyhow::Error> {
         // Convert value to field element
@@ -88,6 +119,17 @@ impl CounterCircuit {
     }
 }
 
+/// The counter as a `ZKPCircuit` over (current, next), both public, as the node registers it
+pub fn counter_zkp_circuit() -> ZKPCircuit {
+    ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, |builder, targets| {
+        let one = builder.one();
+        let s: Target = builder.add(targets[0], one);
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+        builder.connect(s, targets[1]);
+    })
+}
+
 #[test]
 fn counter_example() -> Result<(), anyhow::Error> {
     // Initialize the counter circuit
@@ -114,3 +156,14 @@ fn counter_example() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+
+#[test]
+fn counter_check_witness() -> Result<(), anyhow::Error> {
+    let counter_circuit = CounterCircuit::new();
+
+    counter_circuit.check_witness(3, 4)?;
+    assert!(counter_circuit.check_witness(3, 5).is_err());
+    assert!(counter_circuit.check_witness(3, 3).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,566 @@
+use std::collections::{HashMap, VecDeque};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+#[cfg(feature = "prover")]
+use std::sync::Arc;
+use std::time::Instant;
+
+use plonky2::field::types::PrimeField64;
+use serde::{Deserialize, Serialize};
+
+use crate::abi::Abi;
+use crate::snapshot::{commit, Snapshot, SNAPSHOT_VERSION};
+use crate::state_tree::{StateProof, StateTree};
+use crate::txn::{Bundle, Registration, Transaction};
+use crate::gates::{GateSerializers, SharedGateSerializer};
+#[cfg(feature = "prover")]
+use crate::history::{History, HistoryCircuit};
+use crate::limits::Limits;
+use crate::metrics::{Metrics, RejectReason};
+use crate::{
+    circuit_id, deserialize_common_from_bytes_with_serializer, deserialize_proof_from_bytes_with_limits,
+    deserialize_vk_from_bytes_with_limits, digest_to_hex, hash_bytes, verify_circuit_data, CommonCircuitData, GoldilocksField,
+    HashOut, PoseidonGoldilocksConfig, ProofWithPublicInputs, VerifierOnlyCircuitData,
+};
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+const D: usize = 2;
+
+/// The vk, proof and common data of a transaction, as `Ledger::check` decoded them
+type Decoded = (VerifierOnlyCircuitData<C, D>, ProofWithPublicInputs<F, C, D>, CommonCircuitData<F, D>);
+
+/// Verifier-only builds cannot prove a history, so their ledgers never hold one
+#[cfg(not(feature = "prover"))]
+#[derive(Clone)]
+enum History {}
+
+/// Outcome of applying a single transaction to the ledger
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub vk_id: String,
+    pub status: ReceiptStatus,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    Applied { old: u64, new: u64 },
+    Rejected { reason: String },
+}
+
+impl Receipt {
+    pub fn is_applied(&self) -> bool {
+        matches!(self.status, ReceiptStatus::Applied { .. })
+    }
+}
+
+/// Outcome of applying a bundle: one receipt per transaction, all applied, or one per
+/// transaction up to the first rejected one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleReceipt {
+    pub bundle_hash: String,
+    pub receipts: Vec<Receipt>,
+    /// Why the bundle was rejected, if it was
+    pub error: Option<String>,
+}
+
+impl BundleReceipt {
+    pub fn is_applied(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The vk→state map, together with the receipts of the transactions it has seen: every
+/// applied one, and the latest `Limits::max_rejected_receipts` rejected ones.
+///
+/// A transaction is accepted when it carries the common data its circuit was registered with,
+/// its proof verifies against its vk and its public inputs satisfy the `Abi` of its circuit:
+/// by default, the first public input must equal the stored state and the second becomes the new state.
+/// Every state is committed in a `StateTree` kept up to date as transactions apply.
+/// Verification is measured in `Metrics`, shared by clones of the ledger but not by detached ones.
+///
+/// With the `prover` feature, a ledger may also track a `History`, a recursive proof that its state root results from
+/// valid transactions and registrations since genesis. Every state change is then proven
+/// before it applies, transactions the history cannot fold are rejected, and every circuit
+/// must use the default ABI the history circuit assumes.
+#[derive(Clone, Default)]
+pub struct Ledger {
+    states: HashMap<HashOut<F>, u64>,
+    abis: HashMap<HashOut<F>, Abi>,
+    /// Digest of the common data each circuit was registered with
+    commons: HashMap<HashOut<F>, HashOut<F>>,
+    tree: StateTree,
+    receipts: HashMap<HashOut<F>, Receipt>,
+    rejections: HashMap<HashOut<F>, Receipt>,
+    /// Rejected transactions, oldest first
+    rejection_order: VecDeque<HashOut<F>>,
+    height: u64,
+    last_tx: HashOut<F>,
+    limits: Limits,
+    gate_serializers: GateSerializers,
+    metrics: Metrics,
+    history: Option<History>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a ledger that checks incoming transactions against `limits`
+    pub fn with_limits(limits: Limits) -> Self {
+        Self { limits, ..Self::default() }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// A copy of the ledger with metrics of its own, to replay transactions already counted
+    pub fn clone_detached(&self) -> Self {
+        Self { metrics: Metrics::new(), ..self.clone() }
+    }
+
+    /// Counts the ledger's transactions in `metrics` from now on
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Lets transactions whose common data uses custom gates name `serializer` by `id`
+    pub fn register_gate_serializer(&mut self, id: &str, serializer: SharedGateSerializer) -> Result<(), anyhow::Error> {
+        self.gate_serializers.register(id, serializer)
+    }
+
+    /// Starts proving the history of the ledger, which must still be at genesis
+    #[cfg(feature = "prover")]
+    pub fn track_history(&mut self, circuit: Arc<HistoryCircuit>) -> Result<(), anyhow::Error> {
+        if self.height != 0 {
+            anyhow::bail!("History must be tracked from genesis, the ledger is at height {}", self.height);
+        }
+        if let Some((vk_id, abi)) = self.abis().first() {
+            anyhow::bail!("History only proves circuits with the default ABI, {} has: {}", digest_to_hex(vk_id), abi);
+        }
+        self.history = Some(History::new(circuit, self.state_root()));
+        Ok(())
+    }
+
+    #[cfg(feature = "prover")]
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Registers a circuit with its initial state and the default ABI, returning its id.
+    /// Registering an already known circuit leaves its state and ABI untouched.
+    /// Fails if the registration conflicts with the ledger, see `check_registration`, or
+    /// cannot be proven into the tracked history.
+    pub fn register(&mut self, registration: &Registration, initial: u64) -> Result<HashOut<F>, anyhow::Error> {
+        self.register_with_abi(registration, initial, Abi::default())
+    }
+
+    /// Registers a circuit whose public inputs follow `abi`, returning its id. The id binds the
+    /// ABI, so registering the vk with another ABI registers another circuit, with its own state.
+    /// Registering an already known circuit leaves its state untouched.
+    pub fn register_with_abi(&mut self, registration: &Registration, initial: u64, abi: Abi) -> Result<HashOut<F>, anyhow::Error> {
+        let vk_id = self.check_registration(registration, &abi)?;
+        if !self.states.contains_key(&vk_id) {
+            if self.history.is_some() {
+                self.history = self.fold_registration(&vk_id, initial)?;
+            }
+            self.abis.insert(vk_id, abi);
+            self.commons.insert(vk_id, hash_bytes(&registration.common));
+            self.set_state(vk_id, initial);
+        }
+        Ok(vk_id)
+    }
+
+    /// Checks that a circuit can be registered with `abi`, returning its id: it must not be known
+    /// with other common data, and a tracked history must be able to prove its ABI
+    pub fn check_registration(&self, registration: &Registration, abi: &Abi) -> Result<HashOut<F>, anyhow::Error> {
+        let vk_id = circuit_id(&registration.vk, abi);
+        match self.commons.get(&vk_id) {
+            Some(common) if *common != hash_bytes(&registration.common) => {
+                anyhow::bail!("Circuit {} is registered with other common data", digest_to_hex(&vk_id))
+            }
+            Some(_) => {}
+            None if self.history.is_some() && *abi != Abi::default() => anyhow::bail!("History only proves circuits with the default ABI"),
+            None => {}
+        }
+        Ok(vk_id)
+    }
+
+    /// The ABI a circuit was registered with
+    pub fn abi(&self, vk_id: &HashOut<F>) -> Option<&Abi> {
+        self.abis.get(vk_id)
+    }
+
+    /// The digest of the common data a circuit is registered with
+    pub fn common(&self, vk_id: &HashOut<F>) -> Option<HashOut<F>> {
+        self.commons.get(vk_id).copied()
+    }
+
+    /// Moves the state of a circuit whose vk changed to its new vk id, so state stored
+    /// under the old vk is not forked, and pins the digest of its new common data.
+    /// `from` and `to` are equal when only the common data changed. Returns the migrated value.
+    pub fn migrate(&mut self, from: &HashOut<F>, to: &HashOut<F>, common: HashOut<F>) -> Result<u64, anyhow::Error> {
+        if self.history.is_some() {
+            anyhow::bail!("Circuits cannot be migrated while their history is proven");
+        }
+        if from != to && self.states.contains_key(to) {
+            anyhow::bail!("Circuit {} already has state", digest_to_hex(to));
+        }
+        let value = self.states.remove(from).ok_or_else(|| anyhow::anyhow!("Unknown circuit {}", digest_to_hex(from)))?;
+        self.tree.remove(from);
+        let abi = self.abis.remove(from).unwrap_or_default();
+        self.abis.insert(*to, abi);
+        self.commons.remove(from);
+        self.commons.insert(*to, common);
+        self.set_state(*to, value);
+        Ok(value)
+    }
+
+    fn set_state(&mut self, vk_id: HashOut<F>, value: u64) {
+        self.states.insert(vk_id, value);
+        self.tree.insert(&vk_id, value);
+    }
+
+    /// Root of the Merkle tree committing to every circuit state
+    pub fn state_root(&self) -> HashOut<F> {
+        self.tree.root()
+    }
+
+    /// Proves the current state of a circuit, or that it is unknown, under `state_root`
+    pub fn prove_state(&self, vk_id: &HashOut<F>) -> StateProof {
+        self.tree.prove(vk_id, self.state(vk_id))
+    }
+
+    pub fn state(&self, vk_id: &HashOut<F>) -> Option<u64> {
+        self.states.get(vk_id).copied()
+    }
+
+    /// The receipt of a transaction: once it applied, that it did, even if it was resubmitted since
+    pub fn receipt(&self, tx_hash: &HashOut<F>) -> Option<&Receipt> {
+        self.receipts.get(tx_hash).or_else(|| self.rejections.get(tx_hash))
+    }
+
+    /// Number of transactions applied so far
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Hash of the last applied transaction, or the zero digest at genesis
+    pub fn last_tx(&self) -> HashOut<F> {
+        self.last_tx
+    }
+
+    /// All circuit states, ordered by vk id
+    pub fn states(&self) -> Vec<(HashOut<F>, u64)> {
+        let mut states: Vec<_> = self.states.iter().map(|(k, v)| (*k, *v)).collect();
+        states.sort_by_key(|(k, _)| k.elements.map(|e| e.to_canonical_u64()));
+        states
+    }
+
+    /// The ABIs of the circuits not using the default one, ordered by vk id
+    pub fn abis(&self) -> Vec<(HashOut<F>, Abi)> {
+        let mut abis: Vec<_> = self.abis.iter().filter(|(_, abi)| **abi != Abi::default()).map(|(k, v)| (*k, v.clone())).collect();
+        abis.sort_by_key(|(k, _)| k.elements.map(|e| e.to_canonical_u64()));
+        abis
+    }
+
+    /// The digest of the common data of every circuit, ordered by vk id
+    pub fn commons(&self) -> Vec<(HashOut<F>, HashOut<F>)> {
+        let mut commons: Vec<_> = self.commons.iter().map(|(k, v)| (*k, *v)).collect();
+        commons.sort_by_key(|(k, _)| k.elements.map(|e| e.to_canonical_u64()));
+        commons
+    }
+
+    /// Commits to the height, the last transaction, every circuit state, the ABIs and the common data
+    pub fn commitment(&self) -> HashOut<F> {
+        commit(self.height, &self.last_tx, &self.states(), &self.abis(), &self.commons())
+    }
+
+    /// Captures the state map and its metadata
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            height: self.height,
+            last_tx: self.last_tx,
+            states: self.states(),
+            abis: self.abis(),
+            commons: self.commons(),
+            commitment: self.commitment(),
+        }
+    }
+
+    /// Restores a ledger from a snapshot whose commitment must match `expected`
+    pub fn from_snapshot(snapshot: Snapshot, expected: &HashOut<F>) -> Result<Self, anyhow::Error> {
+        snapshot.validate()?;
+        if snapshot.commitment != *expected {
+            anyhow::bail!("Snapshot commitment {} does not match expected {}", digest_to_hex(&snapshot.commitment), digest_to_hex(expected));
+        }
+        let mut ledger = Self { height: snapshot.height, last_tx: snapshot.last_tx, ..Self::default() };
+        for (vk_id, value) in snapshot.states {
+            ledger.abis.insert(vk_id, Abi::default());
+            ledger.set_state(vk_id, value);
+        }
+        for (vk_id, abi) in snapshot.abis {
+            if !ledger.states.contains_key(&vk_id) {
+                anyhow::bail!("Snapshot has an ABI for unknown circuit {}", digest_to_hex(&vk_id));
+            }
+            ledger.abis.insert(vk_id, abi);
+        }
+        for (vk_id, common) in snapshot.commons {
+            if !ledger.states.contains_key(&vk_id) {
+                anyhow::bail!("Snapshot has common data for unknown circuit {}", digest_to_hex(&vk_id));
+            }
+            ledger.commons.insert(vk_id, common);
+        }
+        if let Some(vk_id) = ledger.states.keys().find(|vk_id| !ledger.commons.contains_key(vk_id)) {
+            anyhow::bail!("Snapshot has no common data for circuit {}", digest_to_hex(vk_id));
+        }
+        Ok(ledger)
+    }
+
+    /// Restores a ledger from a snapshot without replaying its transactions: `proof` must be a
+    /// history proof from the state root `genesis` to the snapshot's states at its height.
+    /// The restored ledger keeps extending the history.
+    #[cfg(feature = "prover")]
+    pub fn from_history(snapshot: Snapshot, circuit: Arc<HistoryCircuit>, proof: ProofWithPublicInputs<F, C, D>, genesis: &HashOut<F>) -> Result<Self, anyhow::Error> {
+        let history = History::resume(circuit, proof)?;
+        let statement = HistoryCircuit::statement(history.proof().expect("resumed histories have a proof"));
+        if statement.genesis != *genesis {
+            anyhow::bail!("History starts at {}, expected: {}", digest_to_hex(&statement.genesis), digest_to_hex(genesis));
+        }
+        if !snapshot.abis.is_empty() {
+            anyhow::bail!("History only proves circuits with the default ABI");
+        }
+        if statement.height != snapshot.height {
+            anyhow::bail!("History is at height {}, the snapshot at: {}", statement.height, snapshot.height);
+        }
+        let commitment = snapshot.commitment;
+        let mut ledger = Self::from_snapshot(snapshot, &commitment)?;
+        if ledger.state_root() != statement.state_root {
+            anyhow::bail!("History proves state root {}, the snapshot has: {}", digest_to_hex(&statement.state_root), digest_to_hex(&ledger.state_root()));
+        }
+        // The history does not commit to the common data, but only transactions of the shape it proves apply
+        ledger.history = Some(history);
+        Ok(ledger)
+    }
+
+    /// Deserializes, verifies and applies a transaction, recording its receipt
+    pub fn apply_bytes(&mut self, data: &[u8]) -> Receipt {
+        let start = Instant::now();
+        let tx = Transaction::deserialize_with_limits(data, &self.limits);
+        self.metrics.observe_decode(start.elapsed());
+        match tx {
+            Ok(tx) => self.apply(&tx),
+            Err(e) => {
+                self.metrics.rejected(RejectReason::Decode);
+                self.record(hash_bytes(data), HashOut::default(), ReceiptStatus::Rejected { reason: e.to_string() })
+            }
+        }
+    }
+
+    /// Verifies and applies a transaction, recording its receipt
+    pub fn apply(&mut self, tx: &Transaction) -> Receipt {
+        let start = Instant::now();
+        let tx_hash = tx.hash();
+        let vk_id = tx.vk_id();
+        let checked = self
+            .check(&vk_id, self.state(&vk_id), tx)
+            .and_then(|(old, new, decoded)| Ok((old, new, self.fold(self.history.as_ref(), &self.tree, &vk_id, old, &decoded)?)));
+        let status = match checked {
+            Ok((old, new, history)) => {
+                self.history = history;
+                self.set_state(vk_id, new);
+                self.height += 1;
+                self.last_tx = tx_hash;
+                self.metrics.accepted();
+                ReceiptStatus::Applied { old, new }
+            }
+            Err((reason, e)) => {
+                self.metrics.rejected(reason);
+                ReceiptStatus::Rejected { reason: e.to_string() }
+            }
+        };
+        let receipt = self.record(tx_hash, vk_id, status);
+        self.metrics.observe_apply(start.elapsed());
+        receipt
+    }
+
+    /// Deserializes and applies a bundle, recording a receipt for each of its transactions
+    pub fn apply_bundle_bytes(&mut self, data: &[u8]) -> BundleReceipt {
+        match Bundle::deserialize_with_limits(data, &self.limits) {
+            Ok(bundle) => self.apply_bundle(&bundle),
+            Err(e) => {
+                self.metrics.rejected(RejectReason::Decode);
+                BundleReceipt { bundle_hash: digest_to_hex(&hash_bytes(data)), receipts: Vec::new(), error: Some(e.to_string()) }
+            }
+        }
+    }
+
+    /// Applies every transaction of a bundle, or none of them. Each transaction is checked
+    /// against the state left by the transactions before it in the bundle.
+    pub fn apply_bundle(&mut self, bundle: &Bundle) -> BundleReceipt {
+        let bundle_hash = digest_to_hex(&bundle.hash());
+        let mut pending: HashMap<HashOut<F>, u64> = HashMap::new();
+        let mut transitions = Vec::new();
+        // The history is folded against the tree as it stands after the transactions before
+        let mut history = self.history.clone();
+        let mut tree = history.as_ref().map(|_| self.tree.clone());
+        let mut failure = bundle.transactions.is_empty().then(|| (0, RejectReason::Bundle, "Bundle is empty".to_string()));
+        for (i, tx) in bundle.transactions.iter().enumerate() {
+            let vk_id = tx.vk_id();
+            let old = pending.get(&vk_id).copied().or_else(|| self.state(&vk_id));
+            let checked = self.check(&vk_id, old, tx).and_then(|(old, new, decoded)| match tree.as_mut() {
+                Some(tree) => {
+                    history = self.fold(history.as_ref(), tree, &vk_id, old, &decoded)?;
+                    tree.insert(&vk_id, new);
+                    Ok((old, new))
+                }
+                None => Ok((old, new)),
+            });
+            match checked {
+                Ok((old, new)) => {
+                    pending.insert(vk_id, new);
+                    transitions.push((tx.hash(), vk_id, ReceiptStatus::Applied { old, new }));
+                }
+                Err((reason, e)) => {
+                    failure = Some((i, reason, e.to_string()));
+                    break;
+                }
+            }
+        }
+
+        if let Some((index, counted, reason)) = failure {
+            // Transactions after the rejected one were never checked, so they get no receipt
+            let receipts = bundle
+                .transactions
+                .iter()
+                .take(index + 1)
+                .enumerate()
+                .map(|(i, tx)| {
+                    self.metrics.rejected(if i == index { counted } else { RejectReason::Bundle });
+                    let reason = if i == index { reason.clone() } else { format!("Bundle rejected at transaction {}", index) };
+                    self.record(tx.hash(), tx.vk_id(), ReceiptStatus::Rejected { reason })
+                })
+                .collect();
+            return BundleReceipt { bundle_hash, receipts, error: Some(format!("Transaction {}: {}", index, reason)) };
+        }
+
+        self.history = history;
+        for (vk_id, value) in pending {
+            self.set_state(vk_id, value);
+        }
+        let receipts = transitions
+            .into_iter()
+            .map(|(tx_hash, vk_id, status)| {
+                self.height += 1;
+                self.last_tx = tx_hash;
+                self.metrics.accepted();
+                self.record(tx_hash, vk_id, status)
+            })
+            .collect();
+        BundleReceipt { bundle_hash, receipts, error: None }
+    }
+
+    /// Checks a transaction of circuit `vk_id` against `old`, the state of the circuit, returning
+    /// the state transition it proves with its decoded parts, or why it was rejected
+    fn check(&self, vk_id: &HashOut<F>, old: Option<u64>, tx: &Transaction) -> Result<(u64, u64, Decoded), (RejectReason, anyhow::Error)> {
+        self.metrics.observe_proof_size(tx.proof_data.len());
+        let old = old.ok_or_else(|| (RejectReason::UnknownCircuit, anyhow::anyhow!("Unknown circuit")))?;
+        // Other common data could weaken the FRI parameters or swap the gates the proof is checked against
+        if self.commons.get(vk_id) != Some(&hash_bytes(&tx.common)) {
+            return Err((RejectReason::CommonData, anyhow::anyhow!("Common data differs from the one the circuit was registered with")));
+        }
+        let serializer = self.gate_serializers.get(&tx.gate_serializer).map_err(|e| (RejectReason::UnknownGateSerializer, e))?;
+
+        // The limits keep plonky2 from panicking on hostile input; a panic they missed still only rejects the transaction
+        let panicked = |_| anyhow::anyhow!("Verification panicked");
+
+        let start = Instant::now();
+        let decoded = catch_unwind(AssertUnwindSafe(|| {
+            deserialize_common_from_bytes_with_serializer(tx.common.clone(), &self.limits, serializer.as_ref()).and_then(|common| {
+                let proof = deserialize_proof_from_bytes_with_limits(tx.proof_data.clone(), common.clone(), &self.limits)?;
+                let vk = deserialize_vk_from_bytes_with_limits(tx.vk.clone(), &self.limits)?;
+                Ok((vk, proof, common))
+            })
+        }))
+        .unwrap_or_else(|e| Err(panicked(e)));
+        self.metrics.observe_deserialize(start.elapsed());
+        let (vk, proof, common) = decoded.map_err(|e| (RejectReason::Malformed, e))?;
+
+        let new = tx.abi.transition(&proof.public_inputs, old)?;
+
+        let start = Instant::now();
+        let verified = catch_unwind(AssertUnwindSafe(|| verify_circuit_data(proof.clone(), vk.clone(), common.clone()))).unwrap_or_else(|e| Err(panicked(e)));
+        self.metrics.observe_verify(start.elapsed());
+        verified.map_err(|e| (RejectReason::InvalidProof, e))?;
+
+        Ok((old, new, (vk, proof, common)))
+    }
+
+    /// Proves a transaction of circuit `vk_id` that passed `check` against `old` into the history,
+    /// if one is tracked. `tree` is the state tree the transaction applies to.
+    #[cfg(feature = "prover")]
+    fn fold(
+        &self,
+        history: Option<&History>,
+        tree: &StateTree,
+        vk_id: &HashOut<F>,
+        old: u64,
+        (vk, proof, common): &Decoded,
+    ) -> Result<Option<History>, (RejectReason, anyhow::Error)> {
+        let Some(history) = history else {
+            return Ok(None);
+        };
+        if common != history.circuit().tx_common() {
+            return Err((RejectReason::History, anyhow::anyhow!("Transaction circuit does not have the shape the history proves")));
+        }
+        history.transaction(&tree.prove(vk_id, Some(old)), vk, proof).map(Some).map_err(|e| (RejectReason::History, e))
+    }
+
+    #[cfg(not(feature = "prover"))]
+    fn fold(&self, _history: Option<&History>, _tree: &StateTree, _vk_id: &HashOut<F>, _old: u64, _decoded: &Decoded) -> Result<Option<History>, (RejectReason, anyhow::Error)> {
+        Ok(None)
+    }
+
+    /// Proves the registration of a circuit with its initial state into the tracked history
+    #[cfg(feature = "prover")]
+    fn fold_registration(&self, vk_id: &HashOut<F>, initial: u64) -> Result<Option<History>, anyhow::Error> {
+        let Some(history) = self.history.as_ref() else {
+            return Ok(None);
+        };
+        history.registration(&self.tree.prove(vk_id, None), initial).map(Some)
+    }
+
+    #[cfg(not(feature = "prover"))]
+    fn fold_registration(&self, _vk_id: &HashOut<F>, _initial: u64) -> Result<Option<History>, anyhow::Error> {
+        Ok(None)
+    }
+
+    fn record(&mut self, tx_hash: HashOut<F>, vk_id: HashOut<F>, status: ReceiptStatus) -> Receipt {
+        let receipt = Receipt {
+            tx_hash: digest_to_hex(&tx_hash),
+            vk_id: digest_to_hex(&vk_id),
+            status,
+        };
+        if receipt.is_applied() {
+            self.rejections.remove(&tx_hash);
+            self.receipts.insert(tx_hash, receipt.clone());
+        } else if !self.receipts.contains_key(&tx_hash) {
+            if self.rejections.insert(tx_hash, receipt.clone()).is_none() {
+                self.rejection_order.push_back(tx_hash);
+            }
+            while self.rejections.len() > self.limits.max_rejected_receipts {
+                let Some(oldest) = self.rejection_order.pop_front() else { break };
+                self.rejections.remove(&oldest);
+            }
+        }
+        receipt
+    }
+}
+
@@ -0,0 +1,141 @@
+use std::sync::Mutex;
+
+use plonky2::iop::generator::{GeneratedValues, SimpleGenerator, WitnessGeneratorRef};
+use plonky2::iop::witness::{PartitionWitness, WitnessWrite};
+use plonky2::plonk::circuit_data::ProverOnlyCircuitData;
+use plonky2::plonk::prover::prove;
+use plonky2::util::serialization::{Buffer, DefaultGeneratorSerializer, IoResult, Read, WitnessGeneratorSerializer, Write};
+use plonky2::util::timing::TimingTree;
+use plonky2::field::types::Sample;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+use crate::txn::{Registration, Transaction};
+use crate::{CommonCircuitData, GoldilocksField, PoseidonGoldilocksConfig, ProofWithPublicInputs, Target, ZKPCircuit};
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+const D: usize = 2;
+
+/// Proves a circuit reproducibly, for golden files and fixtures. Not for production.
+///
+/// plonky2 draws the blinding of zero-knowledge circuits from the OS, and salts its Merkle
+/// leaves the same way. This prover fills the blinding rows from a RNG seeded with `seed`
+/// and commits without salt, under a copy of the common data with hiding turned off. Its
+/// proofs verify against the circuit's own vk, but only with that copy, which its
+/// transactions carry: a transaction from this prover is not zero-knowledge, and is
+/// byte-identical across runs for the same seed and inputs.
+///
+/// Ledgers pin the common data a circuit is registered with, so these transactions only
+/// apply where the circuit was explicitly registered with `registration`.
+///
+/// Circuits using custom witness generators are not supported.
+pub struct DeterministicProver<'a> {
+    circuit: &'a ZKPCircuit,
+    prover_only: ProverOnlyCircuitData<F, C, D>,
+    common: CommonCircuitData<F, D>,
+}
+
+impl<'a> DeterministicProver<'a> {
+    pub fn new(circuit: &'a ZKPCircuit, seed: u64) -> Result<Self, anyhow::Error> {
+        let data = &circuit.circuit_data;
+        let mut common = data.common.clone();
+        common.config.zero_knowledge = false;
+        common.fri_params.hiding = false;
+
+        let bytes = data
+            .prover_only
+            .to_bytes(&DefaultGeneratorSerializer::<C, D>::default(), &data.common)
+            .map_err(|_| anyhow::anyhow!("Circuit uses witness generators the deterministic prover cannot copy"))?;
+        let serializer = SeededGeneratorSerializer { rng: Mutex::new(ChaCha20Rng::seed_from_u64(seed)) };
+        let prover_only = ProverOnlyCircuitData::from_bytes(&bytes, &serializer, &data.common)
+            .map_err(|_| anyhow::anyhow!("Failed to copy the prover data"))?;
+        Ok(Self { circuit, prover_only, common })
+    }
+
+    pub fn prove(&self, inputs: Vec<u64>) -> Result<ProofWithPublicInputs<F, C, D>, anyhow::Error> {
+        let witness = self.circuit.witness(inputs)?;
+        // The proof of work search takes whichever nonce a thread finds first, so it runs on one
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build()?;
+        pool.install(|| prove(&self.prover_only, &self.common, witness, &mut TimingTree::default()))
+    }
+
+    /// The common data the proofs of this prover verify with
+    pub fn common(&self) -> &CommonCircuitData<F, D> {
+        &self.common
+    }
+
+    /// Registers the circuit with the common data of this prover, which its transactions need.
+    /// Not zero-knowledge: a ledger registered so accepts proofs without hiding.
+    pub fn registration(&self) -> Result<Registration, anyhow::Error> {
+        Ok(Registration { vk: self.circuit.get_vk(), common: self.common_bytes()? })
+    }
+
+    /// Packages a proof of this prover as a transaction, with the common data it verifies with
+    pub fn transaction(&self, proof: &ProofWithPublicInputs<F, C, D>) -> Result<Transaction, anyhow::Error> {
+        Ok(Transaction {
+            vk: self.circuit.get_vk(),
+            proof_data: proof.to_bytes(),
+            common: self.common_bytes()?,
+            gate_serializer: self.circuit.gate_serializer.0.clone(),
+            abi: self.circuit.abi().clone(),
+        })
+    }
+
+    fn common_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        self.common.to_bytes(self.circuit.gate_serializer.1.as_ref()).map_err(|e| anyhow::anyhow!("Failed to serialize common data: {}", e))
+    }
+}
+
+/// Reads plonky2's generators, replacing each random value by one drawn from a seeded RNG
+struct SeededGeneratorSerializer {
+    rng: Mutex<ChaCha20Rng>,
+}
+
+impl WitnessGeneratorSerializer<F, D> for SeededGeneratorSerializer {
+    fn read_generator(&self, buf: &mut Buffer, common_data: &CommonCircuitData<F, D>) -> IoResult<WitnessGeneratorRef<F, D>> {
+        let generator = DefaultGeneratorSerializer::<C, D>::default().read_generator(buf, common_data)?;
+        if generator.0.id() != "RandomValueGenerator" {
+            return Ok(generator);
+        }
+        let mut bytes = Vec::new();
+        generator.0.serialize(&mut bytes, common_data)?;
+        let target = Buffer::new(&bytes).read_target()?;
+        let value = F::sample(&mut *self.rng.lock().expect("RNG lock poisoned"));
+        Ok(WitnessGeneratorRef::new(SeededValueGenerator { target, value }.adapter()))
+    }
+
+    fn write_generator(&self, buf: &mut Vec<u8>, generator: &WitnessGeneratorRef<F, D>, common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        DefaultGeneratorSerializer::<C, D>::default().write_generator(buf, generator, common_data)
+    }
+}
+
+/// Sets a blinding target to a value drawn when the prover was created
+#[derive(Debug, Default)]
+struct SeededValueGenerator {
+    target: Target,
+    value: F,
+}
+
+impl SimpleGenerator<F, D> for SeededValueGenerator {
+    fn id(&self) -> String {
+        "SeededValueGenerator".to_string()
+    }
+
+    fn dependencies(&self) -> Vec<Target> {
+        Vec::new()
+    }
+
+    fn run_once(&self, _witness: &PartitionWitness<F>, out_buffer: &mut GeneratedValues<F>) -> Result<(), anyhow::Error> {
+        out_buffer.set_target(self.target, self.value)
+    }
+
+    fn serialize(&self, dst: &mut Vec<u8>, _common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        dst.write_target(self.target)?;
+        dst.write_field(self.value)
+    }
+
+    fn deserialize(src: &mut Buffer, _common_data: &CommonCircuitData<F, D>) -> IoResult<Self> {
+        Ok(Self { target: src.read_target()?, value: src.read_field()? })
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+use std::panic::Location;
+
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::generator::GeneratedValues;
+use plonky2::iop::target::BoolTarget;
+use plonky2::iop::witness::{PartitionWitness, Witness};
+use plonky2::plonk::circuit_data::{CommonCircuitData, ProverOnlyCircuitData};
+use plonky2::plonk::config::Hasher;
+use plonky2::plonk::vars::EvaluationVarsBaseBatch;
+
+use crate::{CircuitBuilder, GoldilocksField, PartialWitness, PoseidonGoldilocksConfig, Target};
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+const D: usize = 2;
+
+/// The kind of a labelled constraint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckKind {
+    Connect,
+    AssertZero,
+    AssertOne,
+    AssertBool,
+}
+
+/// A constraint added through `Checks`, remembered with its label and call site
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub kind: CheckKind,
+    pub label: String,
+    pub location: &'static Location<'static>,
+    pub targets: Vec<Target>,
+}
+
+/// Adds constraints while recording a label and the source location of each, so a
+/// failing witness can be traced back to the constraint it violates.
+///
+/// Every method adds exactly the constraints of the `CircuitBuilder` method it is
+/// named after, so labelling a constraint never changes the circuit.
+#[derive(Clone, Debug, Default)]
+pub struct Checks {
+    checks: Vec<Check>,
+}
+
+impl Checks {
+    #[track_caller]
+    pub fn connect(&mut self, builder: &mut CircuitBuilder<F, D>, x: Target, y: Target, label: &str) {
+        builder.connect(x, y);
+        self.record(CheckKind::Connect, label, vec![x, y]);
+    }
+
+    #[track_caller]
+    pub fn assert_zero(&mut self, builder: &mut CircuitBuilder<F, D>, x: Target, label: &str) {
+        let zero = builder.zero();
+        builder.connect(x, zero);
+        self.record(CheckKind::AssertZero, label, vec![x, zero]);
+    }
+
+    #[track_caller]
+    pub fn assert_one(&mut self, builder: &mut CircuitBuilder<F, D>, x: Target, label: &str) {
+        let one = builder.one();
+        builder.connect(x, one);
+        self.record(CheckKind::AssertOne, label, vec![x, one]);
+    }
+
+    #[track_caller]
+    pub fn assert_bool(&mut self, builder: &mut CircuitBuilder<F, D>, b: BoolTarget, label: &str) {
+        let z = builder.mul_sub(b.target, b.target, b.target);
+        let zero = builder.zero();
+        builder.connect(z, zero);
+        self.record(CheckKind::AssertBool, label, vec![b.target, z, zero]);
+    }
+
+    #[track_caller]
+    fn record(&mut self, kind: CheckKind, label: &str, targets: Vec<Target>) {
+        self.checks.push(Check { kind, label: label.to_string(), location: Location::caller(), targets });
+    }
+
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+}
+
+/// A constraint the witness does not satisfy
+#[derive(Clone, Debug)]
+pub struct ConstraintFailure {
+    /// The labelled check the failure belongs to, if it was added through `Checks`
+    pub check: Option<Check>,
+    /// The target whose value disagreed with the rest of its copy-constraint partition
+    pub target: Target,
+    /// The value the partition already held
+    pub expected: u64,
+    /// The value the witness or a generator tried to give `target`
+    pub actual: u64,
+}
+
+impl fmt::Display for ConstraintFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.check {
+            Some(check) => write!(f, "{:?} \"{}\" at {}", check.kind, check.label, check.location)?,
+            None => write!(f, "unlabelled copy constraint")?,
+        }
+        write!(f, " failed: {:?} is {} but must equal {}", self.target, self.actual, self.expected)
+    }
+}
+
+/// A gate constraint that does not vanish on a row of the witness
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GateFailure {
+    pub gate: String,
+    pub row: usize,
+    /// Index of the constraint among the gate's constraints
+    pub constraint: usize,
+    pub value: u64,
+}
+
+impl fmt::Display for GateFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "constraint {} of {} on row {} evaluates to {}", self.constraint, self.gate, self.row, self.value)
+    }
+}
+
+/// Everything witness generation revealed about an unsatisfiable witness
+#[derive(Clone, Debug, Default)]
+pub struct Diagnosis {
+    pub failures: Vec<ConstraintFailure>,
+    pub gate_failures: Vec<GateFailure>,
+    /// Generators that could not run, usually because an input was never set
+    pub stalled_generators: Vec<String>,
+}
+
+impl Diagnosis {
+    pub fn is_satisfied(&self) -> bool {
+        self.failures.is_empty() && self.gate_failures.is_empty() && self.stalled_generators.is_empty()
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for failure in &self.failures {
+            writeln!(f, "{}", failure)?;
+        }
+        for failure in &self.gate_failures {
+            writeln!(f, "{}", failure)?;
+        }
+        if !self.stalled_generators.is_empty() {
+            writeln!(f, "{} generators could not run: {}", self.stalled_generators.len(), self.stalled_generators.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A value that conflicted with its partition during witness generation
+pub(crate) struct Conflict {
+    pub target: Target,
+    pub expected: F,
+    pub actual: F,
+}
+
+/// Runs witness generation like plonky2's `generate_partial_witness`, except that a
+/// value conflicting with its partition is recorded and skipped instead of aborting.
+/// Returns the witness, the conflicts and the ids of the generators that never finished.
+pub(crate) fn generate_lenient_witness<'a>(
+    inputs: PartialWitness<F>,
+    prover_data: &'a ProverOnlyCircuitData<F, C, D>,
+    common_data: &'a CommonCircuitData<F, D>,
+) -> (PartitionWitness<'a, F>, Vec<Conflict>, Vec<String>) {
+    let generators = &prover_data.generators;
+    let mut witness = PartitionWitness::new(common_data.config.num_wires, common_data.degree(), &prover_data.representative_map);
+    let mut conflicts = Vec::new();
+
+    for (target, value) in inputs.target_values {
+        set_lenient(&mut witness, target, value, &mut conflicts);
+    }
+
+    let mut pending: Vec<usize> = (0..generators.len()).collect();
+    let mut expired = vec![false; generators.len()];
+    let mut buffer = GeneratedValues::empty();
+    while !pending.is_empty() {
+        let mut next_pending = Vec::new();
+        for &index in &pending {
+            if expired[index] {
+                continue;
+            }
+            if generators[index].0.run(&witness, &mut buffer) {
+                expired[index] = true;
+            }
+            for (target, value) in buffer.target_values.drain(..).collect::<Vec<_>>() {
+                if let Some(rep) = set_lenient(&mut witness, target, value, &mut conflicts) {
+                    if let Some(watchers) = prover_data.generator_indices_by_watches.get(&rep) {
+                        next_pending.extend(watchers.iter().filter(|&&w| !expired[w]));
+                    }
+                }
+            }
+        }
+        pending = next_pending;
+    }
+
+    let stalled = generators
+        .iter()
+        .zip(&expired)
+        .filter(|(_, &done)| !done)
+        .map(|(generator, _)| generator.0.id())
+        .collect();
+    (witness, conflicts, stalled)
+}
+
+/// Sets a target unless its partition already holds another value, returning the
+/// representative of a newly populated partition
+fn set_lenient(witness: &mut PartitionWitness<F>, target: Target, value: F, conflicts: &mut Vec<Conflict>) -> Option<usize> {
+    match witness.try_get_target(target) {
+        Some(expected) if expected != value => {
+            conflicts.push(Conflict { target, expected, actual: value });
+            None
+        }
+        _ => witness.set_target_returning_rep(target, value).ok().flatten(),
+    }
+}
+
+/// Diagnoses a witness against a circuit. A conflict is attributed to the labelled check
+/// on the conflicting target itself, or else to the first check sharing its partition.
+pub(crate) fn diagnose(
+    inputs: PartialWitness<F>,
+    prover_data: &ProverOnlyCircuitData<F, C, D>,
+    common_data: &CommonCircuitData<F, D>,
+    checks: &Checks,
+) -> Diagnosis {
+    let (witness, conflicts, stalled_generators) = generate_lenient_witness(inputs, prover_data, common_data);
+    let gate_failures = check_gate_constraints(&witness, prover_data, common_data);
+
+    let rep = |target: Target| prover_data.representative_map[target.index(common_data.config.num_wires, common_data.degree())];
+    let mut checks_by_target: HashMap<Target, &Check> = HashMap::new();
+    let mut checks_by_rep: BTreeMap<usize, &Check> = BTreeMap::new();
+    for check in checks.checks() {
+        for &target in &check.targets {
+            checks_by_target.entry(target).or_insert(check);
+            checks_by_rep.entry(rep(target)).or_insert(check);
+        }
+    }
+
+    let failures = conflicts
+        .into_iter()
+        .map(|conflict| ConstraintFailure {
+            check: checks_by_target
+                .get(&conflict.target)
+                .or_else(|| checks_by_rep.get(&rep(conflict.target)))
+                .map(|&check| check.clone()),
+            target: conflict.target,
+            expected: conflict.expected.to_canonical_u64(),
+            actual: conflict.actual.to_canonical_u64(),
+        })
+        .collect();
+    Diagnosis { failures, gate_failures, stalled_generators }
+}
+
+/// The selector group of every gate, as plonky2's `SelectorsInfo` holds them privately
+pub(crate) struct Selectors {
+    pub selector_indices: Vec<usize>,
+    pub groups: Vec<Range<usize>>,
+}
+
+/// Recomputes the selector groups from the gates the way plonky2 builds them: gates, sorted by
+/// degree, share a selector while the filtered constraints stay within `quotient_degree_factor + 1`
+pub(crate) fn selectors(common_data: &CommonCircuitData<F, D>) -> Selectors {
+    let degrees: Vec<usize> = common_data.gates.iter().map(|gate| gate.0.degree()).collect();
+    let max_degree = common_data.quotient_degree_factor + 1;
+    let single = degrees.last().copied().unwrap_or_default() + degrees.len() <= max_degree + 1;
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < degrees.len() {
+        let size = if single {
+            degrees.len()
+        } else {
+            // A gate of too high a degree fails to build, so never stalls here
+            (0..degrees.len() - start).take_while(|&size| size + degrees[start + size] < max_degree).count().max(1)
+        };
+        groups.push(start..start + size);
+        start += size;
+    }
+    let selector_indices = groups.iter().enumerate().flat_map(|(i, group)| group.clone().map(move |_| i)).collect();
+    Selectors { selector_indices, groups }
+}
+
+/// Values of the constant polynomials, selectors first, on every row of the trace
+pub(crate) fn constant_values(prover_data: &ProverOnlyCircuitData<F, C, D>, common_data: &CommonCircuitData<F, D>) -> Vec<Vec<F>> {
+    // The commitment's LDE lives on a coset, so evaluate the constant polynomials on the trace domain
+    prover_data.constants_sigmas_commitment.polynomials[..common_data.num_constants]
+        .iter()
+        .map(|poly| poly.clone().fft().values)
+        .collect()
+}
+
+/// Evaluates every gate constraint on every row of the witness natively, the way the
+/// prover's vanishing polynomial would, without committing to anything.
+/// Lookup arguments are not checked, as they depend on the verifier's challenges.
+pub(crate) fn check_gate_constraints(
+    witness: &PartitionWitness<F>,
+    prover_data: &ProverOnlyCircuitData<F, C, D>,
+    common_data: &CommonCircuitData<F, D>,
+) -> Vec<GateFailure> {
+    let selectors = selectors(common_data);
+
+    let public_inputs: Vec<F> = prover_data
+        .public_inputs
+        .iter()
+        .map(|&t| witness.try_get_target(t).unwrap_or_default())
+        .collect();
+    let public_inputs_hash = PoseidonHash::hash_no_pad(&public_inputs);
+    let constant_values = constant_values(prover_data, common_data);
+
+    let mut failures = Vec::new();
+    for row in 0..common_data.degree() {
+        let constants: Vec<F> = constant_values.iter().map(|values| values[row]).collect();
+        // Wires no generator reached are zero, as in plonky2's `full_witness`
+        let local_wires: Vec<F> = (0..common_data.config.num_wires)
+            .map(|column| witness.try_get_target(Target::wire(row, column)).unwrap_or_default())
+            .collect();
+        for (i, gate) in common_data.gates.iter().enumerate() {
+            let selector_index = selectors.selector_indices[i];
+            let vars = EvaluationVarsBaseBatch::new(1, &constants, &local_wires, &public_inputs_hash);
+            let values = gate.0.eval_filtered_base_batch(
+                vars,
+                i,
+                selector_index,
+                selectors.groups[selector_index].clone(),
+                selectors.groups.len(),
+                common_data.num_lookup_selectors,
+            );
+            failures.extend(values.iter().enumerate().filter(|(_, v)| !v.is_zero()).map(|(constraint, v)| GateFailure {
+                gate: gate.0.id(),
+                row,
+                constraint,
+                value: v.to_canonical_u64(),
+            }));
+        }
+    }
+    failures
+}
@@ -0,0 +1,242 @@
+use plonky2::field::types::Field64;
+#[cfg(feature = "prover")]
+use plonky2::field::types::{Field, PrimeField64};
+#[cfg(feature = "prover")]
+use plonky2::iop::generator::{GeneratedValues, SimpleGenerator};
+#[cfg(feature = "prover")]
+use plonky2::iop::witness::{PartitionWitness, Witness, WitnessWrite};
+#[cfg(feature = "prover")]
+use plonky2::util::serialization::{Buffer, IoResult, Read, Write};
+
+use crate::GoldilocksField;
+#[cfg(feature = "prover")]
+use crate::{CircuitBuilder, CommonCircuitData, Target};
+
+type F = GoldilocksField;
+#[cfg(feature = "prover")]
+const D: usize = 2;
+
+/// Signed values are encoded within `-2^SIGNED_BITS..2^SIGNED_BITS`, far enough from half
+/// the field order that a negative value never reads as a positive one
+pub const SIGNED_BITS: usize = 62;
+
+/// Encodes a signed integer as the field element it is congruent to, as a canonical `u64`
+/// ready for `ZKPCircuit::prove`
+pub fn encode_signed(value: i64) -> Result<u64, anyhow::Error> {
+    if !(-(1 << SIGNED_BITS)..1 << SIGNED_BITS).contains(&value) {
+        anyhow::bail!("Signed value {} is out of the {} bit range", value, SIGNED_BITS);
+    }
+    Ok(if value >= 0 { value as u64 } else { F::ORDER - value.unsigned_abs() })
+}
+
+/// Decodes a canonical field value, such as a public input, back to a signed integer
+pub fn decode_signed(x: u64) -> Result<i64, anyhow::Error> {
+    if x < 1 << SIGNED_BITS {
+        Ok(x as i64)
+    } else if x < F::ORDER && F::ORDER - x <= 1 << SIGNED_BITS {
+        Ok(-((F::ORDER - x) as i64))
+    } else {
+        anyhow::bail!("Field value {} does not encode a signed value", x)
+    }
+}
+
+#[cfg(feature = "prover")]
+fn to_field(value: i64) -> Result<F, anyhow::Error> {
+    Ok(F::from_canonical_u64(encode_signed(value)?))
+}
+
+/// Decimal numbers with `scale` digits after the point, encoded as signed integers
+/// counting units of `10^-scale`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedPoint {
+    scale: u32,
+}
+
+impl FixedPoint {
+    pub fn new(scale: u32) -> Result<Self, anyhow::Error> {
+        if scale > 18 {
+            anyhow::bail!("Fixed point scale {} exceeds 18 digits", scale);
+        }
+        Ok(Self { scale })
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// The encoding of 1
+    pub fn factor(&self) -> u64 {
+        10u64.pow(self.scale)
+    }
+
+    /// The number of units in a decimal like `-12.5`, which may not have more than `scale` digits after the point
+    pub fn to_units(&self, decimal: &str) -> Result<i64, anyhow::Error> {
+        let invalid = || anyhow::anyhow!("Invalid decimal {:?} for scale {}", decimal, self.scale);
+        let (negative, digits) = match decimal.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, decimal),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) || fraction.len() > self.scale as usize {
+            return Err(invalid());
+        }
+        let fraction = format!("{:0<width$}", fraction, width = self.scale as usize);
+        let units = whole
+            .parse::<i64>()
+            .ok()
+            .and_then(|whole| whole.checked_mul(self.factor() as i64))
+            .and_then(|units| units.checked_add(fraction.parse::<i64>().unwrap_or_default()))
+            .ok_or_else(invalid)?;
+        Ok(if negative { -units } else { units })
+    }
+
+    /// Formats a number of units with exactly `scale` digits after the point
+    pub fn format_units(&self, units: i64) -> String {
+        let sign = if units < 0 { "-" } else { "" };
+        let (whole, fraction) = (units.unsigned_abs() / self.factor(), units.unsigned_abs() % self.factor());
+        match self.scale {
+            0 => format!("{}{}", sign, whole),
+            scale => format!("{}{}.{:0width$}", sign, whole, fraction, width = scale as usize),
+        }
+    }
+
+    /// Encodes a decimal as a canonical `u64` ready for `ZKPCircuit::prove`
+    pub fn encode(&self, decimal: &str) -> Result<u64, anyhow::Error> {
+        encode_signed(self.to_units(decimal)?)
+    }
+
+    /// Decodes a canonical field value back to a decimal
+    pub fn decode(&self, x: u64) -> Result<String, anyhow::Error> {
+        Ok(self.format_units(decode_signed(x)?))
+    }
+
+    /// The product of two numbers of units, rounded down to a number of units as `mul_fixed` does
+    pub fn mul(&self, a: i64, b: i64) -> i64 {
+        let product = a as i128 * b as i128;
+        product.div_euclid(self.factor() as i128) as i64
+    }
+}
+
+/// Checks that `x` encodes a signed value in `-2^bits..2^bits`
+#[cfg(feature = "prover")]
+pub fn range_check_signed(builder: &mut CircuitBuilder<F, D>, x: Target, bits: usize) {
+    assert!(bits < SIGNED_BITS, "Signed range checks support up to {} bits, got: {}", SIGNED_BITS - 1, bits);
+    let offset = builder.constant(F::from_canonical_u64(1 << bits));
+    let shifted = builder.add(x, offset);
+    builder.range_check(shifted, bits + 1);
+}
+
+/// `a + b`, checked to stay within `bits` signed bits
+#[cfg(feature = "prover")]
+pub fn add_signed(builder: &mut CircuitBuilder<F, D>, a: Target, b: Target, bits: usize) -> Target {
+    let sum = builder.add(a, b);
+    range_check_signed(builder, sum, bits);
+    sum
+}
+
+/// `a - b`, checked to stay within `bits` signed bits
+#[cfg(feature = "prover")]
+pub fn sub_signed(builder: &mut CircuitBuilder<F, D>, a: Target, b: Target, bits: usize) -> Target {
+    let difference = builder.sub(a, b);
+    range_check_signed(builder, difference, bits);
+    difference
+}
+
+/// The fixed point product `a * b`, rescaled by rounding down to `fixed`'s scale.
+/// Both operands and the result are checked to be within `bits` signed bits.
+#[cfg(feature = "prover")]
+pub fn mul_fixed(builder: &mut CircuitBuilder<F, D>, a: Target, b: Target, fixed: FixedPoint, bits: usize) -> Target {
+    let factor = fixed.factor();
+    let remainder_bits = (u64::BITS - (factor - 1).leading_zeros()).max(1) as usize;
+    // Neither the product nor the quotient times the factor may wrap around the field
+    assert!(2 * bits < SIGNED_BITS && bits + remainder_bits < SIGNED_BITS, "{} bit operands overflow at scale {}", bits, fixed.scale());
+    range_check_signed(builder, a, bits);
+    range_check_signed(builder, b, bits);
+    let product = builder.mul(a, b);
+
+    let quotient = builder.add_virtual_target();
+    let remainder = builder.add_virtual_target();
+    builder.add_simple_generator(RescaleGenerator { product, quotient, remainder, factor });
+    let factor_target = builder.constant(F::from_canonical_u64(factor));
+    let rebuilt = builder.mul_add(quotient, factor_target, remainder);
+    builder.connect(rebuilt, product);
+    // 0 <= remainder < factor
+    builder.range_check(remainder, remainder_bits);
+    let largest = builder.constant(F::from_canonical_u64(factor - 1));
+    let slack = builder.sub(largest, remainder);
+    builder.range_check(slack, remainder_bits);
+    range_check_signed(builder, quotient, bits);
+    quotient
+}
+
+/// Divides a signed product by the scale factor, rounding down
+#[cfg(feature = "prover")]
+#[derive(Debug, Default)]
+struct RescaleGenerator {
+    product: Target,
+    quotient: Target,
+    remainder: Target,
+    factor: u64,
+}
+
+#[cfg(feature = "prover")]
+impl SimpleGenerator<F, D> for RescaleGenerator {
+    fn id(&self) -> String {
+        "RescaleGenerator".to_string()
+    }
+
+    fn dependencies(&self) -> Vec<Target> {
+        vec![self.product]
+    }
+
+    fn run_once(&self, witness: &PartitionWitness<F>, out_buffer: &mut GeneratedValues<F>) -> Result<(), anyhow::Error> {
+        let product = decode_signed(witness.get_target(self.product).to_canonical_u64())?;
+        let factor = self.factor as i64;
+        out_buffer.set_target(self.quotient, to_field(product.div_euclid(factor))?)?;
+        out_buffer.set_target(self.remainder, to_field(product.rem_euclid(factor))?)
+    }
+
+    fn serialize(&self, dst: &mut Vec<u8>, _common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        dst.write_target(self.product)?;
+        dst.write_target(self.quotient)?;
+        dst.write_target(self.remainder)?;
+        dst.write_field(F::from_canonical_u64(self.factor))
+    }
+
+    fn deserialize(src: &mut Buffer, _common_data: &CommonCircuitData<F, D>) -> IoResult<Self> {
+        Ok(Self {
+            product: src.read_target()?,
+            quotient: src.read_target()?,
+            remainder: src.read_target()?,
+            factor: src.read_field::<F>()?.to_canonical_u64(),
+        })
+    }
+}
+
+#[test]
+fn encodings_round_trip() -> Result<(), anyhow::Error> {
+    for value in [0, 1, -1, 42, -42, (1 << SIGNED_BITS) - 1, -(1 << SIGNED_BITS)] {
+        assert_eq!(decode_signed(encode_signed(value)?)?, value);
+    }
+    assert!(encode_signed(1 << SIGNED_BITS).is_err());
+    assert_eq!(encode_signed(-1)?, F::ORDER - 1);
+    assert!(decode_signed(1 << 63).is_err());
+
+    let cents = FixedPoint::new(2)?;
+    for decimal in ["0.00", "12.50", "-0.05", "-1234.99"] {
+        assert_eq!(cents.decode(cents.encode(decimal)?)?, decimal);
+    }
+    assert_eq!(cents.to_units("3.1")?, 310);
+    assert_eq!(cents.to_units("-7")?, -700);
+    assert!(cents.to_units("1.234").is_err());
+    assert!(cents.to_units("1.2.3").is_err());
+    assert!(cents.to_units("-").is_err());
+    assert_eq!(FixedPoint::new(0)?.format_units(-5), "-5");
+    assert!(FixedPoint::new(19).is_err());
+
+    // Products round down, towards negative infinity
+    assert_eq!(cents.mul(150, 250), 375);
+    assert_eq!(cents.mul(-150, 3), -5);
+    Ok(())
+}
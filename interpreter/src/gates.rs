@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(feature = "prover")]
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::gates::gate::GateRef;
+#[cfg(feature = "prover")]
+use plonky2::iop::generator::{GeneratedValues, SimpleGenerator};
+#[cfg(feature = "prover")]
+use plonky2::iop::witness::{PartitionWitness, Witness, WitnessWrite};
+use plonky2::util::serialization::{Buffer, GateSerializer, IoResult, Read, Write};
+
+use crate::{CommonCircuitData, DefaultGateSerializer, GoldilocksField};
+#[cfg(feature = "prover")]
+use crate::{CircuitBuilder, Target};
+
+type F = GoldilocksField;
+const D: usize = 2;
+
+/// Id of the serializer for circuits built only from plonky2's own gates
+pub const DEFAULT_GATE_SERIALIZER: &str = "default";
+
+pub type SharedGateSerializer = Arc<dyn GateSerializer<F, D> + Send + Sync>;
+
+/// Serializes plonky2's own gates with `DefaultGateSerializer` and every other gate with
+/// the wrapped serializer, which then only needs to know the custom gates
+#[derive(Debug)]
+pub struct WithCustomGates<S>(pub S);
+
+/// Marks which serializer wrote a gate
+const DEFAULT_TAG: u8 = 0;
+const CUSTOM_TAG: u8 = 1;
+
+impl<S: GateSerializer<F, D>> GateSerializer<F, D> for WithCustomGates<S> {
+    fn read_gate(&self, buf: &mut Buffer, common_data: &CommonCircuitData<F, D>) -> IoResult<GateRef<F, D>> {
+        match buf.read_u8()? {
+            DEFAULT_TAG => DefaultGateSerializer.read_gate(buf, common_data),
+            CUSTOM_TAG => self.0.read_gate(buf, common_data),
+            _ => Err(plonky2::util::serialization::IoError),
+        }
+    }
+
+    fn write_gate(&self, buf: &mut Vec<u8>, gate: &GateRef<F, D>, common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        let mut default = Vec::new();
+        if DefaultGateSerializer.write_gate(&mut default, gate, common_data).is_ok() {
+            buf.write_u8(DEFAULT_TAG)?;
+            buf.extend_from_slice(&default);
+            return Ok(());
+        }
+        buf.write_u8(CUSTOM_TAG)?;
+        self.0.write_gate(buf, gate, common_data)
+    }
+}
+
+/// Gate serializers by the id transactions record them under, so a verifier can decode
+/// the common data of circuits using custom gates
+#[derive(Clone)]
+pub struct GateSerializers {
+    serializers: HashMap<String, SharedGateSerializer>,
+}
+
+impl Default for GateSerializers {
+    fn default() -> Self {
+        let mut serializers: HashMap<String, SharedGateSerializer> = HashMap::new();
+        serializers.insert(DEFAULT_GATE_SERIALIZER.to_string(), Arc::new(DefaultGateSerializer));
+        Self { serializers }
+    }
+}
+
+impl GateSerializers {
+    /// Registers a serializer, refusing to replace one already registered under `id`
+    pub fn register(&mut self, id: &str, serializer: SharedGateSerializer) -> Result<(), anyhow::Error> {
+        if self.serializers.contains_key(id) {
+            anyhow::bail!("Gate serializer {} is already registered", id);
+        }
+        self.serializers.insert(id.to_string(), serializer);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Result<&SharedGateSerializer, anyhow::Error> {
+        self.serializers.get(id).ok_or_else(|| anyhow::anyhow!("Unknown gate serializer {}", id))
+    }
+}
+
+/// Constrains `x < 2^bits` with one table lookup per byte of `x`, which costs far fewer
+/// rows than `range_check`'s one wire per bit once a circuit checks many values.
+/// `bits` is at most 56, so the recombined bytes cannot wrap around the field.
+#[cfg(feature = "prover")]
+pub fn lookup_range_check(builder: &mut CircuitBuilder<F, D>, x: Target, bits: usize) {
+    assert!(bits <= 56, "Lookup range checks support at most 56 bits, got: {}", bits);
+    let bytes = builder.add_virtual_targets(bits.div_ceil(8));
+    builder.add_simple_generator(ByteDecompositionGenerator { x, bytes: bytes.clone() });
+
+    let mut sum = builder.zero();
+    for &byte in bytes.iter().rev() {
+        sum = builder.mul_const_add(F::from_canonical_u64(256), sum, byte);
+    }
+    builder.connect(sum, x);
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        // The top byte only holds the bits left over
+        let byte_bits = (bits - 8 * i).min(8);
+        let inputs: Vec<u16> = (0..1 << byte_bits).collect();
+        let table = builder.add_lookup_table_from_fn(|b| b, &inputs);
+        builder.add_lookup_from_index(byte, table);
+    }
+}
+
+/// Splits a value into little-endian bytes
+#[cfg(feature = "prover")]
+#[derive(Debug, Default)]
+struct ByteDecompositionGenerator {
+    x: Target,
+    bytes: Vec<Target>,
+}
+
+#[cfg(feature = "prover")]
+impl SimpleGenerator<F, D> for ByteDecompositionGenerator {
+    fn id(&self) -> String {
+        "ByteDecompositionGenerator".to_string()
+    }
+
+    fn dependencies(&self) -> Vec<Target> {
+        vec![self.x]
+    }
+
+    fn run_once(&self, witness: &PartitionWitness<F>, out_buffer: &mut GeneratedValues<F>) -> Result<(), anyhow::Error> {
+        let x = witness.get_target(self.x).to_canonical_u64();
+        for (i, &byte) in self.bytes.iter().enumerate() {
+            // Bytes past the eighth of a u64 are zero
+            let value = x.checked_shr(8 * i as u32).unwrap_or_default() & 0xff;
+            out_buffer.set_target(byte, F::from_canonical_u64(value))?;
+        }
+        Ok(())
+    }
+
+    fn serialize(&self, dst: &mut Vec<u8>, _common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        dst.write_target(self.x)?;
+        dst.write_target_vec(&self.bytes)
+    }
+
+    fn deserialize(src: &mut Buffer, _common_data: &CommonCircuitData<F, D>) -> IoResult<Self> {
+        Ok(Self { x: src.read_target()?, bytes: src.read_target_vec()? })
+    }
+}
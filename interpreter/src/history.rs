@@ -0,0 +1,350 @@
+use std::sync::Arc;
+
+use plonky2::field::types::PrimeField64;
+use plonky2::gates::gate::GateRef;
+use plonky2::gates::noop::NoopGate;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::BoolTarget;
+use plonky2::plonk::circuit_data::VerifierCircuitTarget;
+use plonky2::plonk::proof::ProofWithPublicInputsTarget;
+use plonky2::recursion::cyclic_recursion::check_cyclic_proof_verifier_data;
+use plonky2::recursion::dummy_circuit::{cyclic_base_proof, dummy_circuit, dummy_proof};
+
+use crate::state_tree::{StateProof, STATE_TREE_DEPTH};
+use crate::{
+    digest_to_hex, CircuitBuilder, CircuitConfig, CircuitData, CommonCircuitData, Field, GoldilocksField, HashOut, PartialWitness,
+    PoseidonGoldilocksConfig, ProofWithPublicInputs, Target, VerifierOnlyCircuitData, WitnessWrite,
+};
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+const D: usize = 2;
+type Proof = ProofWithPublicInputs<F, C, D>;
+
+/// Rebuilds of the step circuit allowed before its common data must have settled
+const MAX_BUILDS: usize = 5;
+
+/// What a history proof attests: `height` valid transactions, and any number of circuit
+/// registrations, lead from the state root `genesis` to `state_root`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryStatement {
+    pub genesis: HashOut<F>,
+    pub state_root: HashOut<F>,
+    pub height: u64,
+}
+
+/// Verifies a transaction proof of any circuit of one shape, exposing the circuit's vk id and
+/// the state transition as `[vk_id, old, new]`
+struct Wrapper {
+    circuit_data: CircuitData<F, C, D>,
+    tx_proof: ProofWithPublicInputsTarget<D>,
+    tx_vk: VerifierCircuitTarget,
+    /// Stands in for the wrapped transaction of registration steps
+    dummy: Proof,
+}
+
+struct StepTargets {
+    genesis: HashOutTarget,
+    has_previous: BoolTarget,
+    previous: ProofWithPublicInputsTarget<D>,
+    is_transaction: BoolTarget,
+    wrapped: ProofWithPublicInputsTarget<D>,
+    registered: HashOutTarget,
+    initial: Target,
+    path: Vec<HashOutTarget>,
+}
+
+/// The cyclic recursive circuit extending a history proof by one state change.
+///
+/// Each step verifies the previous step, or starts from the genesis root, and either moves a
+/// circuit's leaf from a transaction proof's first public input to its second, or registers a
+/// circuit by filling its empty leaf. Public inputs are the genesis root, the new state root
+/// and the height, followed by the step's own vk.
+///
+/// Transaction proofs are first verified by a wrapper circuit, which the step verifies in
+/// turn, so that registrations can swap it for a dummy proof. The wrapper verifies against a
+/// single `CommonCircuitData`, so only circuits of that shape can be folded into the history.
+pub struct HistoryCircuit {
+    pub circuit_data: CircuitData<F, C, D>,
+    tx_common: CommonCircuitData<F, D>,
+    wrapper: Wrapper,
+    targets: StepTargets,
+}
+
+impl HistoryCircuit {
+    /// Builds the step circuit for transactions whose circuits have `tx_common` as common data
+    pub fn new(tx_common: &CommonCircuitData<F, D>) -> Result<Self, anyhow::Error> {
+        if tx_common.num_public_inputs < 2 {
+            anyhow::bail!("Expected at least 2 public inputs, got: {}", tx_common.num_public_inputs);
+        }
+        let wrapper = build_wrapper(tx_common)?;
+        // The step verifies proofs of itself, so its common data is found by rebuilding it
+        // against the previous build's until they agree
+        let mut goal = wrapper.circuit_data.common.clone();
+        for _ in 0..MAX_BUILDS {
+            let (circuit_data, targets, settled) = build_step(&wrapper.circuit_data, &mut goal)?;
+            if settled {
+                return Ok(Self { circuit_data, tx_common: tx_common.clone(), wrapper, targets });
+            }
+            goal = circuit_data.common;
+        }
+        anyhow::bail!("History circuit did not settle after {} builds", MAX_BUILDS)
+    }
+
+    /// The common data of the transaction proofs the history folds
+    pub fn tx_common(&self) -> &CommonCircuitData<F, D> {
+        &self.tx_common
+    }
+
+    pub fn statement(proof: &Proof) -> HistoryStatement {
+        let inputs = &proof.public_inputs;
+        HistoryStatement {
+            genesis: HashOut { elements: [inputs[0], inputs[1], inputs[2], inputs[3]] },
+            state_root: HashOut { elements: [inputs[4], inputs[5], inputs[6], inputs[7]] },
+            height: inputs[8].to_canonical_u64(),
+        }
+    }
+
+    /// Decodes a history proof, as `Proof::to_bytes` encodes it
+    pub fn proof_from_bytes(&self, data: Vec<u8>) -> Result<Proof, anyhow::Error> {
+        Proof::from_bytes(data, &self.circuit_data.common)
+    }
+
+    /// Verifies a history proof, including that every step of it was made with this circuit
+    pub fn verify(&self, proof: &Proof) -> Result<HistoryStatement, anyhow::Error> {
+        self.circuit_data.verify(proof.clone())?;
+        check_cyclic_proof_verifier_data(proof, &self.circuit_data.verifier_only, &self.circuit_data.common)?;
+        Ok(Self::statement(proof))
+    }
+
+    /// Extends `previous`, or starts a history at `genesis`, by a transaction proven by `proof`
+    /// against `vk`. `state` must prove the circuit's current state, the proof's first public input.
+    pub fn prove_transaction(
+        &self,
+        genesis: &HashOut<F>,
+        previous: Option<&Proof>,
+        state: &StateProof,
+        vk: &VerifierOnlyCircuitData<C, D>,
+        proof: &Proof,
+    ) -> Result<Proof, anyhow::Error> {
+        let mut wrapper_witness = PartialWitness::new();
+        wrapper_witness.set_proof_with_pis_target(&self.wrapper.tx_proof, proof)?;
+        wrapper_witness.set_verifier_data_target(&self.wrapper.tx_vk, vk)?;
+        let wrapped = self.wrapper.circuit_data.prove(wrapper_witness)?;
+
+        let mut witness = self.witness(genesis, previous, state)?;
+        witness.set_bool_target(self.targets.is_transaction, true)?;
+        witness.set_proof_with_pis_target(&self.targets.wrapped, &wrapped)?;
+        witness.set_hash_target(self.targets.registered, HashOut::ZERO)?;
+        witness.set_target(self.targets.initial, F::ZERO)?;
+        self.circuit_data.prove(witness)
+    }
+
+    /// Extends `previous`, or starts a history at `genesis`, by registering `state.vk_id` with
+    /// an `initial` state. `state` must prove the circuit is not registered yet.
+    pub fn prove_registration(&self, genesis: &HashOut<F>, previous: Option<&Proof>, state: &StateProof, initial: u64) -> Result<Proof, anyhow::Error> {
+        let mut witness = self.witness(genesis, previous, state)?;
+        witness.set_bool_target(self.targets.is_transaction, false)?;
+        witness.set_proof_with_pis_target(&self.targets.wrapped, &self.wrapper.dummy)?;
+        witness.set_hash_target(self.targets.registered, state.vk_id)?;
+        witness.set_target(self.targets.initial, F::from_noncanonical_u64(initial))?;
+        self.circuit_data.prove(witness)
+    }
+
+    /// Assigns the previous step, or the base case, and the Merkle path of the changed leaf
+    fn witness(&self, genesis: &HashOut<F>, previous: Option<&Proof>, state: &StateProof) -> Result<PartialWitness<F>, anyhow::Error> {
+        let mut witness = PartialWitness::new();
+        witness.set_hash_target(self.targets.genesis, *genesis)?;
+        match previous {
+            Some(previous) => {
+                if Self::statement(previous).genesis != *genesis {
+                    anyhow::bail!("History proof starts at {}, expected: {}", digest_to_hex(&Self::statement(previous).genesis), digest_to_hex(genesis));
+                }
+                witness.set_bool_target(self.targets.has_previous, true)?;
+                witness.set_proof_with_pis_target(&self.targets.previous, previous)?;
+            }
+            None => {
+                let base = cyclic_base_proof(
+                    &self.circuit_data.common,
+                    &self.circuit_data.verifier_only,
+                    genesis.elements.into_iter().enumerate().collect(),
+                );
+                witness.set_bool_target(self.targets.has_previous, false)?;
+                witness.set_proof_with_pis_target(&self.targets.previous, &base)?;
+            }
+        }
+        for (target, sibling) in self.targets.path.iter().zip(state.path()) {
+            witness.set_hash_target(*target, sibling)?;
+        }
+        Ok(witness)
+    }
+}
+
+fn build_wrapper(tx_common: &CommonCircuitData<F, D>) -> Result<Wrapper, anyhow::Error> {
+    let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+    let tx_proof = builder.add_virtual_proof_with_pis(tx_common);
+    let tx_vk = builder.add_virtual_verifier_data(tx_common.config.fri_config.cap_height);
+    builder.verify_proof::<C>(&tx_proof, &tx_vk, tx_common);
+    let id = vk_id(&mut builder, &tx_vk);
+    builder.register_public_inputs(&id.elements);
+    builder.register_public_inputs(&tx_proof.public_inputs[..2]);
+    // Dummy proofs are only made for circuits with padding rows
+    builder.add_gate_to_gate_set(GateRef::new(NoopGate));
+    let circuit_data = builder.build::<C>();
+    let dummy = dummy_proof(&dummy_circuit::<F, C, D>(&circuit_data.common), Default::default())?;
+    Ok(Wrapper { circuit_data, tx_proof, tx_vk, dummy })
+}
+
+/// Builds the step circuit against `goal`, the common data it expects of its own proofs,
+/// returning whether the build matched it
+fn build_step(wrapper: &CircuitData<F, C, D>, goal: &mut CommonCircuitData<F, D>) -> Result<(CircuitData<F, C, D>, StepTargets, bool), anyhow::Error> {
+    let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+    let genesis = builder.add_virtual_hash();
+    builder.register_public_inputs(&genesis.elements);
+    let root = builder.add_virtual_hash();
+    builder.register_public_inputs(&root.elements);
+    let height = builder.add_virtual_public_input();
+    builder.add_verifier_data_public_inputs();
+    goal.num_public_inputs = builder.num_public_inputs();
+
+    // Without a previous step, the history starts at the genesis root with no transactions
+    let has_previous = builder.add_virtual_bool_target_safe();
+    let previous = builder.add_virtual_proof_with_pis(goal);
+    let previous_genesis = HashOutTarget::try_from(&previous.public_inputs[0..4]).expect("four elements");
+    let previous_root = HashOutTarget::try_from(&previous.public_inputs[4..8]).expect("four elements");
+    builder.connect_hashes(genesis, previous_genesis);
+    let old_root = select_hash(&mut builder, has_previous, previous_root, genesis);
+    let previous_height = builder.mul(has_previous.target, previous.public_inputs[8]);
+
+    // A transaction moves its circuit's leaf to a new state, a registration fills an empty leaf
+    let is_transaction = builder.add_virtual_bool_target_safe();
+    let wrapped = builder.add_virtual_proof_with_pis(&wrapper.common);
+    let wrapper_vk = builder.constant_verifier_data(&wrapper.verifier_only);
+    builder.conditionally_verify_proof_or_dummy::<C>(is_transaction, &wrapped, &wrapper_vk, &wrapper.common)?;
+    let registered = builder.add_virtual_hash();
+    let initial = builder.add_virtual_target();
+    let tx_id = HashOutTarget::try_from(&wrapped.public_inputs[0..4]).expect("four elements");
+    let id = select_hash(&mut builder, is_transaction, tx_id, registered);
+
+    let old_value = wrapped.public_inputs[4];
+    let old_leaf = leaf_hash(&mut builder, id, old_value);
+    let empty = builder.constant_hash(HashOut::ZERO);
+    let old_leaf = select_hash(&mut builder, is_transaction, old_leaf, empty);
+    let new_value = builder.select(is_transaction, wrapped.public_inputs[5], initial);
+    let new_leaf = leaf_hash(&mut builder, id, new_value);
+
+    // Both leaves sit at the same path, so the old root and the new share their siblings
+    let bits: Vec<BoolTarget> = id.elements.iter().flat_map(|&element| canonical_bits(&mut builder, element).into_iter().rev()).collect();
+    let path: Vec<HashOutTarget> = (0..STATE_TREE_DEPTH).map(|_| builder.add_virtual_hash()).collect();
+    let mut old_digest = old_leaf;
+    let mut new_digest = new_leaf;
+    for (depth, sibling) in (1..=STATE_TREE_DEPTH).rev().zip(&path) {
+        old_digest = hash_pair(&mut builder, bits[depth - 1], *sibling, old_digest);
+        new_digest = hash_pair(&mut builder, bits[depth - 1], *sibling, new_digest);
+    }
+    builder.connect_hashes(old_digest, old_root);
+    builder.connect_hashes(new_digest, root);
+    let new_height = builder.add(previous_height, is_transaction.target);
+    builder.connect(new_height, height);
+
+    builder.conditionally_verify_cyclic_proof_or_dummy::<C>(has_previous, &previous, goal)?;
+    let (circuit_data, settled) = builder.try_build_with_options::<C>(true);
+    let targets = StepTargets { genesis, has_previous, previous, is_transaction, wrapped, registered, initial, path };
+    Ok((circuit_data, targets, settled))
+}
+
+fn select_hash(builder: &mut CircuitBuilder<F, D>, condition: BoolTarget, x: HashOutTarget, y: HashOutTarget) -> HashOutTarget {
+    HashOutTarget { elements: std::array::from_fn(|i| builder.select(condition, x.elements[i], y.elements[i])) }
+}
+
+/// The parent of `digest` and its `sibling`, with `digest` on the right when `is_right` is set
+fn hash_pair(builder: &mut CircuitBuilder<F, D>, is_right: BoolTarget, sibling: HashOutTarget, digest: HashOutTarget) -> HashOutTarget {
+    let left = select_hash(builder, is_right, sibling, digest);
+    let right = select_hash(builder, is_right, digest, sibling);
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>(left.elements.into_iter().chain(right.elements).collect())
+}
+
+/// In-circuit `state_tree::leaf_hash`
+fn leaf_hash(builder: &mut CircuitBuilder<F, D>, id: HashOutTarget, value: Target) -> HashOutTarget {
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>(id.elements.into_iter().chain([value]).collect())
+}
+
+/// The 64 bits of the canonical value of `x`, least significant first. A plain `split_le`
+/// would also accept the bits of `x + p` when that fits in 64 bits.
+fn canonical_bits(builder: &mut CircuitBuilder<F, D>, x: Target) -> Vec<BoolTarget> {
+    let bits = builder.split_le(x, 64);
+    let low = builder.le_sum(bits[..32].iter());
+    let high = builder.le_sum(bits[32..].iter());
+    // Below p = 2^64 - 2^32 + 1, the high half may only be all ones when the low half is zero
+    let max = builder.constant(F::from_canonical_u32(u32::MAX));
+    let high_is_max = builder.is_equal(high, max);
+    let overflow = builder.mul(high_is_max.target, low);
+    builder.assert_zero(overflow);
+    bits
+}
+
+/// In-circuit `hash_bytes` of a serialized vk, which is the id the ledger keeps its state under
+fn vk_id(builder: &mut CircuitBuilder<F, D>, vk: &VerifierCircuitTarget) -> HashOutTarget {
+    // A vk serializes as its cap height, its cap and its circuit digest, as u64 words
+    let cap = &vk.constants_sigmas_cap.0;
+    let len = 8 + 32 * (cap.len() + 1);
+    let mut words = vec![
+        builder.constant(F::from_canonical_usize(len)),
+        builder.constant(F::from_canonical_usize(cap.len().trailing_zeros() as usize)),
+        builder.zero(),
+    ];
+    for element in cap.iter().flat_map(|hash| hash.elements).chain(vk.circuit_digest.elements) {
+        let bits = canonical_bits(builder, element);
+        words.push(builder.le_sum(bits[..32].iter()));
+        words.push(builder.le_sum(bits[32..].iter()));
+    }
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>(words)
+}
+
+/// A ledger's running history proof, moved forward with every state change the ledger makes.
+/// Clones share the step circuit.
+#[derive(Clone)]
+pub struct History {
+    circuit: Arc<HistoryCircuit>,
+    genesis: HashOut<F>,
+    /// `None` until the first state change after genesis
+    proof: Option<Proof>,
+}
+
+impl History {
+    /// Starts a history at the state root `genesis`
+    pub fn new(circuit: Arc<HistoryCircuit>, genesis: HashOut<F>) -> Self {
+        Self { circuit, genesis, proof: None }
+    }
+
+    /// Continues the history `proof` attests, after verifying it
+    pub fn resume(circuit: Arc<HistoryCircuit>, proof: Proof) -> Result<Self, anyhow::Error> {
+        let statement = circuit.verify(&proof)?;
+        Ok(Self { circuit, genesis: statement.genesis, proof: Some(proof) })
+    }
+
+    pub fn circuit(&self) -> &Arc<HistoryCircuit> {
+        &self.circuit
+    }
+
+    pub fn genesis(&self) -> HashOut<F> {
+        self.genesis
+    }
+
+    pub fn proof(&self) -> Option<&Proof> {
+        self.proof.as_ref()
+    }
+
+    /// The history after a transaction, `state` proving its circuit's state before it
+    pub fn transaction(&self, state: &StateProof, vk: &VerifierOnlyCircuitData<C, D>, proof: &Proof) -> Result<Self, anyhow::Error> {
+        let proof = self.circuit.prove_transaction(&self.genesis, self.proof.as_ref(), state, vk, proof)?;
+        Ok(Self { proof: Some(proof), ..self.clone() })
+    }
+
+    /// The history after a registration, `state` proving the circuit was not registered
+    pub fn registration(&self, state: &StateProof, initial: u64) -> Result<Self, anyhow::Error> {
+        let proof = self.circuit.prove_registration(&self.genesis, self.proof.as_ref(), state, initial)?;
+        Ok(Self { proof: Some(proof), ..self.clone() })
+    }
+}
@@ -1,8 +1,39 @@
 pub mod txn;
+pub mod abi;
+pub mod block;
+pub mod commitment;
+pub mod compat;
+#[cfg(feature = "prover")]
+pub mod compose;
+#[cfg(feature = "prover")]
 pub mod counter;
+pub mod db;
+#[cfg(feature = "deterministic-prover")]
+pub mod deterministic;
+#[cfg(feature = "prover")]
+pub mod diagnostics;
+pub mod encoding;
+pub mod gates;
+#[cfg(feature = "prover")]
+pub mod history;
+pub mod limits;
+pub mod metrics;
+pub mod node;
+#[cfg(feature = "prover")]
+pub mod outputs;
+#[cfg(feature = "prover")]
+pub mod prover;
+pub mod replication;
+pub mod snapshot;
+pub mod state_tree;
+#[cfg(feature = "prover")]
+pub mod stats;
+#[cfg(feature = "prover")]
+pub mod templates;
 
 pub use plonky2::{
     field::{goldilocks_field::GoldilocksField, types::Field},
+    hash::hash_types::HashOut,
     iop::{
         target::Target,
         witness::{PartialWitness, WitnessWrite},
@@ -15,22 +46,57 @@ pub use plonky2::{
     },
     util::serialization::DefaultGateSerializer,
 };
+#[cfg(feature = "prover")]
+use abi::Abi;
+#[cfg(feature = "prover")]
+use diagnostics::{Checks, Diagnosis};
+#[cfg(feature = "prover")]
+use gates::{SharedGateSerializer, DEFAULT_GATE_SERIALIZER};
+use limits::Limits;
+#[cfg(feature = "prover")]
+use outputs::{OutputSpec, Outputs, PublicOutputs};
+#[cfg(feature = "prover")]
+use stats::{CircuitStats, Timings};
+use plonky2::{
+    field::types::Field64,
+    hash::poseidon::PoseidonHash,
+    plonk::config::{GenericHashOut, Hasher},
+};
 
 const D: usize = 2;
 type C = PoseidonGoldilocksConfig;
 type F = GoldilocksField;
 
 /// A structure representing a general ZKP circuit that can be customized with different constraints.
+#[cfg(feature = "prover")]
 pub struct ZKPCircuit{
     pub circuit_data: CircuitData<F, C, D>,
     targets: Vec<Target>,
+    outputs: Outputs,
+    checks: Checks,
+    gate_serializer: (String, SharedGateSerializer),
+    abi: Abi,
 }
 
+#[cfg(feature = "prover")]
 impl ZKPCircuit {
     /// Builds a new general ZKP circuit
     pub fn new(config: CircuitConfig, num_inputs: usize, constraint_fn: impl Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>)) -> Self {
+        Self::with_outputs(config, num_inputs, |builder, targets, _| constraint_fn(builder, targets))
+    }
+
+    /// Builds a new general ZKP circuit whose constraints may declare named public outputs
+    pub fn with_outputs(config: CircuitConfig, num_inputs: usize, constraint_fn: impl Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>, &mut Outputs)) -> Self {
+        Self::with_checks(config, num_inputs, |builder, targets, outputs, _| constraint_fn(builder, targets, outputs))
+    }
+
+    /// Builds a new general ZKP circuit whose constraints may also be added through
+    /// labelled `Checks`, which `diagnose` reports by label and source location
+    pub fn with_checks(config: CircuitConfig, num_inputs: usize, constraint_fn: impl Fn(&mut CircuitBuilder<F, D>, &mut Vec<Target>, &mut Outputs, &mut Checks)) -> Self {
         let mut builder = CircuitBuilder::<F, D>::new(config);
         let mut targets = Vec::new();
+        let mut outputs = Outputs::default();
+        let mut checks = Checks::default();
 
         // Create virtual targets for all inputs
         for _ in 0..num_inputs {
@@ -39,7 +105,7 @@ impl ZKPCircuit {
         }
 
         // Add the custom constraints to the circuit
-        constraint_fn(&mut builder, &mut targets);
+        constraint_fn(&mut builder, &mut targets, &mut outputs, &mut checks);
 
         // Build the circuit
         let circuit_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2> = builder.build::<C>();
@@ -47,15 +113,80 @@ impl ZKPCircuit {
         Self {
             circuit_data,
             targets,
+            outputs,
+            checks,
+            gate_serializer: (DEFAULT_GATE_SERIALIZER.to_string(), std::sync::Arc::new(DefaultGateSerializer)),
+            abi: Abi::default(),
         }
     }
 
-    pub fn get_vk(self: &Self) -> Vec<u8> {
+    /// Serializes the circuit's common data with `serializer`, which must handle any custom
+    /// gates the circuit uses. Transactions record `id`, the name verifiers register it under.
+    pub fn with_gate_serializer(mut self, id: &str, serializer: SharedGateSerializer) -> Self {
+        self.gate_serializer = (id.to_string(), serializer);
+        self
+    }
+
+    /// Declares the roles of the circuit's public inputs, for registering it with a ledger
+    pub fn with_abi(mut self, abi: Abi) -> Self {
+        self.abi = abi;
+        self
+    }
+
+    /// The roles of the circuit's public inputs
+    pub fn abi(&self) -> &Abi {
+        &self.abi
+    }
+
+    /// The id of the serializer the circuit's common data is written with
+    pub fn gate_serializer_id(&self) -> &str {
+        &self.gate_serializer.0
+    }
+
+    /// The named public outputs declared by the circuit
+    pub fn outputs(&self) -> &[OutputSpec] {
+        self.outputs.specs()
+    }
+
+    /// Gate counts by type, dimensions and the estimated proof size of the circuit
+    pub fn stats(&self) -> CircuitStats {
+        CircuitStats::new(&self.circuit_data.prover_only, &self.circuit_data.common)
+    }
+
+    /// Like `stats`, with the prove and verify time and proof size measured on the inputs
+    pub fn measure(&self, inputs: Vec<u64>) -> Result<CircuitStats, anyhow::Error> {
+        let start = std::time::Instant::now();
+        let proof = self.prove(inputs)?;
+        let prove = start.elapsed();
+        let proof_size = proof.to_bytes().len();
+        let start = std::time::Instant::now();
+        self.circuit_data.verify(proof)?;
+        let verify = start.elapsed();
+        Ok(CircuitStats { timings: Some(Timings { prove, verify, proof_size }), ..self.stats() })
+    }
+
+    pub fn get_vk(&self) -> Vec<u8> {
         self.circuit_data.verifier_only.clone().to_bytes().unwrap_or_else(|_| vec![])
     }
 
     pub fn get_common_circuit_data(&self) -> Vec<u8> {
-        self.circuit_data.common.clone().to_bytes(&DefaultGateSerializer).unwrap_or_else(|_| vec![])
+        self.circuit_data.common.clone().to_bytes(self.gate_serializer.1.as_ref()).unwrap_or_else(|_| vec![])
+    }
+
+    /// The vk and common data a ledger registers the circuit with
+    pub fn registration(&self) -> txn::Registration {
+        txn::Registration { vk: self.get_vk(), common: self.get_common_circuit_data() }
+    }
+
+    /// Packages a proof of this circuit as a transaction
+    pub fn transaction(&self, proof: &ProofWithPublicInputs<F, C, D>) -> txn::Transaction {
+        txn::Transaction {
+            vk: self.get_vk(),
+            proof_data: proof.to_bytes(),
+            common: self.get_common_circuit_data(),
+            gate_serializer: self.gate_serializer_id().to_string(),
+            abi: self.abi.clone(),
+        }
     }
 
     /// Generates the proof for a given set of inputs
@@ -63,7 +194,39 @@ impl ZKPCircuit {
         &self,
         inputs: Vec<u64>,
     ) -> Result<ProofWithPublicInputs<F, C, D>, anyhow::Error> {
+        let witness = self.witness(inputs)?;
+
+        // Generate proof
+        let proof= self.circuit_data.prove(witness)?;
+
+        Ok(proof)
+    }
+
+    /// Generates the proof in diagnostic mode: an unsatisfiable witness is reported by
+    /// the constraints it violates instead of plonky2's generator error
+    pub fn prove_with_diagnostics(&self, inputs: Vec<u64>) -> Result<ProofWithPublicInputs<F, C, D>, anyhow::Error> {
+        self.check_witness(inputs.clone())?;
+        self.prove(inputs)
+    }
+
+    /// Checks that the inputs satisfy every constraint without generating a proof
+    pub fn check_witness(&self, inputs: Vec<u64>) -> Result<(), anyhow::Error> {
+        let diagnosis = self.diagnose(inputs)?;
+        if !diagnosis.is_satisfied() {
+            anyhow::bail!("Witness does not satisfy the circuit:\n{}", diagnosis);
+        }
+        Ok(())
+    }
+
+    /// Runs witness generation, evaluates every gate constraint natively and reports
+    /// every constraint the inputs violate
+    pub fn diagnose(&self, inputs: Vec<u64>) -> Result<Diagnosis, anyhow::Error> {
+        let witness = self.witness(inputs)?;
+        Ok(diagnostics::diagnose(witness, &self.circuit_data.prover_only, &self.circuit_data.common, &self.checks))
+    }
 
+    /// Assigns the inputs to the input targets
+    fn witness(&self, inputs: Vec<u64>) -> Result<PartialWitness<F>, anyhow::Error> {
         if inputs.len() != self.targets.len() {
             println!("Input size mismatch, expected: {}, got: {}", self.targets.len(), inputs.len());
             anyhow::bail!("Input size mismatch");
@@ -79,11 +242,21 @@ impl ZKPCircuit {
         let mut witness = PartialWitness::new();
         for (i, &val) in field_inputs.iter().enumerate() {
             let _ = witness.set_target(self.targets[i], val);
-        }        
-        // Generate proof
-        let proof= self.circuit_data.prove(witness)?;
+        }
+        Ok(witness)
+    }
 
-        Ok(proof)
+    /// Generates the proof and decodes the public outputs the circuit computed
+    pub fn prove_with_outputs(&self, inputs: Vec<u64>) -> Result<(ProofWithPublicInputs<F, C, D>, PublicOutputs), anyhow::Error> {
+        let proof = self.prove(inputs)?;
+        let outputs = self.outputs.decode(&proof.public_inputs)?;
+        Ok((proof, outputs))
+    }
+
+    /// Verifies the proof and returns its decoded public outputs
+    pub fn verify_outputs(&self, proof: &ProofWithPublicInputs<F, C, D>) -> Result<PublicOutputs, anyhow::Error> {
+        self.circuit_data.verify(proof.clone())?;
+        self.outputs.decode(&proof.public_inputs)
     }
 
     /// Verifies the proof
@@ -122,6 +295,12 @@ pub fn verify_circuit_data(proof: ProofWithPublicInputs<F, C, D>, vk: VerifierOn
 }
 
 pub fn deserialize_vk_from_bytes(data: Vec<u8>) -> Result<VerifierOnlyCircuitData<C, 2>, anyhow::Error> {
+    deserialize_vk_from_bytes_with_limits(data, &Limits::default())
+}
+
+/// Deserializes a verifier key after checking it against `limits`
+pub fn deserialize_vk_from_bytes_with_limits(data: Vec<u8>, limits: &Limits) -> Result<VerifierOnlyCircuitData<C, 2>, anyhow::Error> {
+    limits::check_vk_bytes(&data, limits)?;
     match VerifierOnlyCircuitData::from_bytes(data) {
         Ok(vk) => {
             println!("Verification key loaded successfully");
@@ -135,6 +314,13 @@ pub fn deserialize_vk_from_bytes(data: Vec<u8>) -> Result<VerifierOnlyCircuitDat
 }
 
 pub fn deserialize_proof_from_bytes(data: Vec<u8>, common_data: CommonCircuitData<F, D>) -> Result<ProofWithPublicInputs<F, C, 2>, anyhow::Error> {
+    deserialize_proof_from_bytes_with_limits(data, common_data, &Limits::default())
+}
+
+/// Deserializes a proof after checking it against `limits`.
+/// The common data is trusted to have been checked when it was deserialized.
+pub fn deserialize_proof_from_bytes_with_limits(data: Vec<u8>, common_data: CommonCircuitData<F, D>, limits: &Limits) -> Result<ProofWithPublicInputs<F, C, 2>, anyhow::Error> {
+    limits::check_proof_bytes(&data, limits)?;
     match ProofWithPublicInputs::from_bytes(data, &common_data) {
         Ok(proof) => Ok(proof),
         Err(e) => {
@@ -146,9 +332,27 @@ pub fn deserialize_proof_from_bytes(data: Vec<u8>, common_data: CommonCircuitDat
 }
 
 pub fn deserialize_common_from_bytes(data: Vec<u8>) -> Result<CommonCircuitData<F, D>, anyhow::Error> {
-    match CommonCircuitData::from_bytes(data,&DefaultGateSerializer) {
-        Ok(common) => Ok(common),
-        Err(e) => {
+    deserialize_common_from_bytes_with_limits(data, &Limits::default())
+}
+
+/// Deserializes common circuit data after checking it against `limits`
+pub fn deserialize_common_from_bytes_with_limits(data: Vec<u8>, limits: &Limits) -> Result<CommonCircuitData<F, D>, anyhow::Error> {
+    deserialize_common_from_bytes_with_serializer(data, limits, &DefaultGateSerializer)
+}
+
+/// Deserializes common circuit data written with a serializer that knows its custom gates
+pub fn deserialize_common_from_bytes_with_serializer(
+    data: Vec<u8>,
+    limits: &Limits,
+    serializer: &dyn plonky2::util::serialization::GateSerializer<F, D>,
+) -> Result<CommonCircuitData<F, D>, anyhow::Error> {
+    limits::check_common_bytes(&data, limits)?;
+    let checked = limits::CheckedGateSerializer::new(serializer);
+    let common = CommonCircuitData::from_bytes(data, &checked);
+    match (common, checked.into_error()) {
+        (Ok(common), _) => Ok(common),
+        (Err(_), Some(e)) => Err(e.context("Failed to deserialize common")),
+        (Err(e), None) => {
             // Log more details about the error to aid debugging
             eprintln!("Deserialization common failed with error: {:?}", e);
             Err(anyhow::anyhow!("Failed to deserialize common: {:?}", e))
@@ -156,7 +360,56 @@ pub fn deserialize_common_from_bytes(data: Vec<u8>) -> Result<CommonCircuitData<
     }
 }
 
+/// Hashes arbitrary bytes with Poseidon, packing them four bytes per field element
+pub fn hash_bytes(data: &[u8]) -> HashOut<F> {
+    let elements: Vec<F> = std::iter::once(F::from_canonical_usize(data.len()))
+        .chain(data.chunks(4).map(|chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            F::from_canonical_u32(u32::from_le_bytes(word))
+        }))
+        .collect();
+    PoseidonHash::hash_no_pad(&elements)
+}
+
+/// Identifies a circuit by its serialized vk and the ABI its public inputs follow, so that the
+/// same vk under another ABI is another circuit. With the default ABI, the id is the hash of
+/// the vk alone, as the history circuit computes it.
+pub fn circuit_id(vk: &[u8], abi: &abi::Abi) -> HashOut<F> {
+    circuit_id_from_vk_hash(hash_bytes(vk), abi)
+}
+
+/// Like `circuit_id`, from the hash of the vk
+pub fn circuit_id_from_vk_hash(vk_id: HashOut<F>, abi: &abi::Abi) -> HashOut<F> {
+    if *abi == abi::Abi::default() {
+        return vk_id;
+    }
+    PoseidonHash::two_to_one(vk_id, PoseidonHash::hash_no_pad(&abi.to_elements()))
+}
+
+/// Encodes a digest as a lowercase hex string
+pub fn digest_to_hex(digest: &HashOut<F>) -> String {
+    digest.to_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a digest previously encoded with `digest_to_hex`
+pub fn digest_from_hex(hex: &str) -> Result<HashOut<F>, anyhow::Error> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        anyhow::bail!("Digest must be 64 hex characters");
+    }
+    let mut elements = [F::ZERO; 4];
+    for (i, element) in elements.iter_mut().enumerate() {
+        let word = u64::from_str_radix(&hex[i * 16..(i + 1) * 16], 16)?.swap_bytes();
+        if word >= F::ORDER {
+            anyhow::bail!("Digest element {} is not a canonical field element", i);
+        }
+        *element = F::from_canonical_u64(word);
+    }
+    Ok(HashOut { elements })
+}
+
 /// Example usage of the general ZKP library
+#[cfg(feature = "prover")]
 #[test]
 fn general_zkp_example() -> Result<(), anyhow::Error> {
     // Define the circuit configuration
@@ -182,3 +435,141 @@ fn general_zkp_example() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+/// Named public outputs are computed by the circuit and returned with the proof
+#[cfg(feature = "prover")]
+#[test]
+fn public_outputs_example() -> Result<(), anyhow::Error> {
+    let config: CircuitConfig = CircuitConfig::standard_recursion_config();
+
+    let zk_circuit = ZKPCircuit::with_outputs(config, 2, |builder, targets, outputs| {
+        let sum: Target = builder.add(targets[0], targets[1]);
+        let digest = builder.hash_n_to_hash_no_pad::<PoseidonHash>(targets.clone());
+        let is_equal = builder.is_equal(targets[0], targets[1]);
+        outputs.field(builder, "sum", sum);
+        outputs.hash(builder, "digest", digest);
+        outputs.bool(builder, "is_equal", is_equal);
+    });
+
+    // The prover learns the outputs without computing them natively
+    let (proof, outputs) = zk_circuit.prove_with_outputs(vec![3, 5])?;
+    assert_eq!(outputs.get_u64("sum")?, 8);
+    assert_eq!(outputs.get_hash("digest")?, PoseidonHash::hash_no_pad(&[F::from_canonical_u64(3), F::from_canonical_u64(5)]));
+    assert!(!outputs.get_bool("is_equal")?);
+    assert!(outputs.get_u64("digest").is_err());
+
+    // The verifier gets the same values back
+    assert_eq!(zk_circuit.verify_outputs(&proof)?, outputs);
+
+    Ok(())
+}
+
+/// A wrong witness is reported by the label and location of the constraint it breaks
+#[cfg(feature = "prover")]
+#[test]
+fn constraint_diagnostics_example() -> Result<(), anyhow::Error> {
+    let config: CircuitConfig = CircuitConfig::standard_recursion_config();
+
+    let zk_circuit = ZKPCircuit::with_checks(config, 2, |builder, targets, _, checks| {
+        let one = builder.one();
+        let next: Target = builder.add(targets[0], one);
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+        checks.connect(builder, next, targets[1], "next = current + 1");
+        checks.assert_bool(builder, plonky2::iop::target::BoolTarget::new_unsafe(targets[0]), "current is a bit");
+    });
+
+    assert!(zk_circuit.diagnose(vec![0, 1])?.is_satisfied());
+
+    let diagnosis = zk_circuit.diagnose(vec![1, 5])?;
+    assert_eq!(diagnosis.failures.len(), 1);
+    let failure = &diagnosis.failures[0];
+    let check = failure.check.as_ref().expect("failure should be attributed to a labelled check");
+    assert_eq!(check.label, "next = current + 1");
+    assert_eq!(check.location.file(), file!());
+    assert_eq!((failure.actual, failure.expected), (2, 5));
+
+    let report = zk_circuit.prove_with_diagnostics(vec![1, 5]).unwrap_err().to_string();
+    assert!(report.contains("next = current + 1"), "{}", report);
+
+    let diagnosis = zk_circuit.diagnose(vec![2, 3])?;
+    assert!(diagnosis.failures.iter().any(|f| f.check.as_ref().is_some_and(|c| c.label == "current is a bit")), "{}", diagnosis);
+
+    Ok(())
+}
+
+/// Witnesses are checked natively, covering both copy and gate constraints
+#[cfg(feature = "prover")]
+#[test]
+fn check_witness_example() -> Result<(), anyhow::Error> {
+    let config: CircuitConfig = CircuitConfig::standard_recursion_config();
+
+    // x fits in 8 bits and y = x + 1
+    let zk_circuit = ZKPCircuit::new(config, 2, |builder, targets| {
+        builder.split_le(targets[0], 8);
+        let one = builder.one();
+        let next = builder.add(targets[0], one);
+        builder.connect(next, targets[1]);
+    });
+
+    zk_circuit.check_witness(vec![200, 201])?;
+
+    // A wrong y breaks a copy constraint
+    let diagnosis = zk_circuit.diagnose(vec![200, 202])?;
+    assert_eq!(diagnosis.failures.len(), 1);
+    assert!(zk_circuit.check_witness(vec![200, 202]).is_err());
+
+    // An x too large for 8 bits sets a limb that must be zero, which also breaks the BaseSumGate's sum
+    let diagnosis = zk_circuit.diagnose(vec![300, 301])?;
+    assert_eq!(diagnosis.failures.len(), 1);
+    assert!(diagnosis.gate_failures.iter().any(|f| f.gate.starts_with("BaseSumGate")), "{}", diagnosis);
+    assert!(zk_circuit.check_witness(vec![300, 301]).is_err());
+
+    // Poseidon's degree splits the gates over several selector groups
+    let hashing = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, |builder, targets| {
+        let digest = builder.hash_n_to_hash_no_pad::<PoseidonHash>(vec![targets[0]]);
+        builder.connect(digest.elements[0], targets[1]);
+    });
+    let selectors = diagnostics::selectors(&hashing.circuit_data.common);
+    assert!(selectors.groups.len() > 1);
+    assert_eq!(selectors.groups.len(), hashing.circuit_data.common.selectors_info.num_selectors());
+    let digest = PoseidonHash::hash_no_pad(&[F::from_canonical_u64(7)]).elements[0];
+    let digest = plonky2::field::types::PrimeField64::to_canonical_u64(&digest);
+    hashing.check_witness(vec![7, digest])?;
+    assert!(hashing.check_witness(vec![7, digest + 1]).is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "prover")]
+#[test]
+fn circuit_stats_example() -> Result<(), anyhow::Error> {
+    let counter = |hashes: usize| {
+        ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, move |builder, targets| {
+            let one = builder.one();
+            let s = builder.add(targets[0], one);
+            builder.register_public_input(targets[0]);
+            builder.register_public_input(targets[1]);
+            builder.connect(s, targets[1]);
+            for _ in 0..hashes {
+                let digest = builder.hash_n_to_hash_no_pad::<PoseidonHash>(targets.clone());
+                builder.register_public_inputs(&digest.elements);
+            }
+        })
+    };
+    let small = counter(0);
+    let stats = small.measure(vec![3, 4])?;
+    assert_eq!(stats.gates.values().sum::<usize>(), stats.num_rows());
+    assert_eq!(stats.num_public_inputs, 2);
+    assert_eq!(Some(stats.estimated_proof_size), stats.timings.map(|t| t.proof_size));
+
+    let large = counter(3);
+    let diff = large.stats().diff(&small.stats());
+    assert!(small.stats().diff(&small.stats()).is_empty());
+    assert_eq!(diff.get("num_public_inputs").map(|c| (c.before, c.after)), Some((2, 14)));
+    assert!(diff.changes.iter().any(|c| c.metric.starts_with("gates[PoseidonGate") && c.after > c.before), "{}", diff);
+
+    let measured = large.measure(vec![3, 4])?;
+    assert_eq!(Some(measured.estimated_proof_size), measured.timings.map(|t| t.proof_size));
+    Ok(())
+}
@@ -0,0 +1,342 @@
+use std::cell::RefCell;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use plonky2::gates::arithmetic_base::ArithmeticGate;
+use plonky2::gates::arithmetic_extension::ArithmeticExtensionGate;
+use plonky2::gates::gate::GateRef;
+use plonky2::gates::lookup::LookupGate;
+use plonky2::gates::lookup_table::LookupTableGate;
+use plonky2::util::serialization::{Buffer, GateSerializer, IoError, IoResult};
+
+use crate::{CommonCircuitData, GoldilocksField};
+
+type F = GoldilocksField;
+const D: usize = 2;
+
+/// Bounds enforced on untrusted bytes before they reach plonky2's deserializers.
+///
+/// plonky2 trusts the length prefixes and shape parameters it reads, preallocating
+/// vectors and Merkle caps from them, so every such value is checked here first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest serialized `Transaction`
+    pub max_transaction_size: usize,
+    /// Largest serialized verifier key
+    pub max_vk_size: usize,
+    /// Largest serialized `CommonCircuitData`
+    pub max_common_size: usize,
+    /// Largest serialized proof with its public inputs
+    pub max_proof_size: usize,
+    /// Largest element count any length prefix may claim
+    pub max_vec_len: usize,
+    /// Largest `degree_bits + rate_bits`, bounding the size of the low-degree extension
+    pub max_degree_bits: usize,
+    /// Largest number of wires per row
+    pub max_wires: usize,
+    /// Largest number of gate types in a circuit
+    pub max_gates: usize,
+    /// Largest number of public inputs
+    pub max_public_inputs: usize,
+    /// Largest height of a Merkle cap
+    pub max_cap_height: usize,
+    /// Largest number of FRI query rounds
+    pub max_query_rounds: usize,
+    /// Fewest bits of security the circuit config may claim, and its FRI parameters conjecture:
+    /// `rate_bits * num_query_rounds + proof_of_work_bits`
+    pub min_security_bits: usize,
+    /// Largest number of transactions in a bundle
+    pub max_bundle_len: usize,
+    /// Most receipts of rejected transactions the ledger keeps, dropping the oldest first
+    pub max_rejected_receipts: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_transaction_size: 4 * 1024 * 1024,
+            max_vk_size: 64 * 1024,
+            max_common_size: 1024 * 1024,
+            max_proof_size: 2 * 1024 * 1024,
+            max_vec_len: 1 << 16,
+            max_degree_bits: 24,
+            max_wires: 256,
+            max_gates: 64,
+            max_public_inputs: 1024,
+            max_cap_height: 8,
+            max_query_rounds: 128,
+            min_security_bits: 100,
+            max_bundle_len: 16,
+            max_rejected_receipts: 4096,
+        }
+    }
+}
+
+/// Size of a serialized hash digest
+const HASH_SIZE: usize = 32;
+
+/// Reads the little-endian primitives of plonky2's serialization format without allocating
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], anyhow::Error> {
+        if n > self.remaining() {
+            anyhow::bail!("Unexpected end of data at byte {}", self.pos);
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), anyhow::Error> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, anyhow::Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => anyhow::bail!("Invalid bool {} at byte {}", v, self.pos - 1),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, anyhow::Error> {
+        let value = u64::from_le_bytes(self.take(8)?.try_into()?);
+        usize::try_from(value).map_err(|_| anyhow::anyhow!("Value {} does not fit in usize", value))
+    }
+
+    /// Reads a value that must not exceed `max`
+    fn bounded(&mut self, what: &str, max: usize) -> Result<usize, anyhow::Error> {
+        let value = self.usize()?;
+        if value > max {
+            anyhow::bail!("{} is {}, limit is {}", what, value, max);
+        }
+        Ok(value)
+    }
+
+    /// Reads a length prefix for `elem_size`-byte elements, checking it against the
+    /// limit and against the bytes actually left in the input
+    fn len(&mut self, what: &str, elem_size: usize, limits: &Limits) -> Result<usize, anyhow::Error> {
+        let len = self.bounded(what, limits.max_vec_len)?;
+        if len.saturating_mul(elem_size) > self.remaining() {
+            anyhow::bail!("{} claims {} elements but only {} bytes remain", what, len, self.remaining());
+        }
+        Ok(len)
+    }
+
+    fn usize_vec(&mut self, what: &str, limits: &Limits) -> Result<Vec<usize>, anyhow::Error> {
+        let len = self.len(what, 8, limits)?;
+        (0..len).map(|_| self.usize()).collect()
+    }
+}
+
+fn check_size(what: &str, len: usize, max: usize) -> Result<(), anyhow::Error> {
+    if len > max {
+        anyhow::bail!("{} is {} bytes, limit is {}", what, len, max);
+    }
+    Ok(())
+}
+
+/// Checks a serialized verifier key: its size, and that its cap height is bounded
+/// and matches the length of the data
+pub fn check_vk_bytes(data: &[u8], limits: &Limits) -> Result<(), anyhow::Error> {
+    check_size("Verifier key", data.len(), limits.max_vk_size)?;
+    let mut cursor = Cursor::new(data);
+    let cap_height = cursor.bounded("Verifier key cap height", limits.max_cap_height)?;
+    let expected = 1usize
+        .checked_shl(cap_height as u32)
+        .and_then(|cap_len| (cap_len + 1).checked_mul(HASH_SIZE))
+        .ok_or_else(|| anyhow::anyhow!("Verifier key cap height {} is too large", cap_height))?;
+    if cursor.remaining() != expected {
+        anyhow::bail!("Verifier key with cap height {} must have {} bytes after the height, got: {}", cap_height, expected, cursor.remaining());
+    }
+    Ok(())
+}
+
+/// Checks the fixed part of serialized `CommonCircuitData`, up to and including the
+/// number of gates, against the limits
+pub fn check_common_bytes(data: &[u8], limits: &Limits) -> Result<(), anyhow::Error> {
+    check_size("Common circuit data", data.len(), limits.max_common_size)?;
+    let mut c = Cursor::new(data);
+
+    // CircuitConfig
+    c.bounded("Number of wires", limits.max_wires)?;
+    c.bounded("Number of routed wires", limits.max_wires)?;
+    c.bounded("Number of constants", limits.max_vec_len)?;
+    let security_bits = c.usize()?;
+    if security_bits < limits.min_security_bits {
+        anyhow::bail!("Security bits {} are below the minimum {}", security_bits, limits.min_security_bits);
+    }
+    c.bounded("Number of challenges", limits.max_vec_len)?;
+    c.bounded("Max quotient degree factor", limits.max_vec_len)?;
+    c.bool()?; // use_base_arithmetic_gate
+    c.bool()?; // zero_knowledge
+    check_fri_config(&mut c, limits)?;
+
+    // FriParams
+    let rate_bits = check_fri_config(&mut c, limits)?;
+    let arity_bits = c.usize_vec("Reduction arity bits", limits)?;
+    let degree_bits = c.usize()?;
+    if degree_bits.saturating_add(rate_bits) > limits.max_degree_bits {
+        anyhow::bail!("Degree bits {} plus rate bits {} exceed limit {}", degree_bits, rate_bits, limits.max_degree_bits);
+    }
+    if arity_bits.iter().try_fold(0usize, |acc, &b| acc.checked_add(b)).is_none_or(|total| total > degree_bits) {
+        anyhow::bail!("Reduction arity bits {:?} exceed degree bits {}", arity_bits, degree_bits);
+    }
+    c.bool()?; // hiding
+
+    // SelectorsInfo
+    c.usize_vec("Selector indices", limits)?;
+    let groups = c.len("Selector groups", 16, limits)?;
+    c.skip(groups * 16)?;
+
+    c.bounded("Quotient degree factor", limits.max_vec_len)?;
+    c.bounded("Number of gate constraints", limits.max_vec_len)?;
+    c.bounded("Number of constants", limits.max_vec_len)?;
+    c.bounded("Number of public inputs", limits.max_public_inputs)?;
+    let k_is = c.len("k_is", 8, limits)?;
+    c.skip(k_is * 8)?;
+    c.bounded("Number of partial products", limits.max_vec_len)?;
+    c.bounded("Number of lookup polynomials", limits.max_vec_len)?;
+    c.bounded("Number of lookup selectors", limits.max_vec_len)?;
+
+    let luts = c.len("Lookup tables", 8, limits)?;
+    for _ in 0..luts {
+        let entries = c.len("Lookup table entries", 4, limits)?;
+        c.skip(entries * 4)?;
+    }
+
+    let gates = c.bounded("Number of gates", limits.max_gates)?;
+    // Every gate is at least its u32 tag
+    if gates.saturating_mul(4) > c.remaining() {
+        anyhow::bail!("Number of gates {} exceeds the {} bytes remaining", gates, c.remaining());
+    }
+    Ok(())
+}
+
+/// Reads gates with another serializer, checking each one against the circuit before it is used.
+///
+/// plonky2's gates index the lookup tables with the index they read, panicking when it is out of
+/// range, and size their wires and constraints by the counts they read. A panic while reading is
+/// turned into an error, and the first error is kept for `into_error`.
+pub(crate) struct CheckedGateSerializer<'a> {
+    inner: &'a dyn GateSerializer<F, D>,
+    error: RefCell<Option<anyhow::Error>>,
+}
+
+impl<'a> CheckedGateSerializer<'a> {
+    pub(crate) fn new(inner: &'a dyn GateSerializer<F, D>) -> Self {
+        Self { inner, error: RefCell::new(None) }
+    }
+
+    /// Why a gate was rejected, if one was
+    pub(crate) fn into_error(self) -> Option<anyhow::Error> {
+        self.error.into_inner()
+    }
+
+    fn reject(&self, error: anyhow::Error) -> IoError {
+        self.error.borrow_mut().get_or_insert(error);
+        IoError
+    }
+}
+
+impl GateSerializer<F, D> for CheckedGateSerializer<'_> {
+    fn read_gate(&self, buf: &mut Buffer, common_data: &CommonCircuitData<F, D>) -> IoResult<GateRef<F, D>> {
+        let gate = catch_unwind(AssertUnwindSafe(|| self.inner.read_gate(buf, common_data)))
+            .map_err(|_| self.reject(anyhow::anyhow!("A gate cannot be read")))??;
+        check_gate(&gate, common_data).map_err(|e| self.reject(e))?;
+        Ok(gate)
+    }
+
+    fn write_gate(&self, buf: &mut Vec<u8>, gate: &GateRef<F, D>, common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        self.inner.write_gate(buf, gate, common_data)
+    }
+}
+
+/// Checks that a gate fits the wires and constraints of the circuit. The counts of plonky2's
+/// gates are read directly, as their wire counts multiply them without overflow checks.
+fn check_gate(gate: &GateRef<F, D>, common_data: &CommonCircuitData<F, D>) -> Result<(), anyhow::Error> {
+    let any = gate.0.as_any();
+    let wires = if let Some(gate) = any.downcast_ref::<ArithmeticGate>() {
+        gate.num_ops.checked_mul(4)
+    } else if let Some(gate) = any.downcast_ref::<ArithmeticExtensionGate<D>>() {
+        gate.num_ops.checked_mul(4 * D)
+    } else if let Some(gate) = any.downcast_ref::<LookupGate>() {
+        gate.num_slots.checked_mul(2)
+    } else if let Some(gate) = any.downcast_ref::<LookupTableGate>() {
+        gate.num_slots.checked_mul(3)
+    } else {
+        catch_unwind(AssertUnwindSafe(|| gate.0.num_wires())).ok()
+    };
+    let (max_wires, max_constraints) = (common_data.config.num_wires, common_data.num_gate_constraints);
+    match wires {
+        Some(wires) if wires <= max_wires => {}
+        _ => anyhow::bail!("A gate uses more than the {} wires of the circuit", max_wires),
+    }
+    match catch_unwind(AssertUnwindSafe(|| (gate.0.num_constraints(), gate.0.num_constants()))) {
+        Ok((constraints, _)) if constraints > max_constraints => {
+            anyhow::bail!("A gate has {} constraints, the circuit at most {}", constraints, max_constraints)
+        }
+        Ok((_, constants)) if constants > common_data.num_constants => {
+            anyhow::bail!("A gate uses {} constants, the circuit has {}", constants, common_data.num_constants)
+        }
+        Ok(_) => Ok(()),
+        Err(_) => anyhow::bail!("A gate has an invalid shape"),
+    }
+}
+
+/// Checks a `FriConfig`, returning its rate bits
+fn check_fri_config(c: &mut Cursor, limits: &Limits) -> Result<usize, anyhow::Error> {
+    let rate_bits = c.bounded("Rate bits", limits.max_degree_bits)?;
+    c.bounded("Cap height", limits.max_cap_height)?;
+    let query_rounds = c.bounded("Number of query rounds", limits.max_query_rounds)?;
+    let pow_bits = c.u32()?;
+    // Both factors are bounded, so the product cannot overflow
+    let security = rate_bits * query_rounds + pow_bits as usize;
+    if security < limits.min_security_bits {
+        anyhow::bail!(
+            "FRI parameters give {} bits of security ({} rate bits, {} query rounds, {} proof of work bits), minimum is {}",
+            security,
+            rate_bits,
+            query_rounds,
+            pow_bits,
+            limits.min_security_bits
+        );
+    }
+    match c.u8()? {
+        0 => {
+            c.usize_vec("Fixed reduction arities", limits)?;
+        }
+        1 => c.skip(16)?,
+        2 => match c.u8()? {
+            0 => {}
+            1 => c.skip(8)?,
+            v => anyhow::bail!("Invalid MinSize tag {}", v),
+        },
+        v => anyhow::bail!("Invalid reduction strategy {}", v),
+    }
+    Ok(rate_bits)
+}
+
+/// Checks the size of a serialized proof; its shape is bounded by the common data it is read with
+pub fn check_proof_bytes(data: &[u8], limits: &Limits) -> Result<(), anyhow::Error> {
+    check_size("Proof", data.len(), limits.max_proof_size)
+}
@@ -1,66 +1,60 @@
-use plonky2::plonk::circuit_data::VerifierCircuitData;
-use std::collections::hash_map::DefaultHasher;
-use zk::*;
-use zk::txn::Transaction;
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
-use plonky2::util::serialization::DefaultGateSerializer;
-use plonky2::field::goldilocks_field::GoldilocksField as F;
-use plonky2::field::types::{Field, PrimeField64};
-
-fn hash<T: Hash>(t: &T) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    t.hash(&mut hasher);
-    hasher.finish()
-}
+use std::path::Path;
 
+use zk::*;
+use zk::compat::{GoldenDigests, GOLDEN_DIGESTS};
+use zk::counter::counter_zkp_circuit;
+use zk::db::Ledger;
+use zk::node::Node;
+use zk::snapshot::Snapshot;
+
+/// Default address of the local node
+const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+/// Usage: `zk [addr] [snapshot path] [expected commitment]`
+///
+/// When a snapshot path is given the node starts from that snapshot if it exists,
+/// and writes a fresh snapshot there when it shuts down. An existing snapshot is only
+/// restored against the expected commitment, as printed when it was written, since the
+/// snapshot's own commitment proves nothing about where it came from.
 fn main() -> Result<(), anyhow::Error>  {
-    let config: CircuitConfig = CircuitConfig::standard_recursion_config();
+    let args: Vec<String> = std::env::args().collect();
+    let addr = args.get(1).cloned().unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let snapshot_path = args.get(2).map(Path::new);
 
     // Initialize a ZKP circuit with 2 inputs
-    let zk_circuit_1: ZKPCircuit = ZKPCircuit::new(config.clone(), 2, |builder, targets| {
-        let one = builder.one();
-        let s: Target = builder.add(targets[0], one);
-        builder.register_public_input(targets[0]);
-        builder.register_public_input(targets[1]);
-        builder.connect(s, targets[1]);
-    });
-
-    let mut m = HashMap::new();
-    m.insert(hash(&zk_circuit_1.get_vk()), 0);
-
-    let mut txns: Vec<Vec<u8>> = Vec::new();
-
-    for i in 0..10 {
-        let proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = zk_circuit_1.prove(vec![i, i+1]).expect("REASON");
-
-        let tx = Transaction{
-            vk: zk_circuit_1.get_vk(),
-            proof_data: proof.to_bytes(),
-            common: zk_circuit_1.get_common_circuit_data()
-        };
-
-        let tx_data = tx.serialize();
-
-        txns.push(tx_data);
+    let zk_circuit_1: ZKPCircuit = counter_zkp_circuit();
+
+    let mut ledger = match snapshot_path {
+        Some(path) if path.exists() => {
+            let snapshot = Snapshot::read_from(path)?;
+            let Some(hex) = args.get(3) else {
+                anyhow::bail!("Refusing to restore {} without its expected commitment", path.display());
+            };
+            let expected = digest_from_hex(hex)?;
+            println!("Restoring snapshot at height {} with commitment {}", snapshot.height, digest_to_hex(&expected));
+            Ledger::from_snapshot(snapshot, &expected)?
+        }
+        _ => Ledger::new(),
+    };
+    // Carry state stored under a retired vk of a library circuit over to its current vk
+    let golden = GoldenDigests::from_json(GOLDEN_DIGESTS)?;
+    for migration in golden.migrate_ledger(&mut ledger)? {
+        println!("{}", migration);
     }
+    let vk_id = ledger.register_with_abi(&zk_circuit_1.registration(), 0, zk_circuit_1.abi().clone())?;
 
-    for txn in txns {
-        let tx: Transaction = Transaction::deserialize(&txn[..]);
+    let node = Node::bind(&addr, ledger)?;
+    println!("Node listening on {}", node.local_addr()?);
+    println!("Counter circuit registered as {}", digest_to_hex(&vk_id));
 
-        let common = deserialize_common_from_bytes(tx.common.clone())?;
+    // Serve until a client sends `POST /shutdown`
+    let ledger = node.run();
 
-        let proof_data: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = deserialize_proof_from_bytes(tx.proof_data, common.clone())?;
-
-        let vk = deserialize_vk_from_bytes(tx.vk.clone())?;
-
-        if m.get(&hash(&tx.vk)) != Some(&(proof_data.public_inputs[0].to_canonical_u64() as i32)) || verify_circuit_data(proof_data.clone(), vk, common).is_err() {
-            continue;
-        } {
-            m.insert(hash(&tx.vk), proof_data.clone().public_inputs[1].to_canonical_u64() as i32);
-        }
+    if let Some(path) = snapshot_path {
+        ledger.snapshot().write_to(path)?;
+        println!("Snapshot written at height {} with commitment {}", ledger.height(), digest_to_hex(&ledger.commitment()));
     }
 
-    println!("Map: {:?}", m);
+    println!("Node stopped");
     Ok(())  // Return Ok when the function completes successfully
 }
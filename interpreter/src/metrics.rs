@@ -0,0 +1,209 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Upper bounds of the proof size buckets, in bytes
+const SIZE_BUCKETS: &[f64] = &[8192.0, 16384.0, 32768.0, 65536.0, 131072.0, 262144.0, 524288.0, 1048576.0];
+
+/// Why the ledger rejected a transaction, kept coarse so the metric has few label values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The transaction bytes did not decode or exceeded the limits
+    Decode,
+    UnknownCircuit,
+    UnknownGateSerializer,
+    /// The common data differs from the one the circuit was registered with
+    CommonData,
+    /// The vk, proof or common data did not decode or exceeded the limits
+    Malformed,
+    /// Too few public inputs to carry a state transition
+    PublicInputs,
+    StaleState,
+    InvalidProof,
+    /// Another transaction of the same bundle was rejected
+    Bundle,
+    /// The transaction could not be proven into the ledger's history
+    History,
+}
+
+impl RejectReason {
+    pub const ALL: [RejectReason; 10] = [
+        RejectReason::Decode,
+        RejectReason::UnknownCircuit,
+        RejectReason::UnknownGateSerializer,
+        RejectReason::CommonData,
+        RejectReason::Malformed,
+        RejectReason::PublicInputs,
+        RejectReason::StaleState,
+        RejectReason::InvalidProof,
+        RejectReason::Bundle,
+        RejectReason::History,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RejectReason::Decode => "decode",
+            RejectReason::UnknownCircuit => "unknown_circuit",
+            RejectReason::UnknownGateSerializer => "unknown_gate_serializer",
+            RejectReason::CommonData => "common_data",
+            RejectReason::Malformed => "malformed",
+            RejectReason::PublicInputs => "public_inputs",
+            RejectReason::StaleState => "stale_state",
+            RejectReason::InvalidProof => "invalid_proof",
+            RejectReason::Bundle => "bundle",
+            RejectReason::History => "history",
+        }
+    }
+}
+
+/// A histogram with fixed bucket bounds, as Prometheus exposes them
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    /// Bits of the `f64` sum of observations
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(), count: AtomicU64::new(0), sum: AtomicU64::new(0) }
+    }
+
+    fn observe(&self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self.sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + value).to_bits()));
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, f64::from_bits(self.sum.load(Ordering::Relaxed)));
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+struct Registry {
+    accepted: AtomicU64,
+    rejected: [AtomicU64; RejectReason::ALL.len()],
+    pending: AtomicU64,
+    decode: Histogram,
+    deserialize: Histogram,
+    verify: Histogram,
+    apply: Histogram,
+    proof_size: Histogram,
+}
+
+/// Counters, gauges and histograms of the verification loop, rendered in the Prometheus
+/// text format. Clones share the same values.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            registry: Arc::new(Registry {
+                accepted: AtomicU64::new(0),
+                rejected: Default::default(),
+                pending: AtomicU64::new(0),
+                decode: Histogram::new(LATENCY_BUCKETS),
+                deserialize: Histogram::new(LATENCY_BUCKETS),
+                verify: Histogram::new(LATENCY_BUCKETS),
+                apply: Histogram::new(LATENCY_BUCKETS),
+                proof_size: Histogram::new(SIZE_BUCKETS),
+            }),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn accepted(&self) {
+        self.registry.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self, reason: RejectReason) {
+        self.registry.rejected[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn accepted_count(&self) -> u64 {
+        self.registry.accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_count(&self, reason: RejectReason) -> u64 {
+        self.registry.rejected[reason as usize].load(Ordering::Relaxed)
+    }
+
+    /// Sets the number of transactions accepted into the block being built
+    pub fn set_pending(&self, count: usize) {
+        self.registry.pending.store(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn pending_count(&self) -> u64 {
+        self.registry.pending.load(Ordering::Relaxed)
+    }
+
+    /// Time spent decoding a submitted transaction from its bytes
+    pub fn observe_decode(&self, elapsed: Duration) {
+        self.registry.decode.observe(elapsed.as_secs_f64());
+    }
+
+    /// Time spent decoding the vk, proof and common data of a transaction
+    pub fn observe_deserialize(&self, elapsed: Duration) {
+        self.registry.deserialize.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_verify(&self, elapsed: Duration) {
+        self.registry.verify.observe(elapsed.as_secs_f64());
+    }
+
+    /// Time spent applying a transaction end to end, whether or not it was accepted
+    pub fn observe_apply(&self, elapsed: Duration) {
+        self.registry.apply.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_proof_size(&self, bytes: usize) {
+        self.registry.proof_size.observe(bytes as f64);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let registry = &self.registry;
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP zk_transactions_accepted_total Transactions applied to the ledger");
+        let _ = writeln!(out, "# TYPE zk_transactions_accepted_total counter");
+        let _ = writeln!(out, "zk_transactions_accepted_total {}", registry.accepted.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP zk_transactions_rejected_total Transactions rejected by the ledger, by reason");
+        let _ = writeln!(out, "# TYPE zk_transactions_rejected_total counter");
+        for reason in RejectReason::ALL {
+            let _ = writeln!(out, "zk_transactions_rejected_total{{reason=\"{}\"}} {}", reason.label(), self.rejected_count(reason));
+        }
+        let _ = writeln!(out, "# HELP zk_pending_transactions Transactions accepted into the block being built");
+        let _ = writeln!(out, "# TYPE zk_pending_transactions gauge");
+        let _ = writeln!(out, "zk_pending_transactions {}", self.pending_count());
+        registry.decode.render(&mut out, "zk_decode_seconds", "Time spent decoding submitted transactions");
+        registry.deserialize.render(&mut out, "zk_deserialize_seconds", "Time spent decoding vks, proofs and common data");
+        registry.verify.render(&mut out, "zk_verify_seconds", "Time spent verifying proofs");
+        registry.apply.render(&mut out, "zk_apply_seconds", "Time spent applying transactions");
+        registry.proof_size.render(&mut out, "zk_proof_size_bytes", "Size of submitted proofs");
+        out
+    }
+}
@@ -0,0 +1,517 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::abi::Abi;
+use crate::db::{BundleReceipt, Ledger, Receipt};
+use crate::snapshot::Snapshot;
+use crate::txn::{Bundle, Registration, Transaction};
+use crate::state_tree::StateProof;
+use crate::{digest_from_hex, digest_to_hex, GoldilocksField, HashOut};
+
+/// How long the node waits on a silent client before dropping it
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client may take to send a whole request, however steadily it trickles in
+const REQUEST_DEADLINE: Duration = Duration::from_secs(10);
+
+/// The longest request, status or header line accepted, in bytes
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// The most header lines accepted in a request or response
+const MAX_HEADERS: usize = 64;
+
+/// How many connections a node serves at once; further ones are turned away until one finishes
+pub const MAX_CONNECTIONS: usize = 16;
+
+/// The state of a single circuit as reported by `GET /state/<vk_id>`, with a proof of
+/// it under the node's state root
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateResponse {
+    pub vk_id: String,
+    pub value: u64,
+    pub state_root: String,
+    pub proof: StateProof,
+}
+
+impl StateResponse {
+    fn new(ledger: &Ledger, vk_id: &HashOut<GoldilocksField>, value: u64) -> Self {
+        Self {
+            vk_id: digest_to_hex(vk_id),
+            value,
+            state_root: digest_to_hex(&ledger.state_root()),
+            proof: ledger.prove_state(vk_id),
+        }
+    }
+
+    /// Checks that the proof is for this circuit and value and leads to `state_root`
+    pub fn verify(&self, state_root: &HashOut<GoldilocksField>) -> Result<(), anyhow::Error> {
+        if self.proof.vk_id != digest_from_hex(&self.vk_id)? || self.proof.value != Some(self.value) {
+            anyhow::bail!("State proof does not match the reported state of {}", self.vk_id);
+        }
+        self.proof.verify(state_root)
+    }
+}
+
+/// A local node serving a ledger over HTTP.
+///
+/// Routes:
+/// - `POST /transactions` with a serialized `Transaction` body, answered with its receipt
+/// - `POST /bundles` with a serialized `Bundle` body, applied atomically and answered with its receipts
+/// - `POST /circuits` with a serialized `Registration` body, registering the circuit with state 0
+///   if it is new; a registration that conflicts with the ledger is answered with 409, one the
+///   ledger's history fails to prove is a server error
+/// - `POST /circuits/<abi>` likewise, with the public input roles of the circuit as in
+///   `parameter,new_state,precondition`; the same vk under another ABI is another circuit
+/// - `GET /state/<vk_id>` returning the current state of a circuit and its inclusion proof
+/// - `GET /state_root` returning the root of the state tree
+/// - `GET /receipts/<tx_hash>` returning the receipt of a transaction
+/// - `GET /snapshot` returning an encoded `Snapshot` of the ledger
+/// - `GET /history` returning the ledger's history proof, which `HistoryCircuit::proof_from_bytes` decodes
+/// - `GET /metrics` returning the ledger's `Metrics` in the Prometheus text format
+/// - `POST /shutdown` stopping the node once the request has been answered
+///
+/// Each connection is served on a thread of its own, up to `MAX_CONNECTIONS`, so a slow client
+/// only holds up itself; the ledger is locked only once a request has been read in full.
+pub struct Node {
+    listener: TcpListener,
+    ledger: Arc<Mutex<Ledger>>,
+    max_body_size: usize,
+}
+
+/// A running node, used to find its address and shut it down
+pub struct NodeHandle {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) body: Vec<u8>,
+}
+
+pub(crate) struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn json<T: Serialize>(value: &T) -> Self {
+        Self { status: 200, content_type: "application/json", body: serde_json::to_vec(value).expect("Failed to encode response") }
+    }
+
+    fn text(body: String) -> Self {
+        Self { status: 200, content_type: "text/plain; version=0.0.4", body: body.into_bytes() }
+    }
+
+    fn bytes(body: Vec<u8>) -> Self {
+        Self { status: 200, content_type: "application/octet-stream", body }
+    }
+
+    pub(crate) fn error(status: u16, message: impl ToString) -> Self {
+        let body = serde_json::json!({ "error": message.to_string() });
+        Self { status, content_type: "application/json", body: body.to_string().into_bytes() }
+    }
+}
+
+impl Node {
+    /// Binds the node to a local address; use port 0 to pick a free port
+    pub fn bind(addr: impl ToSocketAddrs, ledger: Ledger) -> Result<Self, anyhow::Error> {
+        let listener = TcpListener::bind(addr)?;
+        // No route accepts a body larger than a transaction
+        let max_body_size = ledger.limits().max_transaction_size;
+        Ok(Self { listener, ledger: Arc::new(Mutex::new(ledger)), max_body_size })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, anyhow::Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Starts serving requests on a background thread
+    pub fn spawn(self) -> Result<NodeHandle, anyhow::Error> {
+        let addr = self.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let ledger = self.ledger.clone();
+        let flag = shutdown.clone();
+        let thread = std::thread::spawn(move || self.serve(&flag));
+        Ok(NodeHandle { addr, shutdown, thread, ledger })
+    }
+
+    /// Serves requests on the current thread until a shutdown is requested, then returns the ledger
+    pub fn run(self) -> Ledger {
+        self.serve(&AtomicBool::new(false));
+        into_ledger(self.ledger).expect("Ledger still in use")
+    }
+
+    fn serve(&self, shutdown: &AtomicBool) {
+        let active = AtomicUsize::new(0);
+        // Connections still being served are waited for before the node returns its ledger
+        std::thread::scope(|scope| {
+            for stream in self.listener.incoming() {
+                if shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                };
+                if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    if let Err(e) = write_response(&mut stream, Response::error(503, "Too many connections")) {
+                        eprintln!("Failed to handle request: {}", e);
+                    }
+                    continue;
+                }
+                let active = &active;
+                scope.spawn(move || {
+                    if let Err(e) = self.handle(stream, shutdown) {
+                        eprintln!("Failed to handle request: {}", e);
+                    }
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    if shutdown.load(Ordering::SeqCst) {
+                        // Wake the accept loop so it sees the shutdown
+                        let _ = self.local_addr().map(TcpStream::connect);
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle(&self, mut stream: TcpStream, shutdown: &AtomicBool) -> Result<(), anyhow::Error> {
+        let response = match read_request(&mut stream, self.max_body_size) {
+            Ok(request) => self.route(request, shutdown),
+            Err(e) => Response::error(400, e),
+        };
+        write_response(&mut stream, response)
+    }
+
+    fn route(&self, request: Request, shutdown: &AtomicBool) -> Response {
+        let mut ledger = self.ledger.lock().expect("Ledger lock poisoned");
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["transactions"]) => Response::json(&ledger.apply_bytes(&request.body)),
+            ("POST", ["bundles"]) => Response::json(&ledger.apply_bundle_bytes(&request.body)),
+            ("POST", ["circuits"]) => match Registration::deserialize_with_limits(&request.body, ledger.limits()) {
+                Ok(registration) => register(&mut ledger, &registration, Abi::default()),
+                Err(e) => Response::error(400, e),
+            },
+            ("POST", ["circuits", abi]) => match (abi.parse::<Abi>(), Registration::deserialize_with_limits(&request.body, ledger.limits())) {
+                (Ok(abi), Ok(registration)) => register(&mut ledger, &registration, abi),
+                (Err(e), _) | (_, Err(e)) => Response::error(400, e),
+            },
+            ("GET", ["state", vk_id]) => match digest_from_hex(vk_id) {
+                Ok(id) => match ledger.state(&id) {
+                    Some(value) => Response::json(&StateResponse::new(&ledger, &id, value)),
+                    None => Response::error(404, "Unknown circuit"),
+                },
+                Err(e) => Response::error(400, e),
+            },
+            ("GET", ["state_root"]) => Response::json(&digest_to_hex(&ledger.state_root())),
+            ("GET", ["receipts", tx_hash]) => match digest_from_hex(tx_hash) {
+                Ok(hash) => match ledger.receipt(&hash) {
+                    Some(receipt) => Response::json(receipt),
+                    None => Response::error(404, "Unknown transaction"),
+                },
+                Err(e) => Response::error(400, e),
+            },
+            ("GET", ["snapshot"]) => Response::bytes(ledger.snapshot().to_bytes()),
+            #[cfg(feature = "prover")]
+            ("GET", ["history"]) => match ledger.history().and_then(|history| history.proof()) {
+                Some(proof) => Response::bytes(proof.to_bytes()),
+                None => Response::error(404, "No history proof"),
+            },
+            ("GET", ["metrics"]) => Response::text(ledger.metrics().render()),
+            ("POST", ["shutdown"]) => {
+                shutdown.store(true, Ordering::SeqCst);
+                Response::json(&"shutting down")
+            }
+            _ => Response::error(404, "Not found"),
+        }
+    }
+}
+
+/// Registers a circuit with state 0: a registration that conflicts with the ledger is answered
+/// with 409, one that fails after passing the checks, in proving the history, with 500
+fn register(ledger: &mut Ledger, registration: &Registration, abi: Abi) -> Response {
+    if let Err(e) = ledger.check_registration(registration, &abi) {
+        return Response::error(409, e);
+    }
+    match ledger.register_with_abi(registration, 0, abi) {
+        Ok(id) => {
+            let value = ledger.state(&id).unwrap_or_default();
+            Response::json(&StateResponse::new(ledger, &id, value))
+        }
+        Err(e) => Response::error(500, e),
+    }
+}
+
+impl NodeHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops accepting connections, waits for the in-flight requests and returns the ledger
+    pub fn shutdown(self) -> Result<Ledger, anyhow::Error> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop; it may already have exited after a `POST /shutdown`
+        let _ = TcpStream::connect(self.addr);
+        self.thread.join().map_err(|_| anyhow::anyhow!("Node thread panicked"))?;
+        into_ledger(self.ledger)
+    }
+}
+
+fn into_ledger(ledger: Arc<Mutex<Ledger>>) -> Result<Ledger, anyhow::Error> {
+    let ledger = Arc::try_unwrap(ledger).map_err(|_| anyhow::anyhow!("Ledger still in use"))?;
+    Ok(ledger.into_inner().expect("Ledger lock poisoned"))
+}
+
+/// A blocking client for a local node
+pub struct Client {
+    addr: SocketAddr,
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    pub fn submit(&self, tx: &Transaction) -> Result<Receipt, anyhow::Error> {
+        self.submit_bytes(&tx.serialize())
+    }
+
+    pub fn submit_bytes(&self, data: &[u8]) -> Result<Receipt, anyhow::Error> {
+        let body = self.expect_ok(self.request("POST", "/transactions", data)?)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub fn submit_bundle(&self, bundle: &Bundle) -> Result<BundleReceipt, anyhow::Error> {
+        let body = self.expect_ok(self.request("POST", "/bundles", &bundle.serialize())?)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Registers a circuit by its vk and common data, returning its id
+    pub fn register(&self, registration: &Registration) -> Result<String, anyhow::Error> {
+        let body = self.expect_ok(self.request("POST", "/circuits", &registration.serialize())?)?;
+        Ok(serde_json::from_slice::<StateResponse>(&body)?.vk_id)
+    }
+
+    /// Registers a circuit whose public inputs follow `abi`, returning its id
+    pub fn register_with_abi(&self, registration: &Registration, abi: &Abi) -> Result<String, anyhow::Error> {
+        let body = self.expect_ok(self.request("POST", &format!("/circuits/{}", abi), &registration.serialize())?)?;
+        Ok(serde_json::from_slice::<StateResponse>(&body)?.vk_id)
+    }
+
+    pub fn state(&self, vk_id: &str) -> Result<Option<u64>, anyhow::Error> {
+        Ok(self.state_with_proof(vk_id)?.map(|response| response.value))
+    }
+
+    /// Fetches a circuit's state together with its inclusion proof
+    pub fn state_with_proof(&self, vk_id: &str) -> Result<Option<StateResponse>, anyhow::Error> {
+        let (status, body) = self.request("GET", &format!("/state/{}", vk_id), &[])?;
+        if status == 404 {
+            return Ok(None);
+        }
+        let body = self.expect_ok((status, body))?;
+        Ok(Some(serde_json::from_slice(&body)?))
+    }
+
+    /// Fetches a circuit's state, accepting it only if its proof leads to the trusted `state_root`
+    pub fn verified_state(&self, vk_id: &str, state_root: &HashOut<GoldilocksField>) -> Result<Option<u64>, anyhow::Error> {
+        match self.state_with_proof(vk_id)? {
+            Some(response) => {
+                response.verify(state_root)?;
+                Ok(Some(response.value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn state_root(&self) -> Result<HashOut<GoldilocksField>, anyhow::Error> {
+        let body = self.expect_ok(self.request("GET", "/state_root", &[])?)?;
+        digest_from_hex(&serde_json::from_slice::<String>(&body)?)
+    }
+
+    pub fn receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, anyhow::Error> {
+        let (status, body) = self.request("GET", &format!("/receipts/{}", tx_hash), &[])?;
+        if status == 404 {
+            return Ok(None);
+        }
+        let body = self.expect_ok((status, body))?;
+        Ok(Some(serde_json::from_slice(&body)?))
+    }
+
+    pub fn snapshot(&self) -> Result<Snapshot, anyhow::Error> {
+        let body = self.expect_ok(self.request("GET", "/snapshot", &[])?)?;
+        Snapshot::from_bytes(&body)
+    }
+
+    /// Fetches the encoded proof of the node's history, if it proves one and it has moved past genesis
+    pub fn history(&self) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let (status, body) = self.request("GET", "/history", &[])?;
+        if status == 404 {
+            return Ok(None);
+        }
+        Ok(Some(self.expect_ok((status, body))?))
+    }
+
+    /// Scrapes the node's metrics in the Prometheus text format
+    pub fn metrics(&self) -> Result<String, anyhow::Error> {
+        let body = self.expect_ok(self.request("GET", "/metrics", &[])?)?;
+        Ok(String::from_utf8(body)?)
+    }
+
+    pub fn shutdown(&self) -> Result<(), anyhow::Error> {
+        self.expect_ok(self.request("POST", "/shutdown", &[])?)?;
+        Ok(())
+    }
+
+    fn expect_ok(&self, (status, body): (u16, Vec<u8>)) -> Result<Vec<u8>, anyhow::Error> {
+        if status != 200 {
+            anyhow::bail!("Node returned {}: {}", status, String::from_utf8_lossy(&body));
+        }
+        Ok(body)
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>), anyhow::Error> {
+        request(self.addr, method, path, body)
+    }
+}
+
+/// Sends one HTTP request to a local server, returning the status and body of the response
+pub(crate) fn request(addr: SocketAddr, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>), anyhow::Error> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        addr,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let status_line = read_line(&mut reader)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Malformed status line: {:?}", status_line))?;
+    let content_length = read_headers(&mut reader)?;
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((status, body))
+}
+
+/// Reads a line of at most `MAX_LINE_LENGTH` bytes, returning an empty one if the connection closed
+fn read_line(reader: &mut impl BufRead) -> Result<String, anyhow::Error> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH as u64 + 1).read_line(&mut line)?;
+    if line.len() > MAX_LINE_LENGTH {
+        anyhow::bail!("Line longer than {} bytes", MAX_LINE_LENGTH);
+    }
+    Ok(line)
+}
+
+/// Reads header lines up to the blank line, returning the content length
+fn read_headers(reader: &mut impl BufRead) -> Result<usize, anyhow::Error> {
+    let mut content_length = 0;
+    for _ in 0..=MAX_HEADERS {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            anyhow::bail!("Connection closed before end of headers");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(content_length);
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+    anyhow::bail!("More than {} headers", MAX_HEADERS)
+}
+
+/// A connection that fails reads once `REQUEST_DEADLINE` has passed since it was opened
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Request not received in time"));
+        }
+        self.stream.set_read_timeout(Some(remaining.min(READ_TIMEOUT)))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Reads a request, giving up on one that is silent for `READ_TIMEOUT` or not complete within
+/// `REQUEST_DEADLINE`, or whose lines, headers or body exceed the bounds
+pub(crate) fn read_request(stream: &mut TcpStream, max_body_size: usize) -> Result<Request, anyhow::Error> {
+    let mut reader = BufReader::new(Deadline { stream, deadline: Instant::now() + REQUEST_DEADLINE });
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => anyhow::bail!("Malformed request line: {:?}", request_line),
+    };
+
+    let content_length = read_headers(&mut reader)?;
+    if content_length > max_body_size {
+        anyhow::bail!("Request body too large: {} bytes", content_length);
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, body })
+}
+
+pub(crate) fn write_response(stream: &mut TcpStream, response: Response) -> Result<(), anyhow::Error> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        500 => "Internal Server Error",
+        _ => "Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[test]
+fn read_headers_is_bounded() {
+    let headers = |data: String| read_headers(&mut std::io::Cursor::new(data.into_bytes()));
+    assert_eq!(headers("Content-Length: 3\r\n\r\n".to_string()).unwrap(), 3);
+    assert!(headers(format!("X-Padding: {}\r\n\r\n", "a".repeat(MAX_LINE_LENGTH))).is_err());
+    assert!(headers(format!("{}\r\n", "X-Padding: 1\r\n".repeat(MAX_HEADERS))).is_ok());
+    assert!(headers(format!("{}\r\n", "X-Padding: 1\r\n".repeat(MAX_HEADERS + 1))).is_err());
+}
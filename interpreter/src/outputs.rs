@@ -0,0 +1,158 @@
+use std::ops::Range;
+
+use plonky2::field::types::PrimeField64;
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::iop::target::BoolTarget;
+
+use crate::commitment::commit_targets;
+use crate::{CircuitBuilder, GoldilocksField, HashOut, Target};
+
+type F = GoldilocksField;
+const D: usize = 2;
+
+/// How a named public output is decoded from the proof's public inputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputKind {
+    /// A single field element, returned as its canonical `u64`
+    Field,
+    /// A single element constrained to be 0 or 1
+    Bool,
+    /// Four elements forming a Poseidon digest
+    Hash,
+}
+
+impl OutputKind {
+    fn len(&self) -> usize {
+        match self {
+            OutputKind::Field | OutputKind::Bool => 1,
+            OutputKind::Hash => 4,
+        }
+    }
+}
+
+/// A named public output and its position among the public inputs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputSpec {
+    pub name: String,
+    pub kind: OutputKind,
+    pub offset: usize,
+}
+
+/// A decoded public output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputValue {
+    Field(u64),
+    Bool(bool),
+    Hash(HashOut<F>),
+}
+
+/// Collects the named public outputs declared while building a circuit.
+///
+/// Declaring an output registers its targets as public inputs, so the values the
+/// circuit computes for them end up in the proof.
+#[derive(Clone, Debug, Default)]
+pub struct Outputs {
+    specs: Vec<OutputSpec>,
+    targets: Vec<Vec<Target>>,
+}
+
+impl Outputs {
+    pub fn field(&mut self, builder: &mut CircuitBuilder<F, D>, name: &str, target: Target) {
+        self.declare(builder, name, OutputKind::Field, &[target]);
+    }
+
+    pub fn bool(&mut self, builder: &mut CircuitBuilder<F, D>, name: &str, target: BoolTarget) {
+        self.declare(builder, name, OutputKind::Bool, &[target.target]);
+    }
+
+    pub fn hash(&mut self, builder: &mut CircuitBuilder<F, D>, name: &str, target: HashOutTarget) {
+        self.declare(builder, name, OutputKind::Hash, &target.elements);
+    }
+
+    /// Marks the inputs at `committed` as committed, exposing `Poseidon(inputs || blinding)`
+    /// under `name`. The blinding is appended to the circuit's inputs and the circuit must be
+    /// zero-knowledge, see `commit_targets`.
+    pub fn commitment(&mut self, builder: &mut CircuitBuilder<F, D>, name: &str, targets: &mut Vec<Target>, committed: Range<usize>) -> HashOutTarget {
+        let commitment = commit_targets(builder, targets, committed);
+        self.hash(builder, name, commitment);
+        commitment
+    }
+
+    fn declare(&mut self, builder: &mut CircuitBuilder<F, D>, name: &str, kind: OutputKind, targets: &[Target]) {
+        assert!(self.specs.iter().all(|spec| spec.name != name), "Output {} declared twice", name);
+        let offset = builder.num_public_inputs();
+        builder.register_public_inputs(targets);
+        self.specs.push(OutputSpec { name: name.to_string(), kind, offset });
+        self.targets.push(targets.to_vec());
+    }
+
+    /// The targets a declared output was computed into
+    pub fn targets(&self, name: &str) -> Option<&[Target]> {
+        self.specs.iter().position(|spec| spec.name == name).map(|i| self.targets[i].as_slice())
+    }
+
+    pub fn specs(&self) -> &[OutputSpec] {
+        &self.specs
+    }
+
+    /// Decodes every declared output from a proof's public inputs
+    pub fn decode(&self, public_inputs: &[F]) -> Result<PublicOutputs, anyhow::Error> {
+        let values = self
+            .specs
+            .iter()
+            .map(|spec| {
+                let elements = public_inputs
+                    .get(spec.offset..spec.offset + spec.kind.len())
+                    .ok_or_else(|| anyhow::anyhow!("Output {} is missing from the public inputs", spec.name))?;
+                let value = match spec.kind {
+                    OutputKind::Field => OutputValue::Field(elements[0].to_canonical_u64()),
+                    OutputKind::Bool => match elements[0].to_canonical_u64() {
+                        0 => OutputValue::Bool(false),
+                        1 => OutputValue::Bool(true),
+                        v => anyhow::bail!("Output {} is not a bool: {}", spec.name, v),
+                    },
+                    OutputKind::Hash => OutputValue::Hash(HashOut::try_from(elements)?),
+                };
+                Ok((spec.name.clone(), value))
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+        Ok(PublicOutputs { values })
+    }
+}
+
+/// The named public outputs of a proof, in declaration order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PublicOutputs {
+    values: Vec<(String, OutputValue)>,
+}
+
+impl PublicOutputs {
+    pub fn get(&self, name: &str) -> Option<OutputValue> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    }
+
+    pub fn get_u64(&self, name: &str) -> Result<u64, anyhow::Error> {
+        match self.get(name) {
+            Some(OutputValue::Field(v)) => Ok(v),
+            other => anyhow::bail!("Output {} is not a field element: {:?}", name, other),
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<bool, anyhow::Error> {
+        match self.get(name) {
+            Some(OutputValue::Bool(v)) => Ok(v),
+            other => anyhow::bail!("Output {} is not a bool: {:?}", name, other),
+        }
+    }
+
+    pub fn get_hash(&self, name: &str) -> Result<HashOut<F>, anyhow::Error> {
+        match self.get(name) {
+            Some(OutputValue::Hash(v)) => Ok(v),
+            other => anyhow::bail!("Output {} is not a hash: {:?}", name, other),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, OutputValue)> {
+        self.values.iter()
+    }
+}
@@ -0,0 +1,206 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::{GoldilocksField, PoseidonGoldilocksConfig, ProofWithPublicInputs, ZKPCircuit};
+
+type Proof = ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>;
+type Work = Box<dyn FnOnce() -> Result<Proof, anyhow::Error> + Send>;
+
+/// Where a proving job is in its lifecycle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed(String),
+    Cancelled,
+    TimedOut,
+}
+
+impl JobStatus {
+    /// Whether the job will not change status again
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+struct Slot {
+    status: JobStatus,
+    proof: Option<Proof>,
+}
+
+/// State shared between a job's handle and the worker running it
+struct Shared {
+    slot: Mutex<Slot>,
+    finished: Condvar,
+    deadline: Option<Instant>,
+}
+
+impl Shared {
+    /// Locks the slot, timing the job out first if its deadline has passed
+    fn lock(&self) -> MutexGuard<'_, Slot> {
+        let mut slot = self.slot.lock().expect("Job lock poisoned");
+        if !slot.status.is_finished() && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            slot.status = JobStatus::TimedOut;
+            self.finished.notify_all();
+        }
+        slot
+    }
+
+    fn finish(&self, result: Result<Proof, anyhow::Error>) {
+        let mut slot = self.lock();
+        // A job cancelled or timed out while running keeps that status; its proof is dropped
+        if slot.status == JobStatus::Running {
+            match result {
+                Ok(proof) => {
+                    slot.status = JobStatus::Succeeded;
+                    slot.proof = Some(proof);
+                }
+                Err(e) => slot.status = JobStatus::Failed(e.to_string()),
+            }
+        }
+        self.finished.notify_all();
+    }
+}
+
+struct Job {
+    work: Work,
+    shared: Arc<Shared>,
+}
+
+/// A handle to a submitted proving job
+pub struct JobHandle {
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.shared.lock().status.clone()
+    }
+
+    /// Cancels the job. A queued job never runs; a running job cannot be interrupted, so it
+    /// finishes on its worker but its proof is discarded. Returns whether the job was cancelled.
+    pub fn cancel(&self) -> bool {
+        let mut slot = self.shared.lock();
+        if slot.status.is_finished() {
+            return false;
+        }
+        slot.status = JobStatus::Cancelled;
+        self.shared.finished.notify_all();
+        true
+    }
+
+    /// Blocks until the job finishes or its deadline passes, returning the proof
+    pub fn wait(self) -> Result<Proof, anyhow::Error> {
+        let mut slot = self.shared.lock();
+        while !slot.status.is_finished() {
+            slot = match self.shared.deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    let (slot, _) = self.shared.finished.wait_timeout(slot, timeout).expect("Job lock poisoned");
+                    drop(slot);
+                    self.shared.lock()
+                }
+                None => self.shared.finished.wait(slot).expect("Job lock poisoned"),
+            };
+        }
+        match &slot.status {
+            JobStatus::Succeeded => slot.proof.take().ok_or_else(|| anyhow::anyhow!("Job {} result was already taken", self.id)),
+            JobStatus::Failed(reason) => anyhow::bail!("Job {} failed: {}", self.id, reason),
+            status => anyhow::bail!("Job {} did not complete: {:?}", self.id, status),
+        }
+    }
+
+    /// Returns the proof if the job has succeeded, without blocking
+    pub fn try_take(&self) -> Option<Proof> {
+        self.shared.lock().proof.take()
+    }
+}
+
+/// Runs proving jobs on a fixed pool of worker threads, so callers get a handle back
+/// immediately instead of blocking for the whole proof.
+///
+/// At most `capacity` jobs wait in the queue; further submissions are refused rather
+/// than buffered without bound.
+pub struct ProvingQueue {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    next_id: AtomicU64,
+}
+
+impl ProvingQueue {
+    pub fn new(workers: usize, capacity: usize) -> Self {
+        assert!(workers > 0, "A proving queue needs at least one worker");
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers)
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                std::thread::Builder::new()
+                    .name(format!("prover-{}", i))
+                    .spawn(move || work(&receiver))
+                    .expect("Failed to spawn prover thread")
+            })
+            .collect();
+        Self { sender: Some(sender), workers, next_id: AtomicU64::new(0) }
+    }
+
+    /// Queues a proof of `inputs` for `circuit`. With a timeout, the job is abandoned if
+    /// it has not finished that long after submission.
+    pub fn prove(&self, circuit: Arc<ZKPCircuit>, inputs: Vec<u64>, timeout: Option<Duration>) -> Result<JobHandle, anyhow::Error> {
+        self.submit(move || circuit.prove(inputs), timeout)
+    }
+
+    /// Queues any proving work, such as a circuit with its own witness assignment
+    pub fn submit(&self, work: impl FnOnce() -> Result<Proof, anyhow::Error> + Send + 'static, timeout: Option<Duration>) -> Result<JobHandle, anyhow::Error> {
+        let shared = Arc::new(Shared {
+            slot: Mutex::new(Slot { status: JobStatus::Queued, proof: None }),
+            finished: Condvar::new(),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        });
+        let job = Job { work: Box::new(work), shared: Arc::clone(&shared) };
+        match self.sender.as_ref().expect("Proving queue is shut down").try_send(job) {
+            Ok(()) => Ok(JobHandle { id: self.next_id.fetch_add(1, Ordering::Relaxed), shared }),
+            Err(TrySendError::Full(_)) => anyhow::bail!("Proving queue is full"),
+            Err(TrySendError::Disconnected(_)) => anyhow::bail!("Proving queue is shut down"),
+        }
+    }
+}
+
+impl Drop for ProvingQueue {
+    /// Lets the workers drain the queue, then joins them
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock().expect("Queue lock poisoned").recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        {
+            let mut slot = job.shared.lock();
+            // Skip jobs cancelled or timed out while queued
+            if slot.status != JobStatus::Queued {
+                continue;
+            }
+            slot.status = JobStatus::Running;
+        }
+        let result = catch_unwind(AssertUnwindSafe(job.work)).unwrap_or_else(|_| Err(anyhow::anyhow!("Prover panicked")));
+        job.shared.finish(result);
+    }
+}
@@ -0,0 +1,448 @@
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use plonky2::plonk::config::GenericHashOut;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Ledger, Receipt};
+use crate::node::{read_request, request, write_response, Request, Response};
+use crate::txn::Transaction;
+use crate::{digest_from_hex, digest_to_hex, hash_bytes, GoldilocksField, HashOut};
+
+type F = GoldilocksField;
+
+/// How often a follower polls its leader for new log entries
+pub const SYNC_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A transaction the leader applied, at its position in the replicated log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Term of the leader that appended the entry
+    pub term: u64,
+    pub tx: Vec<u8>,
+    /// Chains the entry to the one before it, so followers detect a diverged log
+    pub hash: HashOut<F>,
+}
+
+impl LogEntry {
+    fn new(prev: &HashOut<F>, term: u64, tx: Vec<u8>) -> Self {
+        Self { hash: entry_hash(prev, term, &tx), term, tx }
+    }
+}
+
+fn entry_hash(prev: &HashOut<F>, term: u64, tx: &[u8]) -> HashOut<F> {
+    let data: Vec<u8> = prev.to_bytes().into_iter().chain(term.to_le_bytes()).chain(tx.iter().copied()).collect();
+    hash_bytes(&data)
+}
+
+/// The log entries from some index on, as served by `GET /log/<from>/<term>`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogSlice {
+    /// Term of the serving replica
+    pub term: u64,
+    /// Hash of the entry before `from`, zero at the start of the log, `None` past its end
+    pub prev: Option<HashOut<F>>,
+    pub entries: Vec<LogEntry>,
+}
+
+/// Where a replica stands, as reported by `GET /status`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaStatus {
+    pub term: u64,
+    pub leader: bool,
+    /// The leader a follower replicates from
+    pub following: Option<SocketAddr>,
+    pub log_len: usize,
+    pub state_root: String,
+    /// Why the last sync with the leader failed, if it did
+    pub sync_error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Role {
+    Leader,
+    Follower { leader: Option<SocketAddr> },
+}
+
+/// Request body of `POST /follow`
+#[derive(Serialize, Deserialize)]
+struct Follow {
+    leader: SocketAddr,
+    term: u64,
+}
+
+struct State {
+    role: Role,
+    term: u64,
+    genesis: Ledger,
+    ledger: Ledger,
+    log: Vec<LogEntry>,
+    sync_error: Option<String>,
+}
+
+impl State {
+    fn last_hash(&self) -> HashOut<F> {
+        self.log.last().map(|entry| entry.hash).unwrap_or_default()
+    }
+
+    fn status(&self) -> ReplicaStatus {
+        ReplicaStatus {
+            term: self.term,
+            leader: self.role == Role::Leader,
+            following: match self.role {
+                Role::Follower { leader } => leader,
+                Role::Leader => None,
+            },
+            log_len: self.log.len(),
+            state_root: digest_to_hex(&self.ledger.state_root()),
+            sync_error: self.sync_error.clone(),
+        }
+    }
+
+    /// Checks an entry's link to the log and applies its transaction
+    fn append(&mut self, entry: LogEntry) -> Result<(), anyhow::Error> {
+        let index = self.log.len();
+        if entry.hash != entry_hash(&self.last_hash(), entry.term, &entry.tx) {
+            anyhow::bail!("Log entry {} does not chain to the log", index);
+        }
+        let receipt = self.ledger.apply_bytes(&entry.tx);
+        if !receipt.is_applied() {
+            anyhow::bail!("Log entry {} does not apply: {:?}", index, receipt.status);
+        }
+        self.log.push(entry);
+        Ok(())
+    }
+
+    /// Replaces the log and ledger with the leader's full log, replayed from genesis.
+    /// Entries replayed are not counted again in the ledger's metrics.
+    fn rebuild(&mut self, entries: Vec<LogEntry>) -> Result<(), anyhow::Error> {
+        let metrics = self.ledger.metrics().clone();
+        self.ledger = self.genesis.clone_detached();
+        self.log.clear();
+        let replayed = entries.into_iter().try_for_each(|entry| self.append(entry));
+        self.ledger = std::mem::take(&mut self.ledger).with_metrics(metrics);
+        replayed
+    }
+}
+
+/// One replica of the ledger, serving HTTP on a local address.
+///
+/// The leader orders the transactions it accepts into a log; followers poll the leader's
+/// log, verify and apply every entry, and refuse transactions themselves. Every replica
+/// starts from the same genesis ledger, so a follower that falls behind, or whose log
+/// diverged from the leader's, catches up by replaying the leader's log.
+///
+/// Failover is term based: promoting a follower to leader needs a higher term than it
+/// has seen, and the promoted replica tells the leader it followed to follow it instead.
+/// A leader that sees a higher term steps down, and followers refuse the log of a leader
+/// with a lower term than theirs.
+///
+/// A leader acknowledges a transaction as soon as it applied it, before any follower has
+/// replicated it, so its receipts are not durable: a leader cut off from its successor
+/// keeps accepting transactions until it learns of the newer term, then drops them.
+///
+/// Besides `GET /state/<vk_id>`, replicas serve:
+/// - `POST /transactions` applying and logging a transaction, on the leader only
+/// - `GET /log/<from>/<term>` returning the `LogSlice` from `from`, for a follower at `term`
+/// - `GET /status` returning the `ReplicaStatus`
+/// - `POST /promote/<term>` making the replica leader of `term`
+/// - `POST /follow` pointing the replica at a leader of at least its term
+/// - `POST /sync` fetching new entries from the leader right away
+pub struct Replica {
+    listener: TcpListener,
+    state: Arc<Mutex<State>>,
+    max_body_size: usize,
+}
+
+/// A running replica, used to find its address and shut it down
+pub struct ReplicaHandle {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Replica {
+    /// Binds the leader of term 1 to a local address; use port 0 to pick a free port
+    pub fn leader(addr: impl ToSocketAddrs, genesis: Ledger) -> Result<Self, anyhow::Error> {
+        Self::bind(addr, genesis, Role::Leader, 1)
+    }
+
+    /// Binds a follower replicating from `leader`
+    pub fn follower(addr: impl ToSocketAddrs, genesis: Ledger, leader: SocketAddr) -> Result<Self, anyhow::Error> {
+        Self::bind(addr, genesis, Role::Follower { leader: Some(leader) }, 0)
+    }
+
+    fn bind(addr: impl ToSocketAddrs, genesis: Ledger, role: Role, term: u64) -> Result<Self, anyhow::Error> {
+        let listener = TcpListener::bind(addr)?;
+        // No route accepts a body larger than a transaction
+        let max_body_size = genesis.limits().max_transaction_size;
+        // Replicas count their own transactions, even when they start from the same genesis
+        let genesis = genesis.clone_detached();
+        let state = State { role, term, ledger: genesis.clone(), genesis, log: Vec::new(), sync_error: None };
+        Ok(Self { listener, state: Arc::new(Mutex::new(state)), max_body_size })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, anyhow::Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Starts serving requests and, while following, polling the leader on background threads
+    pub fn spawn(self) -> Result<ReplicaHandle, anyhow::Error> {
+        let addr = self.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let state = self.state.clone();
+
+        let flag = shutdown.clone();
+        let syncing = state.clone();
+        let sync = std::thread::spawn(move || {
+            while !flag.load(Ordering::SeqCst) {
+                std::thread::sleep(SYNC_INTERVAL);
+                let _ = sync(&syncing);
+            }
+        });
+        let flag = shutdown.clone();
+        let server = std::thread::spawn(move || self.serve(&flag));
+        Ok(ReplicaHandle { addr, shutdown, threads: vec![server, sync], state })
+    }
+
+    fn serve(&self, shutdown: &AtomicBool) {
+        for stream in self.listener.incoming() {
+            if shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = self.handle(stream) {
+                        eprintln!("Failed to handle request: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("Failed to accept connection: {}", e),
+            }
+        }
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<(), anyhow::Error> {
+        let response = match read_request(&mut stream, self.max_body_size) {
+            Ok(request) => self.route(request),
+            Err(e) => Response::error(400, e),
+        };
+        write_response(&mut stream, response)
+    }
+
+    fn route(&self, request: Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        // Syncing talks to the leader, so it must not hold the lock
+        if let ("POST", ["sync"]) = (request.method.as_str(), segments.as_slice()) {
+            return match sync(&self.state) {
+                Ok(applied) => Response::json(&applied),
+                Err(e) => Response::error(409, e),
+            };
+        }
+
+        let mut state = lock(&self.state);
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["transactions"]) => {
+                if let Role::Follower { leader } = state.role {
+                    return Response::error(409, format!("Not the leader, following: {:?}", leader));
+                }
+                let receipt = state.ledger.apply_bytes(&request.body);
+                if receipt.is_applied() {
+                    let entry = LogEntry::new(&state.last_hash(), state.term, request.body);
+                    state.log.push(entry);
+                }
+                Response::json(&receipt)
+            }
+            ("GET", ["log", from, term]) => match (from.parse::<usize>(), term.parse::<u64>()) {
+                (Ok(from), Ok(term)) => {
+                    if term > state.term {
+                        // A follower has seen a newer leader, so this one is stale
+                        state.term = term;
+                        state.role = Role::Follower { leader: None };
+                        return Response::error(409, format!("Stale term, stepped down for term {}", term));
+                    }
+                    let prev = match from {
+                        0 => Some(HashOut::default()),
+                        from => state.log.get(from - 1).map(|entry| entry.hash),
+                    };
+                    let entries = state.log.get(from..).unwrap_or_default().to_vec();
+                    Response::json(&LogSlice { term: state.term, prev, entries })
+                }
+                _ => Response::error(400, "Invalid log position"),
+            },
+            ("GET", ["state", vk_id]) => match digest_from_hex(vk_id) {
+                Ok(id) => match state.ledger.state(&id) {
+                    Some(value) => Response::json(&value),
+                    None => Response::error(404, "Unknown circuit"),
+                },
+                Err(e) => Response::error(400, e),
+            },
+            ("GET", ["status"]) => Response::json(&state.status()),
+            ("POST", ["promote", term]) => match term.parse::<u64>() {
+                Ok(term) if term > state.term => {
+                    let deposed = match state.role {
+                        Role::Follower { leader } => leader,
+                        Role::Leader => None,
+                    };
+                    state.term = term;
+                    state.role = Role::Leader;
+                    let status = state.status();
+                    // Fencing talks to the old leader, so it must not hold the lock either
+                    drop(state);
+                    if let Some(deposed) = deposed {
+                        if let Err(e) = self.local_addr().and_then(|leader| fence(deposed, leader, term)) {
+                            eprintln!("Failed to fence the deposed leader {}: {}", deposed, e);
+                        }
+                    }
+                    Response::json(&status)
+                }
+                Ok(term) => Response::error(409, format!("Term {} is not newer than {}", term, state.term)),
+                Err(e) => Response::error(400, e),
+            },
+            ("POST", ["follow"]) => match serde_json::from_slice::<Follow>(&request.body) {
+                Ok(follow) if follow.term >= state.term => {
+                    state.term = follow.term;
+                    state.role = Role::Follower { leader: Some(follow.leader) };
+                    Response::json(&state.status())
+                }
+                Ok(follow) => Response::error(409, format!("Term {} is older than {}", follow.term, state.term)),
+                Err(e) => Response::error(400, e),
+            },
+            _ => Response::error(404, "Not found"),
+        }
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().expect("Replica lock poisoned")
+}
+
+/// Fetches and applies the leader's new log entries, returning how many were applied
+fn sync(shared: &Mutex<State>) -> Result<usize, anyhow::Error> {
+    let (leader, term, from) = {
+        let state = lock(shared);
+        match state.role {
+            Role::Follower { leader: Some(leader) } => (leader, state.term, state.log.len()),
+            _ => return Ok(0),
+        }
+    };
+    let result = fetch(leader, from, term).and_then(|slice| {
+        let mut state = lock(shared);
+        // Ignore the slice if the replica changed while it was fetched
+        if state.role != (Role::Follower { leader: Some(leader) }) || state.log.len() != from {
+            return Ok(0);
+        }
+        if slice.term < state.term {
+            anyhow::bail!("Leader term {} is older than {}", slice.term, state.term);
+        }
+        state.term = slice.term;
+        if slice.prev == Some(state.last_hash()) {
+            let applied = slice.entries.len();
+            slice.entries.into_iter().try_for_each(|entry| state.append(entry))?;
+            return Ok(applied);
+        }
+        // The logs diverged, such as after entries of a deposed leader, so start over
+        drop(state);
+        let full = fetch(leader, 0, term)?;
+        let mut state = lock(shared);
+        if state.role != (Role::Follower { leader: Some(leader) }) {
+            return Ok(0);
+        }
+        state.rebuild(full.entries)?;
+        Ok(state.log.len())
+    });
+    lock(shared).sync_error = result.as_ref().err().map(|e| e.to_string());
+    result
+}
+
+/// Makes a deposed leader follow the replica promoted in its place, so it stops taking transactions
+fn fence(deposed: SocketAddr, leader: SocketAddr, term: u64) -> Result<(), anyhow::Error> {
+    let body = serde_json::to_vec(&Follow { leader, term })?;
+    let (status, body) = request(deposed, "POST", "/follow", &body)?;
+    if status != 200 {
+        anyhow::bail!("Deposed leader returned {}: {}", status, String::from_utf8_lossy(&body));
+    }
+    Ok(())
+}
+
+fn fetch(leader: SocketAddr, from: usize, term: u64) -> Result<LogSlice, anyhow::Error> {
+    let (status, body) = request(leader, "GET", &format!("/log/{}/{}", from, term), &[])?;
+    if status != 200 {
+        anyhow::bail!("Leader returned {}: {}", status, String::from_utf8_lossy(&body));
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+impl ReplicaHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops serving and syncing, returning the replica's ledger and log
+    pub fn shutdown(self) -> Result<(Ledger, Vec<LogEntry>), anyhow::Error> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop
+        let _ = TcpStream::connect(self.addr);
+        for thread in self.threads {
+            thread.join().map_err(|_| anyhow::anyhow!("Replica thread panicked"))?;
+        }
+        let state = Arc::try_unwrap(self.state).map_err(|_| anyhow::anyhow!("Replica state still in use"))?;
+        let state = state.into_inner().expect("Replica lock poisoned");
+        Ok((state.ledger, state.log))
+    }
+}
+
+/// A blocking client for a local replica
+pub struct ReplicaClient {
+    addr: SocketAddr,
+}
+
+impl ReplicaClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    pub fn submit(&self, tx: &Transaction) -> Result<Receipt, anyhow::Error> {
+        self.json(request(self.addr, "POST", "/transactions", &tx.serialize())?)
+    }
+
+    pub fn state(&self, vk_id: &HashOut<F>) -> Result<Option<u64>, anyhow::Error> {
+        match request(self.addr, "GET", &format!("/state/{}", digest_to_hex(vk_id)), &[])? {
+            (404, _) => Ok(None),
+            response => self.json(response),
+        }
+    }
+
+    pub fn status(&self) -> Result<ReplicaStatus, anyhow::Error> {
+        self.json(request(self.addr, "GET", "/status", &[])?)
+    }
+
+    pub fn log(&self, from: usize) -> Result<LogSlice, anyhow::Error> {
+        let term = self.status()?.term;
+        self.json(request(self.addr, "GET", &format!("/log/{}/{}", from, term), &[])?)
+    }
+
+    pub fn promote(&self, term: u64) -> Result<ReplicaStatus, anyhow::Error> {
+        self.json(request(self.addr, "POST", &format!("/promote/{}", term), &[])?)
+    }
+
+    pub fn follow(&self, leader: SocketAddr, term: u64) -> Result<ReplicaStatus, anyhow::Error> {
+        let body = serde_json::to_vec(&Follow { leader, term })?;
+        self.json(request(self.addr, "POST", "/follow", &body)?)
+    }
+
+    /// Makes a follower fetch new entries from its leader now, returning how many it applied
+    pub fn sync(&self) -> Result<usize, anyhow::Error> {
+        self.json(request(self.addr, "POST", "/sync", &[])?)
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&self, (status, body): (u16, Vec<u8>)) -> Result<T, anyhow::Error> {
+        if status != 200 {
+            anyhow::bail!("Replica returned {}: {}", status, String::from_utf8_lossy(&body));
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
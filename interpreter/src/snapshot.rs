@@ -0,0 +1,149 @@
+use std::io::Write;
+use std::path::Path;
+
+use plonky2::field::types::Field;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::{GenericHashOut, Hasher};
+use serde::{Deserialize, Serialize};
+
+use crate::abi::Abi;
+use crate::{digest_to_hex, hash_bytes, GoldilocksField, HashOut};
+
+type F = GoldilocksField;
+
+/// Current snapshot format version
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// Leading bytes of every snapshot file
+const MAGIC: &[u8; 4] = b"ZKSS";
+
+/// Length of the magic, the version and the checksum preceding the payload
+const HEADER_LEN: usize = 4 + 4 + 32;
+
+/// The full vk→state map of a ledger with its metadata.
+///
+/// On disk a snapshot is `MAGIC || version || checksum || payload`, where the
+/// checksum is the Poseidon hash of the bincode payload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub height: u64,
+    pub last_tx: HashOut<F>,
+    pub states: Vec<(HashOut<F>, u64)>,
+    /// The ABIs of the circuits that do not use the default one
+    pub abis: Vec<(HashOut<F>, Abi)>,
+    /// The digest of the common data every circuit was registered with
+    pub commons: Vec<(HashOut<F>, HashOut<F>)>,
+    pub commitment: HashOut<F>,
+}
+
+/// Commits to the ledger metadata, its states, its ABIs and its common data, which must be ordered by vk id
+pub fn commit(height: u64, last_tx: &HashOut<F>, states: &[(HashOut<F>, u64)], abis: &[(HashOut<F>, Abi)], commons: &[(HashOut<F>, HashOut<F>)]) -> HashOut<F> {
+    let elements: Vec<F> = [F::from_canonical_u64(height)]
+        .into_iter()
+        .chain(last_tx.elements)
+        .chain(states.iter().flat_map(|(vk_id, value)| {
+            vk_id.elements.into_iter().chain([F::from_noncanonical_u64(*value)])
+        }))
+        .chain(abis.iter().flat_map(|(vk_id, abi)| vk_id.elements.into_iter().chain(abi.to_elements())))
+        .chain(commons.iter().flat_map(|(vk_id, common)| vk_id.elements.into_iter().chain(common.elements)))
+        .collect();
+    PoseidonHash::hash_no_pad(&elements)
+}
+
+impl Snapshot {
+    /// Checks that the snapshot is of a supported version and matches its own commitment
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.version != SNAPSHOT_VERSION {
+            anyhow::bail!("Unsupported snapshot version {}, expected: {}", self.version, SNAPSHOT_VERSION);
+        }
+        let computed = commit(self.height, &self.last_tx, &self.states, &self.abis, &self.commons);
+        if computed != self.commitment {
+            anyhow::bail!("Snapshot commitment mismatch, recorded: {}, computed: {}", digest_to_hex(&self.commitment), digest_to_hex(&computed));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload = bincode::serialize(self).expect("Failed to serialize snapshot");
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&hash_bytes(&payload).to_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Decodes a snapshot, checking its magic, version, checksum and commitment
+    pub fn from_bytes(data: &[u8]) -> Result<Self, anyhow::Error> {
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            anyhow::bail!("Not a snapshot");
+        }
+        let version = u32::from_le_bytes(data[4..8].try_into()?);
+        if version != SNAPSHOT_VERSION {
+            anyhow::bail!("Unsupported snapshot version {}, expected: {}", version, SNAPSHOT_VERSION);
+        }
+        let (checksum, payload) = data[8..].split_at(32);
+        if hash_bytes(payload).to_bytes() != checksum {
+            anyhow::bail!("Snapshot checksum mismatch");
+        }
+        let snapshot: Snapshot = bincode::deserialize(payload).map_err(|e| anyhow::anyhow!("Failed to deserialize snapshot: {}", e))?;
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        // Write next to the target first so a crash never leaves a truncated snapshot behind
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&self.to_bytes())?;
+        // The data must be on disk before the rename makes it the snapshot
+        file.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        // And the rename itself must be on disk before the snapshot counts as written
+        #[cfg(unix)]
+        {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[test]
+fn snapshot_round_trip_and_corruption() -> Result<(), anyhow::Error> {
+    let mut ledger = crate::db::Ledger::new();
+    let registration = |vk: &[u8]| crate::txn::Registration { vk: vk.to_vec(), common: b"common".to_vec() };
+    let a = ledger.register(&registration(b"circuit a"), 7)?;
+    let b = ledger.register(&registration(b"circuit b"), 0)?;
+
+    let snapshot = ledger.snapshot();
+    let bytes = snapshot.to_bytes();
+    assert_eq!(Snapshot::from_bytes(&bytes)?, snapshot);
+
+    let restored = crate::db::Ledger::from_snapshot(Snapshot::from_bytes(&bytes)?, &ledger.commitment())?;
+    assert_eq!(restored.state(&a), Some(7));
+    assert_eq!(restored.state(&b), Some(0));
+    assert_eq!(restored.commitment(), ledger.commitment());
+
+    // A flipped payload byte fails the checksum
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(Snapshot::from_bytes(&corrupted).is_err());
+
+    // An unknown version is refused before the payload is read
+    let mut future = bytes.clone();
+    future[4] = 4;
+    assert!(Snapshot::from_bytes(&future).is_err());
+
+    // A consistent snapshot of another ledger is refused by commitment
+    let other = crate::db::Ledger::new().snapshot();
+    assert!(crate::db::Ledger::from_snapshot(other, &ledger.commitment()).is_err());
+
+    Ok(())
+}
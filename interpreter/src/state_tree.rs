@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+use serde::{Deserialize, Serialize};
+
+use crate::{digest_to_hex, GoldilocksField, HashOut};
+
+type F = GoldilocksField;
+
+/// One level per bit of a vk id
+pub const STATE_TREE_DEPTH: usize = 256;
+
+/// Digests of empty subtrees, indexed by depth; an empty leaf is the zero digest
+static DEFAULT_NODES: LazyLock<[HashOut<F>; STATE_TREE_DEPTH + 1]> = LazyLock::new(|| {
+    let mut nodes = [HashOut::ZERO; STATE_TREE_DEPTH + 1];
+    for depth in (0..STATE_TREE_DEPTH).rev() {
+        nodes[depth] = PoseidonHash::two_to_one(nodes[depth + 1], nodes[depth + 1]);
+    }
+    nodes
+});
+
+/// The leaf committing to a circuit's state
+pub fn leaf_hash(vk_id: &HashOut<F>, value: u64) -> HashOut<F> {
+    let elements: Vec<F> = vk_id.elements.into_iter().chain([F::from_noncanonical_u64(value)]).collect();
+    PoseidonHash::hash_no_pad(&elements)
+}
+
+/// The path of a vk id through the tree, most significant bit of its first element first
+fn key(vk_id: &HashOut<F>) -> [u64; 4] {
+    vk_id.elements.map(|e| e.to_canonical_u64())
+}
+
+fn bit(key: &[u64; 4], index: usize) -> bool {
+    key[index / 64] >> (63 - index % 64) & 1 == 1
+}
+
+/// The first `depth` bits of a key, the rest cleared, identifying a node at that depth
+fn prefix(key: &[u64; 4], depth: usize) -> [u64; 4] {
+    let mut prefix = [0; 4];
+    for (i, limb) in prefix.iter_mut().enumerate() {
+        let bits = depth.saturating_sub(i * 64).min(64);
+        *limb = if bits == 0 { 0 } else { key[i] & (u64::MAX << (64 - bits)) };
+    }
+    prefix
+}
+
+/// The prefix of the node next to the one at `depth` on the path of `key`
+fn sibling(key: &[u64; 4], depth: usize) -> [u64; 4] {
+    let mut sibling = prefix(key, depth);
+    let index = depth - 1;
+    sibling[index / 64] ^= 1 << (63 - index % 64);
+    sibling
+}
+
+/// A sparse Poseidon Merkle tree committing to the vk→state map.
+///
+/// Only nodes differing from the empty subtree of their depth are stored, and setting
+/// a leaf rehashes just the nodes on its path.
+#[derive(Clone, Debug, Default)]
+pub struct StateTree {
+    nodes: HashMap<(usize, [u64; 4]), HashOut<F>>,
+}
+
+impl StateTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn root(&self) -> HashOut<F> {
+        self.node(0, &[0; 4])
+    }
+
+    fn node(&self, depth: usize, prefix: &[u64; 4]) -> HashOut<F> {
+        self.nodes.get(&(depth, *prefix)).copied().unwrap_or(DEFAULT_NODES[depth])
+    }
+
+    fn set_node(&mut self, depth: usize, prefix: [u64; 4], digest: HashOut<F>) {
+        if digest == DEFAULT_NODES[depth] {
+            self.nodes.remove(&(depth, prefix));
+        } else {
+            self.nodes.insert((depth, prefix), digest);
+        }
+    }
+
+    /// Sets a circuit's state and rehashes its path to the root
+    pub fn insert(&mut self, vk_id: &HashOut<F>, value: u64) {
+        self.set_leaf(vk_id, leaf_hash(vk_id, value));
+    }
+
+    pub fn remove(&mut self, vk_id: &HashOut<F>) {
+        self.set_leaf(vk_id, HashOut::ZERO);
+    }
+
+    fn set_leaf(&mut self, vk_id: &HashOut<F>, leaf: HashOut<F>) {
+        let key = key(vk_id);
+        let mut digest = leaf;
+        self.set_node(STATE_TREE_DEPTH, key, digest);
+        for depth in (1..=STATE_TREE_DEPTH).rev() {
+            let sibling = self.node(depth, &sibling(&key, depth));
+            digest = if bit(&key, depth - 1) {
+                PoseidonHash::two_to_one(sibling, digest)
+            } else {
+                PoseidonHash::two_to_one(digest, sibling)
+            };
+            self.set_node(depth - 1, prefix(&key, depth - 1), digest);
+        }
+    }
+
+    /// Proves the state of a circuit, or its absence when `value` is `None`.
+    /// `value` must be what the tree holds for `vk_id`, or the proof will not verify.
+    pub fn prove(&self, vk_id: &HashOut<F>, value: Option<u64>) -> StateProof {
+        let key = key(vk_id);
+        let siblings = (1..=STATE_TREE_DEPTH)
+            .rev()
+            .filter_map(|depth| {
+                let sibling = self.node(depth, &sibling(&key, depth));
+                (sibling != DEFAULT_NODES[depth]).then_some((depth, sibling))
+            })
+            .collect();
+        StateProof { vk_id: *vk_id, value, siblings }
+    }
+}
+
+/// A Merkle proof that a circuit has a given state, or none, under a state root
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    pub vk_id: HashOut<F>,
+    pub value: Option<u64>,
+    /// The siblings on the path that are not empty subtrees, with their depths, leaf first
+    pub siblings: Vec<(usize, HashOut<F>)>,
+}
+
+impl StateProof {
+    /// Recomputes the root the proof leads to
+    pub fn root(&self) -> Result<HashOut<F>, anyhow::Error> {
+        let key = key(&self.vk_id);
+        let mut siblings = self.siblings.iter().peekable();
+        let mut digest = match self.value {
+            Some(value) => leaf_hash(&self.vk_id, value),
+            None => HashOut::ZERO,
+        };
+        for depth in (1..=STATE_TREE_DEPTH).rev() {
+            let sibling = match siblings.next_if(|(d, _)| *d == depth) {
+                Some((_, sibling)) => *sibling,
+                None => DEFAULT_NODES[depth],
+            };
+            digest = if bit(&key, depth - 1) {
+                PoseidonHash::two_to_one(sibling, digest)
+            } else {
+                PoseidonHash::two_to_one(digest, sibling)
+            };
+        }
+        if let Some((depth, _)) = siblings.next() {
+            anyhow::bail!("State proof sibling at depth {} is out of order", depth);
+        }
+        Ok(digest)
+    }
+
+    /// Every sibling on the path, leaf first, with empty subtrees filled in
+    pub fn path(&self) -> Vec<HashOut<F>> {
+        let mut siblings = self.siblings.iter().peekable();
+        (1..=STATE_TREE_DEPTH)
+            .rev()
+            .map(|depth| match siblings.next_if(|(d, _)| *d == depth) {
+                Some((_, sibling)) => *sibling,
+                None => DEFAULT_NODES[depth],
+            })
+            .collect()
+    }
+
+    /// Checks the proof against a trusted state root
+    pub fn verify(&self, root: &HashOut<F>) -> Result<(), anyhow::Error> {
+        let computed = self.root()?;
+        if computed != *root {
+            anyhow::bail!("State proof for {} leads to root {}, expected: {}", digest_to_hex(&self.vk_id), digest_to_hex(&computed), digest_to_hex(root));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn state_tree_proofs_and_incremental_updates() -> Result<(), anyhow::Error> {
+    use crate::hash_bytes;
+
+    let ids: Vec<HashOut<F>> = (0u8..4).map(|i| hash_bytes(&[i])).collect();
+    let mut tree = StateTree::new();
+    let empty = tree.root();
+    assert_eq!(empty, DEFAULT_NODES[0]);
+    tree.prove(&ids[0], None).verify(&empty)?;
+
+    for (i, id) in ids.iter().enumerate() {
+        tree.insert(id, i as u64);
+    }
+    let root = tree.root();
+    for (i, id) in ids.iter().enumerate() {
+        tree.prove(id, Some(i as u64)).verify(&root)?;
+        assert!(tree.prove(id, Some(i as u64 + 1)).verify(&root).is_err());
+        assert!(tree.prove(id, None).verify(&root).is_err());
+    }
+    let absent = hash_bytes(b"absent");
+    tree.prove(&absent, None).verify(&root)?;
+
+    // Updating a leaf in place gives the root of a tree built from scratch
+    tree.insert(&ids[1], 7);
+    let mut rebuilt = StateTree::new();
+    for (i, id) in ids.iter().enumerate().rev() {
+        rebuilt.insert(id, if i == 1 { 7 } else { i as u64 });
+    }
+    assert_eq!(tree.root(), rebuilt.root());
+    assert!(tree.prove(&ids[0], Some(0)).verify(&root).is_err());
+
+    // Removing every leaf prunes the tree back to empty
+    for id in &ids {
+        tree.remove(id);
+    }
+    assert_eq!(tree.root(), empty);
+    assert!(tree.nodes.is_empty());
+
+    let mut forged = rebuilt.prove(&ids[2], Some(2));
+    forged.siblings.reverse();
+    assert!(forged.verify(&rebuilt.root()).is_err());
+    Ok(())
+}
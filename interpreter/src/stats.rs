@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use plonky2::field::types::PrimeField64;
+use plonky2::fri::oracle::SALT_SIZE;
+use plonky2::plonk::circuit_data::{CommonCircuitData, ProverOnlyCircuitData};
+
+use crate::diagnostics;
+use crate::{GoldilocksField, PoseidonGoldilocksConfig};
+
+type F = GoldilocksField;
+type C = PoseidonGoldilocksConfig;
+const D: usize = 2;
+
+/// Serialized sizes of the elements a proof is made of
+const FIELD_SIZE: usize = 8;
+const EXTENSION_SIZE: usize = FIELD_SIZE * D;
+const HASH_SIZE: usize = 32;
+
+/// The shape and cost of a built circuit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitStats {
+    /// Number of rows each gate type occupies, padding included
+    pub gates: BTreeMap<String, usize>,
+    pub degree_bits: usize,
+    pub num_wires: usize,
+    pub num_routed_wires: usize,
+    pub num_constants: usize,
+    pub num_public_inputs: usize,
+    /// Size in bytes of a serialized proof with its public inputs, derived from the circuit's shape
+    pub estimated_proof_size: usize,
+    /// Measured when the stats were taken by proving a witness
+    pub timings: Option<Timings>,
+}
+
+/// Costs measured by proving and verifying one witness
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timings {
+    pub prove: Duration,
+    pub verify: Duration,
+    pub proof_size: usize,
+}
+
+impl CircuitStats {
+    pub(crate) fn new(prover_data: &ProverOnlyCircuitData<F, C, D>, common_data: &CommonCircuitData<F, D>) -> Self {
+        Self {
+            gates: gate_rows(prover_data, common_data),
+            degree_bits: common_data.degree_bits(),
+            num_wires: common_data.config.num_wires,
+            num_routed_wires: common_data.config.num_routed_wires,
+            num_constants: common_data.num_constants,
+            num_public_inputs: common_data.num_public_inputs,
+            estimated_proof_size: estimate_proof_size(common_data),
+            timings: None,
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        1 << self.degree_bits
+    }
+
+    /// Lists every metric that differs from `other`, the circuit this one is compared against
+    pub fn diff(&self, other: &CircuitStats) -> StatsDiff {
+        let mut changes = Vec::new();
+        let mut compare = |metric: String, before: u128, after: u128| {
+            if before != after {
+                changes.push(Change { metric, before, after });
+            }
+        };
+
+        for name in other.gates.keys().chain(self.gates.keys().filter(|name| !other.gates.contains_key(*name))) {
+            let rows = |stats: &CircuitStats| stats.gates.get(name).copied().unwrap_or_default() as u128;
+            compare(format!("gates[{}]", name), rows(other), rows(self));
+        }
+        for (metric, before, after) in [
+            ("degree_bits", other.degree_bits, self.degree_bits),
+            ("num_wires", other.num_wires, self.num_wires),
+            ("num_routed_wires", other.num_routed_wires, self.num_routed_wires),
+            ("num_constants", other.num_constants, self.num_constants),
+            ("num_public_inputs", other.num_public_inputs, self.num_public_inputs),
+            ("estimated_proof_size", other.estimated_proof_size, self.estimated_proof_size),
+        ] {
+            compare(metric.to_string(), before as u128, after as u128);
+        }
+        if let (Some(before), Some(after)) = (other.timings, self.timings) {
+            compare("prove_time_us".to_string(), before.prove.as_micros(), after.prove.as_micros());
+            compare("verify_time_us".to_string(), before.verify.as_micros(), after.verify.as_micros());
+            compare("proof_size".to_string(), before.proof_size as u128, after.proof_size as u128);
+        }
+        StatsDiff { changes }
+    }
+}
+
+impl fmt::Display for CircuitStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows: {} (degree bits {})", self.num_rows(), self.degree_bits)?;
+        writeln!(f, "wires: {} ({} routed)", self.num_wires, self.num_routed_wires)?;
+        writeln!(f, "constants: {}", self.num_constants)?;
+        writeln!(f, "public inputs: {}", self.num_public_inputs)?;
+        writeln!(f, "estimated proof size: {} bytes", self.estimated_proof_size)?;
+        if let Some(timings) = &self.timings {
+            writeln!(f, "prove: {:?}, verify: {:?}, proof size: {} bytes", timings.prove, timings.verify, timings.proof_size)?;
+        }
+        for (gate, rows) in &self.gates {
+            writeln!(f, "{:>8} x {}", rows, gate)?;
+        }
+        Ok(())
+    }
+}
+
+/// A metric that differs between two circuits
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub metric: String,
+    pub before: u128,
+    pub after: u128,
+}
+
+/// The metrics in which one circuit differs from another
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatsDiff {
+    pub changes: Vec<Change>,
+}
+
+impl StatsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn get(&self, metric: &str) -> Option<&Change> {
+        self.changes.iter().find(|change| change.metric == metric)
+    }
+}
+
+impl fmt::Display for StatsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            let delta = change.after as i128 - change.before as i128;
+            writeln!(f, "{}: {} -> {} ({:+})", change.metric, change.before, change.after, delta)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the rows of each gate type by reading which gate every row's selector polynomial picks
+fn gate_rows(prover_data: &ProverOnlyCircuitData<F, C, D>, common_data: &CommonCircuitData<F, D>) -> BTreeMap<String, usize> {
+    let selectors = diagnostics::selectors(common_data);
+    let constant_values = diagnostics::constant_values(prover_data, common_data);
+    let mut rows = BTreeMap::new();
+    for row in 0..common_data.degree() {
+        // Rows outside a selector group hold an out-of-range placeholder instead of a gate index
+        let gate = selectors
+            .groups
+            .iter()
+            .zip(&constant_values)
+            .map(|(range, values)| (range, values[row].to_canonical_u64() as usize))
+            .find(|(range, gate)| range.contains(gate))
+            .map(|(_, gate)| common_data.gates[gate].0.id());
+        if let Some(gate) = gate {
+            *rows.entry(gate).or_default() += 1;
+        }
+    }
+    rows
+}
+
+/// Computes the length of `ProofWithPublicInputs::to_bytes` for a circuit, mirroring
+/// plonky2's proof serialization field by field
+fn estimate_proof_size(common_data: &CommonCircuitData<F, D>) -> usize {
+    let config = &common_data.config;
+    let fri = &common_data.fri_params;
+    let challenges = config.num_challenges;
+    let cap = (1 << fri.config.cap_height) * HASH_SIZE;
+    let salt = if fri.hiding { SALT_SIZE } else { 0 };
+    let merkle_proof = |tree_bits: usize| 1 + (tree_bits - fri.config.cap_height) * HASH_SIZE;
+
+    // Wires, Z and partial product, and quotient caps
+    let caps = 3 * cap;
+
+    let zs_partial_products = challenges * (1 + common_data.num_partial_products + common_data.num_lookup_polys);
+    let quotient_polys = challenges * common_data.quotient_degree_factor;
+    let openings = EXTENSION_SIZE
+        * (common_data.num_constants
+            + config.num_routed_wires
+            + config.num_wires
+            + 2 * challenges * (1 + common_data.num_lookup_polys)
+            + challenges * common_data.num_partial_products
+            + quotient_polys);
+
+    let lde_bits = common_data.degree_bits() + fri.config.rate_bits;
+    let initial_trees = [
+        common_data.num_constants + config.num_routed_wires,
+        config.num_wires + salt,
+        zs_partial_products + salt,
+        quotient_polys + salt,
+    ]
+    .iter()
+    .map(|leaf_len| leaf_len * FIELD_SIZE + merkle_proof(lde_bits))
+    .sum::<usize>();
+    let mut steps = 0;
+    let mut tree_bits = lde_bits;
+    for &arity_bits in &fri.reduction_arity_bits {
+        tree_bits -= arity_bits;
+        steps += (1 << arity_bits) * EXTENSION_SIZE + merkle_proof(tree_bits);
+    }
+    let final_poly = (1 << (common_data.degree_bits() - fri.reduction_arity_bits.iter().sum::<usize>())) * EXTENSION_SIZE;
+    let opening_proof = fri.reduction_arity_bits.len() * cap + fri.config.num_query_rounds * (initial_trees + steps) + final_poly + FIELD_SIZE;
+
+    let public_inputs = FIELD_SIZE + common_data.num_public_inputs * FIELD_SIZE;
+    caps + openings + opening_proof + public_inputs
+}
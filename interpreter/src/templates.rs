@@ -0,0 +1,207 @@
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::merkle_tree::MerkleTree;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::BoolTarget;
+use plonky2::plonk::config::Hasher;
+
+use crate::{digest_to_hex, CircuitBuilder, CircuitConfig, GoldilocksField, HashOut, PoseidonGoldilocksConfig, ProofWithPublicInputs, Target, ZKPCircuit};
+
+type F = GoldilocksField;
+const D: usize = 2;
+type Proof = ProofWithPublicInputs<F, PoseidonGoldilocksConfig, D>;
+
+/// Whether `lhs < rhs`, comparing their `bits`-bit decompositions from the most significant
+/// bit down. Both values are range checked to `bits` bits, which must be below 64 so each
+/// decomposition is unique.
+pub fn less_than(builder: &mut CircuitBuilder<F, D>, lhs: Target, rhs: Target, bits: usize) -> BoolTarget {
+    assert!(bits > 0 && bits < 64, "Comparisons support 1 to 63 bits, got: {}", bits);
+    let lhs_bits = builder.split_le(lhs, bits);
+    let rhs_bits = builder.split_le(rhs, bits);
+
+    // The result is set at the first bit where the values differ with `lhs` lower
+    let mut less = builder._false();
+    let mut equal_so_far = builder._true();
+    for (&a, &b) in lhs_bits.iter().zip(&rhs_bits).rev() {
+        let not_a = builder.not(a);
+        let a_lower = builder.and(not_a, b);
+        let first_lower = builder.and(equal_so_far, a_lower);
+        less = builder.or(less, first_lower);
+        let equal = builder.is_equal(a.target, b.target);
+        equal_so_far = builder.and(equal_so_far, equal);
+    }
+    less
+}
+
+/// "I know `x` with `Poseidon(x) = h`" for a preimage of `len` elements
+pub struct HashPreimage {
+    circuit: ZKPCircuit,
+    len: usize,
+}
+
+impl HashPreimage {
+    pub fn new(len: usize) -> Self {
+        let circuit = ZKPCircuit::with_outputs(CircuitConfig::standard_recursion_zk_config(), len, |builder, targets, outputs| {
+            let hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(targets.clone());
+            outputs.hash(builder, "hash", hash);
+        });
+        Self { circuit, len }
+    }
+
+    pub fn circuit(&self) -> &ZKPCircuit {
+        &self.circuit
+    }
+
+    /// The digest a preimage proves knowledge of
+    pub fn hash(preimage: &[u64]) -> HashOut<F> {
+        let elements: Vec<F> = preimage.iter().map(|&v| F::from_noncanonical_u64(v)).collect();
+        PoseidonHash::hash_no_pad(&elements)
+    }
+
+    pub fn prove(&self, preimage: &[u64]) -> Result<Proof, anyhow::Error> {
+        if preimage.len() != self.len {
+            anyhow::bail!("Preimage has {} elements, expected: {}", preimage.len(), self.len);
+        }
+        self.circuit.prove(preimage.to_vec())
+    }
+
+    /// Verifies that the proof shows knowledge of a preimage of `hash`
+    pub fn verify(&self, proof: &Proof, hash: &HashOut<F>) -> Result<(), anyhow::Error> {
+        let proven = self.circuit.verify_outputs(proof)?.get_hash("hash")?;
+        if proven != *hash {
+            anyhow::bail!("Proof is for hash {}, expected: {}", digest_to_hex(&proven), digest_to_hex(hash));
+        }
+        Ok(())
+    }
+}
+
+/// "`lower ≤ x ≤ upper`" for a private `x` and public bounds, all below `2^bits`
+pub struct Range {
+    circuit: ZKPCircuit,
+}
+
+impl Range {
+    pub fn new(bits: usize) -> Self {
+        let circuit = ZKPCircuit::with_outputs(CircuitConfig::standard_recursion_zk_config(), 3, |builder, targets, outputs| {
+            let (x, lower, upper) = (targets[0], targets[1], targets[2]);
+            let below = less_than(builder, x, lower, bits);
+            let above = less_than(builder, upper, x, bits);
+            builder.assert_zero(below.target);
+            builder.assert_zero(above.target);
+            outputs.field(builder, "lower", lower);
+            outputs.field(builder, "upper", upper);
+        });
+        Self { circuit }
+    }
+
+    pub fn circuit(&self) -> &ZKPCircuit {
+        &self.circuit
+    }
+
+    pub fn prove(&self, x: u64, lower: u64, upper: u64) -> Result<Proof, anyhow::Error> {
+        if !(lower..=upper).contains(&x) {
+            anyhow::bail!("{} is not in [{}, {}]", x, lower, upper);
+        }
+        self.circuit.prove(vec![x, lower, upper])
+    }
+
+    /// Verifies that the proof shows a value in `[lower, upper]`
+    pub fn verify(&self, proof: &Proof, lower: u64, upper: u64) -> Result<(), anyhow::Error> {
+        let outputs = self.circuit.verify_outputs(proof)?;
+        let bounds = (outputs.get_u64("lower")?, outputs.get_u64("upper")?);
+        if bounds != (lower, upper) {
+            anyhow::bail!("Proof is for range [{}, {}], expected: [{}, {}]", bounds.0, bounds.1, lower, upper);
+        }
+        Ok(())
+    }
+}
+
+/// Leaves past the end of a `MerkleSet`. A value's leaf is its single element padded with zeros,
+/// while this one has more than four elements and is hashed, so no value matches it.
+const EMPTY_LEAF: [u64; 5] = [0; 5];
+
+/// A set of values committed by the root of a Poseidon Merkle tree with `2^depth` leaves
+pub struct MerkleSet {
+    tree: MerkleTree<F, PoseidonHash>,
+    values: Vec<u64>,
+    depth: usize,
+}
+
+impl MerkleSet {
+    /// Commits to `values`, padded with `EMPTY_LEAF` to `2^depth` leaves
+    pub fn new(values: &[u64], depth: usize) -> Result<Self, anyhow::Error> {
+        if values.len() > 1 << depth {
+            anyhow::bail!("{} values do not fit in a tree of depth {}", values.len(), depth);
+        }
+        let empty = EMPTY_LEAF.map(F::from_canonical_u64).to_vec();
+        let mut leaves: Vec<Vec<F>> = values.iter().map(|&v| vec![F::from_noncanonical_u64(v)]).collect();
+        leaves.resize(1 << depth, empty);
+        Ok(Self { tree: MerkleTree::new(leaves, 0), values: values.to_vec(), depth })
+    }
+
+    pub fn root(&self) -> HashOut<F> {
+        self.tree.cap.0[0]
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The position of `value` in the set, if it is a member
+    pub fn index_of(&self, value: u64) -> Option<usize> {
+        self.values.iter().position(|&v| v == value)
+    }
+
+    pub fn siblings(&self, index: usize) -> Vec<HashOut<F>> {
+        self.tree.prove(index).siblings
+    }
+}
+
+/// "`x` is in the set committed by Merkle root `r`", without revealing `x` or its position
+pub struct Membership {
+    circuit: ZKPCircuit,
+    depth: usize,
+}
+
+impl Membership {
+    pub fn new(depth: usize) -> Self {
+        // Inputs: the value, its index, then the siblings from the leaf up, four elements each
+        let circuit = ZKPCircuit::with_outputs(CircuitConfig::standard_recursion_zk_config(), 2 + 4 * depth, |builder, targets, outputs| {
+            let index = builder.split_le(targets[1], depth);
+            let mut node = builder.hash_or_noop::<PoseidonHash>(vec![targets[0]]);
+            for (bit, sibling) in index.into_iter().zip(targets[2..].chunks(4)) {
+                // A set index bit puts the node on the right
+                let (left, right): (Vec<Target>, Vec<Target>) = node
+                    .elements
+                    .iter()
+                    .zip(sibling)
+                    .map(|(&n, &s)| (builder.select(bit, s, n), builder.select(bit, n, s)))
+                    .unzip();
+                node = builder.hash_n_to_hash_no_pad::<PoseidonHash>([left, right].concat());
+            }
+            outputs.hash(builder, "root", node);
+        });
+        Self { circuit, depth }
+    }
+
+    pub fn circuit(&self) -> &ZKPCircuit {
+        &self.circuit
+    }
+
+    pub fn prove(&self, set: &MerkleSet, value: u64) -> Result<Proof, anyhow::Error> {
+        if set.depth() != self.depth {
+            anyhow::bail!("Set has depth {}, expected: {}", set.depth(), self.depth);
+        }
+        let index = set.index_of(value).ok_or_else(|| anyhow::anyhow!("{} is not in the set", value))?;
+        let siblings = set.siblings(index).into_iter().flat_map(|sibling| sibling.elements.map(|e| e.to_canonical_u64()));
+        self.circuit.prove([value, index as u64].into_iter().chain(siblings).collect())
+    }
+
+    /// Verifies that the proof shows membership in the set with `root`
+    pub fn verify(&self, proof: &Proof, root: &HashOut<F>) -> Result<(), anyhow::Error> {
+        let proven = self.circuit.verify_outputs(proof)?.get_hash("root")?;
+        if proven != *root {
+            anyhow::bail!("Proof is for root {}, expected: {}", digest_to_hex(&proven), digest_to_hex(root));
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,20 @@
 use serde::{Serialize, Deserialize};
 use bincode;
+use bincode::Options;
+
+use crate::abi::Abi;
+use crate::limits::Limits;
+use crate::{circuit_id, hash_bytes, GoldilocksField, HashOut};
 
 #[derive(Serialize, Deserialize)]
 pub struct Transaction {
     pub vk: Vec<u8>,
     pub proof_data: Vec<u8>,
-    pub common: Vec<u8>
+    pub common: Vec<u8>,
+    /// Id of the gate serializer `common` was written with
+    pub gate_serializer: String,
+    /// Roles of the proof's public inputs, part of the id of the circuit the transaction targets
+    pub abi: Abi,
 }
 
 impl Transaction {
@@ -13,7 +22,117 @@ impl Transaction {
         bincode::serialize(self).expect("Failed to serialize transaction")
     }
 
-    pub fn deserialize(data: &[u8]) -> Self {
-        bincode::deserialize(data).expect("Failed to deserialize transaction")
+    pub fn deserialize(data: &[u8]) -> Result<Self, anyhow::Error> {
+        Self::deserialize_with_limits(data, &Limits::default())
+    }
+
+    /// Deserializes a transaction, refusing inputs and fields larger than `limits` allow
+    pub fn deserialize_with_limits(data: &[u8], limits: &Limits) -> Result<Self, anyhow::Error> {
+        if data.len() > limits.max_transaction_size {
+            anyhow::bail!("Transaction is {} bytes, limit is {}", data.len(), limits.max_transaction_size);
+        }
+        // Same encoding as `bincode::serialize`, with every length prefix bounded by the input size
+        let tx: Self = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(limits.max_transaction_size as u64)
+            .deserialize(data)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize transaction: {}", e))?;
+        tx.check_fields(limits)?;
+        Ok(tx)
+    }
+
+    fn check_fields(&self, limits: &Limits) -> Result<(), anyhow::Error> {
+        for (what, len, max) in [
+            ("Verifier key", self.vk.len(), limits.max_vk_size),
+            ("Proof", self.proof_data.len(), limits.max_proof_size),
+            ("Common circuit data", self.common.len(), limits.max_common_size),
+        ] {
+            if len > max {
+                anyhow::bail!("{} is {} bytes, limit is {}", what, len, max);
+            }
+        }
+        Ok(())
+    }
+
+    /// Identifies the transaction by the Poseidon hash of its serialized form
+    pub fn hash(&self) -> HashOut<GoldilocksField> {
+        hash_bytes(&self.serialize())
+    }
+
+    /// Identifies the circuit the transaction targets, by its vk and ABI
+    pub fn vk_id(&self) -> HashOut<GoldilocksField> {
+        circuit_id(&self.vk, &self.abi)
+    }
+}
+
+/// A circuit as a ledger registers it: its vk, and the common data every transaction of it must carry.
+/// The proof is checked against the common data the transaction carries, which sets the FRI
+/// parameters and gates, so a circuit is only sound under the common data it was built with.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub vk: Vec<u8>,
+    pub common: Vec<u8>,
+}
+
+impl Registration {
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Failed to serialize registration")
+    }
+
+    /// Deserializes a registration, refusing fields larger than `limits` allow
+    pub fn deserialize_with_limits(data: &[u8], limits: &Limits) -> Result<Self, anyhow::Error> {
+        let registration: Self = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(limits.max_transaction_size as u64)
+            .deserialize(data)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize registration: {}", e))?;
+        for (what, len, max) in [("Verifier key", registration.vk.len(), limits.max_vk_size), ("Common circuit data", registration.common.len(), limits.max_common_size)] {
+            if len > max {
+                anyhow::bail!("{} is {} bytes, limit is {}", what, len, max);
+            }
+        }
+        Ok(registration)
+    }
+}
+
+/// Several transactions, possibly for different circuits, applied atomically in order
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub transactions: Vec<Transaction>,
+}
+
+impl Bundle {
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Failed to serialize bundle")
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, anyhow::Error> {
+        Self::deserialize_with_limits(data, &Limits::default())
+    }
+
+    /// Deserializes a bundle, bounding its total size by the transaction size limit
+    pub fn deserialize_with_limits(data: &[u8], limits: &Limits) -> Result<Self, anyhow::Error> {
+        if data.len() > limits.max_transaction_size {
+            anyhow::bail!("Bundle is {} bytes, limit is {}", data.len(), limits.max_transaction_size);
+        }
+        let bundle: Self = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(limits.max_transaction_size as u64)
+            .deserialize(data)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize bundle: {}", e))?;
+        if bundle.transactions.len() > limits.max_bundle_len {
+            anyhow::bail!("Bundle has {} transactions, limit is {}", bundle.transactions.len(), limits.max_bundle_len);
+        }
+        for tx in &bundle.transactions {
+            tx.check_fields(limits)?;
+        }
+        Ok(bundle)
+    }
+
+    pub fn hash(&self) -> HashOut<GoldilocksField> {
+        hash_bytes(&self.serialize())
     }
 }
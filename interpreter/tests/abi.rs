@@ -0,0 +1,83 @@
+#![cfg(feature = "prover")]
+
+use zk::abi::Abi;
+use zk::db::Ledger;
+use zk::metrics::RejectReason;
+use zk::node::{Client, Node};
+use zk::snapshot::Snapshot;
+use zk::txn::Transaction;
+use zk::*;
+
+/// Adds a step to the state: public inputs are the step, the new state, then the old state
+fn stepper_circuit() -> Result<ZKPCircuit, anyhow::Error> {
+    let circuit = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 3, |builder, targets| {
+        let sum = builder.add(targets[2], targets[0]);
+        builder.connect(sum, targets[1]);
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+        builder.register_public_input(targets[2]);
+    });
+    Ok(circuit.with_abi("parameter,new_state,precondition".parse()?))
+}
+
+#[test]
+fn transactions_follow_the_registered_abi() -> Result<(), anyhow::Error> {
+    let circuit = stepper_circuit()?;
+    let mut ledger = Ledger::new();
+    let vk_id = ledger.register_with_abi(&circuit.registration(), 10, circuit.abi().clone())?;
+    assert_eq!(ledger.abi(&vk_id), Some(circuit.abi()));
+
+    assert!(ledger.apply(&circuit.transaction(&circuit.prove(vec![5, 15, 10])?)).is_applied());
+    assert_eq!(ledger.state(&vk_id), Some(15));
+    let receipt = ledger.apply(&circuit.transaction(&circuit.prove(vec![5, 15, 10])?));
+    assert!(!receipt.is_applied(), "{:?}", receipt);
+    assert_eq!(ledger.metrics().rejected_count(RejectReason::StaleState), 1);
+
+    // The ABI is part of the circuit's id, so whoever registers the vk first cannot pick it for others
+    assert_eq!(ledger.register_with_abi(&circuit.registration(), 0, circuit.abi().clone())?, vk_id);
+    let default_id = ledger.register(&circuit.registration(), 0)?;
+    assert_ne!(default_id, vk_id);
+    let tx = Transaction { abi: Abi::default(), ..circuit.transaction(&circuit.prove(vec![0, 10, 10])?) };
+    assert_eq!(tx.vk_id(), default_id);
+    assert!(ledger.apply(&tx).is_applied());
+    assert_eq!((ledger.state(&default_id), ledger.state(&vk_id)), (Some(10), Some(15)));
+
+    // Without a new state input, a proof only attests to the current state
+    let reader = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, |builder, targets| {
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+    })
+    .with_abi("parameter,precondition".parse()?);
+    let reader_id = ledger.register_with_abi(&reader.registration(), 4, reader.abi().clone())?;
+    assert!(ledger.apply(&reader.transaction(&reader.prove(vec![1, 4])?)).is_applied());
+    assert!(!ledger.apply(&reader.transaction(&reader.prove(vec![1, 5])?)).is_applied());
+    assert_eq!(ledger.state(&reader_id), Some(4));
+
+    // Snapshots carry the ABIs, and commit to them
+    let snapshot = Snapshot::from_bytes(&ledger.snapshot().to_bytes())?;
+    assert_eq!(snapshot.abis.len(), 2);
+    let mut restored = Ledger::from_snapshot(snapshot.clone(), &ledger.commitment())?;
+    assert_eq!(restored.abi(&vk_id), Some(circuit.abi()));
+    assert!(restored.apply(&circuit.transaction(&circuit.prove(vec![1, 16, 15])?)).is_applied());
+    let mut stripped = snapshot;
+    stripped.abis.clear();
+    assert!(Ledger::from_snapshot(stripped, &ledger.commitment()).is_err());
+    Ok(())
+}
+
+#[test]
+fn node_registers_circuits_with_an_abi() -> Result<(), anyhow::Error> {
+    let circuit = stepper_circuit()?;
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let client = Client::new(handle.local_addr());
+
+    let vk_id = client.register_with_abi(&circuit.registration(), circuit.abi())?;
+    assert!(client.submit(&circuit.transaction(&circuit.prove(vec![3, 3, 0])?))?.is_applied());
+    assert_eq!(client.state(&vk_id)?, Some(3));
+    assert_ne!(client.register_with_abi(&circuit.registration(), &Abi::default())?, vk_id);
+    assert_eq!(client.state(&vk_id)?, Some(3));
+
+    let ledger = handle.shutdown()?;
+    assert_eq!(ledger.abi(&digest_from_hex(&vk_id)?), Some(circuit.abi()));
+    Ok(())
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "prover")]
+
+use zk::db::Ledger;
+use zk::limits::Limits;
+use zk::node::{Client, Node};
+use zk::txn::{Bundle, Transaction};
+use zk::*;
+
+/// Two counters whose vks differ, so they hold separate states
+fn counters() -> (ZKPCircuit, ZKPCircuit) {
+    let build = |bits: usize| {
+        ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, move |builder, targets| {
+            builder.range_check(targets[0], bits);
+            let one = builder.one();
+            let s = builder.add(targets[0], one);
+            builder.register_public_input(targets[0]);
+            builder.register_public_input(targets[1]);
+            builder.connect(s, targets[1]);
+        })
+    };
+    (build(32), build(16))
+}
+
+fn tx(circuit: &ZKPCircuit, current: u64) -> Result<Transaction, anyhow::Error> {
+    let proof = circuit.prove(vec![current, current + 1])?;
+    Ok(circuit.transaction(&proof))
+}
+
+#[test]
+fn bundles_apply_atomically() -> Result<(), anyhow::Error> {
+    let (a, b) = counters();
+    let mut ledger = Ledger::new();
+    let a_id = ledger.register(&a.registration(), 0)?;
+    let b_id = ledger.register(&b.registration(), 0)?;
+
+    // Later proofs are checked against the state earlier proofs in the bundle produce
+    let mut first = Bundle { transactions: vec![tx(&a, 0)?, tx(&b, 0)?, tx(&a, 1)?] };
+    let receipt = ledger.apply_bundle(&first);
+    assert!(receipt.is_applied(), "{:?}", receipt.error);
+    assert!(receipt.receipts.iter().all(|r| r.is_applied()));
+    let applied = receipt.receipts[0].clone();
+    assert_eq!((ledger.state(&a_id), ledger.state(&b_id), ledger.height()), (Some(2), Some(1), 3));
+
+    // One stale proof rejects the whole bundle and leaves every state untouched
+    let root = ledger.state_root();
+    let bundle = Bundle { transactions: vec![tx(&a, 2)?, tx(&b, 0)?] };
+    let receipt = ledger.apply_bundle(&bundle);
+    assert!(!receipt.is_applied());
+    assert!(receipt.error.as_deref().unwrap_or_default().starts_with("Transaction 1: Stale state"));
+    assert!(receipt.receipts.iter().all(|r| !r.is_applied()));
+    assert_eq!((ledger.state(&a_id), ledger.state(&b_id), ledger.height()), (Some(2), Some(1), 3));
+    assert_eq!(ledger.state_root(), root);
+
+    // Replaying an applied transaction leaves its receipt alone, and what follows it goes unchecked
+    let (replayed, unchecked) = (first.transactions.swap_remove(0), tx(&a, 2)?);
+    let unchecked_hash = unchecked.hash();
+    let receipt = ledger.apply_bundle(&Bundle { transactions: vec![replayed, unchecked] });
+    assert_eq!(receipt.receipts.len(), 1);
+    assert!(!receipt.receipts[0].is_applied());
+    assert_eq!(ledger.receipt(&digest_from_hex(&applied.tx_hash)?), Some(&applied));
+    assert_eq!(ledger.receipt(&unchecked_hash), None);
+
+    // A proof replaying a transition already made within the bundle is stale too
+    assert!(!ledger.apply_bundle(&Bundle { transactions: vec![tx(&a, 2)?, tx(&a, 2)?] }).is_applied());
+    assert!(!ledger.apply_bundle(&Bundle { transactions: vec![] }).is_applied());
+    assert_eq!(ledger.state(&a_id), Some(2));
+    Ok(())
+}
+
+#[test]
+fn bundles_are_bounded_and_served_by_the_node() -> Result<(), anyhow::Error> {
+    let (a, b) = counters();
+    let bundle = Bundle { transactions: vec![tx(&a, 0)?, tx(&b, 0)?] };
+
+    let limits = Limits { max_bundle_len: 1, ..Limits::default() };
+    assert!(Bundle::deserialize_with_limits(&bundle.serialize(), &limits).is_err());
+    assert!(Bundle::deserialize(&[0xff; 16]).is_err());
+
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let client = Client::new(handle.local_addr());
+    let a_id = client.register(&a.registration())?;
+
+    // b is unknown, so neither transition applies
+    assert!(!client.submit_bundle(&bundle)?.is_applied());
+    assert_eq!(client.state(&a_id)?, Some(0));
+
+    let b_id = client.register(&b.registration())?;
+    let receipt = client.submit_bundle(&bundle)?;
+    assert!(receipt.is_applied());
+    assert_eq!(client.receipt(&receipt.receipts[1].tx_hash)?, Some(receipt.receipts[1].clone()));
+    assert_eq!((client.state(&a_id)?, client.state(&b_id)?), (Some(1), Some(1)));
+    handle.shutdown()?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "prover")]
+
+use zk::commitment::{check_commitment, commit, random_blinding, BLINDING_LEN};
+use zk::*;
+
+/// Proves that a committed balance covers a public amount, without revealing the balance
+fn solvency_circuit() -> ZKPCircuit {
+    ZKPCircuit::with_outputs(CircuitConfig::standard_recursion_zk_config(), 2, |builder, targets, outputs| {
+        outputs.commitment(builder, "balance", targets, 0..1);
+        let remaining = builder.sub(targets[0], targets[1]);
+        builder.range_check(remaining, 32);
+        outputs.field(builder, "amount", targets[1]);
+    })
+}
+
+#[test]
+fn committed_inputs_open_to_the_public_commitment() -> Result<(), anyhow::Error> {
+    let circuit = solvency_circuit();
+    let blinding = random_blinding();
+    let inputs: Vec<u64> = [100, 40].into_iter().chain(blinding).collect();
+    assert_eq!(inputs.len(), 2 + BLINDING_LEN);
+
+    let (proof, outputs) = circuit.prove_with_outputs(inputs)?;
+    let commitment = outputs.get_hash("balance")?;
+    assert_eq!(commitment, commit(&[100], &blinding));
+    assert_eq!(outputs.get_u64("amount")?, 40);
+    check_commitment(&commitment, &[100], &blinding)?;
+    assert_eq!(circuit.verify_outputs(&proof)?, outputs);
+
+    // Neither a different balance nor a different blinding opens the commitment
+    assert!(check_commitment(&commitment, &[101], &blinding).is_err());
+    assert!(check_commitment(&commitment, &[100], &random_blinding()).is_err());
+    // The balance stays hidden: the same balance commits differently under a fresh blinding
+    let other = random_blinding();
+    let (_, outputs) = circuit.prove_with_outputs([100, 40].into_iter().chain(other).collect())?;
+    assert_ne!(outputs.get_hash("balance")?, commitment);
+
+    assert!(circuit.prove(vec![100, 40]).is_err(), "missing blinding was accepted");
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "zero-knowledge")]
+fn committed_inputs_need_a_zero_knowledge_config() {
+    ZKPCircuit::with_outputs(CircuitConfig::standard_recursion_config(), 1, |builder, targets, outputs| {
+        outputs.commitment(builder, "value", targets, 0..1);
+    });
+}
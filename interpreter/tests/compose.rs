@@ -0,0 +1,80 @@
+#![cfg(feature = "prover")]
+
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+use zk::commitment::{commit, random_blinding};
+use zk::compose::Stage;
+use zk::*;
+
+/// Sums two values and hashes them
+fn summing() -> Stage {
+    Stage::with_outputs(&["x", "y"], |builder, targets, outputs| {
+        let sum = builder.add(targets[0], targets[1]);
+        outputs.field(builder, "sum", sum);
+        let digest = builder.hash_n_to_hash_no_pad::<PoseidonHash>(targets.clone());
+        outputs.hash(builder, "digest", digest);
+    })
+    .expect("inputs are distinct")
+}
+
+/// Scales a total by a committed factor and rehashes a digest
+fn scaling() -> Stage {
+    Stage::with_outputs(&["total", "factor", "d0", "d1", "d2", "d3"], |builder, targets, outputs| {
+        let scaled = builder.mul(targets[0], targets[1]);
+        outputs.field(builder, "scaled", scaled);
+        outputs.commitment(builder, "factor", targets, 1..2);
+        let rehashed = builder.hash_n_to_hash_no_pad::<PoseidonHash>(targets[2..6].to_vec());
+        outputs.hash(builder, "rehashed", rehashed);
+    })
+    .expect("inputs are distinct")
+}
+
+#[test]
+fn composed_stages_prove_once() -> Result<(), anyhow::Error> {
+    let composed = summing().then(scaling(), &[("sum", "total"), ("digest", "d0")])?;
+    assert_eq!(composed.inputs(), ["x", "y", "factor"]);
+    let blinding = random_blinding();
+    let inputs: Vec<u64> = composed.assign(&[("factor", 3), ("y", 5), ("x", 2)])?.into_iter().chain(blinding).collect();
+
+    let circuit = composed.build(CircuitConfig::standard_recursion_zk_config());
+    let names: Vec<&str> = circuit.outputs().iter().map(|spec| spec.name.as_str()).collect();
+    assert_eq!(names, ["sum", "digest", "scaled", "factor", "rehashed"]);
+
+    let (proof, outputs) = circuit.prove_with_outputs(inputs)?;
+    let digest = PoseidonHash::hash_no_pad(&[GoldilocksField::from_canonical_u64(2), GoldilocksField::from_canonical_u64(5)]);
+    assert_eq!(outputs.get_u64("sum")?, 7);
+    assert_eq!(outputs.get_hash("digest")?, digest);
+    assert_eq!(outputs.get_u64("scaled")?, 21);
+    assert_eq!(outputs.get_hash("factor")?, commit(&[3], &blinding));
+    assert_eq!(outputs.get_hash("rehashed")?, PoseidonHash::hash_no_pad(&digest.elements));
+    assert_eq!(circuit.verify_outputs(&proof)?, outputs);
+    Ok(())
+}
+
+#[test]
+fn invalid_wirings_are_refused() -> Result<(), anyhow::Error> {
+    for (wiring, error) in [
+        (vec![("missing", "total")], "Unknown output missing"),
+        (vec![("sum", "missing")], "Unknown input missing"),
+        (vec![("sum", "total"), ("sum", "total")], "wired twice"),
+        (vec![("digest", "d0"), ("sum", "d2")], "wired twice"),
+        (vec![("digest", "d1")], "does not fit"),
+    ] {
+        let err = summing().then(scaling(), &wiring).err().expect("invalid wiring was accepted");
+        assert!(err.to_string().contains(error), "{}", err);
+    }
+    // Unwired inputs of the second stage must not clash with the first stage's
+    let clashing = Stage::new(&["y"], |_, _| {})?;
+    assert!(summing().then(clashing, &[]).is_err());
+    // As must their outputs
+    let resumming = Stage::with_outputs(&["z"], |builder, targets, outputs| outputs.field(builder, "sum", targets[0]))?;
+    let err = summing().then(resumming, &[]).err().expect("clashing outputs were accepted");
+    assert!(err.to_string().contains("Output sum is declared by both stages"), "{}", err);
+    assert!(Stage::new(&["x", "x"], |_, _| {}).is_err());
+
+    let stage = summing();
+    assert!(stage.assign(&[("x", 1)]).is_err());
+    assert!(stage.assign(&[("x", 1), ("y", 2), ("z", 3)]).is_err());
+    assert!(stage.assign(&[("x", 1), ("y", 2), ("x", 3)]).is_err());
+    Ok(())
+}
@@ -0,0 +1,157 @@
+#![cfg(feature = "prover")]
+
+use std::sync::Arc;
+
+use zk::db::Ledger;
+use zk::gates::{lookup_range_check, WithCustomGates};
+use zk::txn::Registration;
+use zk::{CircuitConfig, CommonCircuitData, ZKPCircuit};
+use plonky2::field::extension::Extendable;
+use plonky2::gates::gate::Gate;
+use plonky2::hash::hash_types::RichField;
+use plonky2::iop::ext_target::ExtensionTarget;
+use plonky2::iop::generator::{GeneratedValues, SimpleGenerator, WitnessGeneratorRef};
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::{PartitionWitness, Witness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::vars::{EvaluationTargets, EvaluationVars};
+use plonky2::util::serialization::{Buffer, GateSerializer, IoResult, Read, Write};
+use plonky2::{get_gate_tag_impl, impl_gate_serializer, read_gate_impl};
+
+/// Constrains wire 1 to be the cube of wire 0
+#[derive(Debug)]
+struct CubeGate;
+
+impl<F: RichField + Extendable<D>, const D: usize> Gate<F, D> for CubeGate {
+    fn id(&self) -> String {
+        "CubeGate".into()
+    }
+
+    fn serialize(&self, _dst: &mut Vec<u8>, _common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        Ok(())
+    }
+
+    fn deserialize(_src: &mut Buffer, _common_data: &CommonCircuitData<F, D>) -> IoResult<Self> {
+        Ok(Self)
+    }
+
+    fn eval_unfiltered(&self, vars: EvaluationVars<F, D>) -> Vec<F::Extension> {
+        let x = vars.local_wires[0];
+        vec![x * x * x - vars.local_wires[1]]
+    }
+
+    fn eval_unfiltered_circuit(&self, builder: &mut CircuitBuilder<F, D>, vars: EvaluationTargets<D>) -> Vec<ExtensionTarget<D>> {
+        let x = vars.local_wires[0];
+        let cube = builder.mul_many_extension([x, x, x]);
+        vec![builder.sub_extension(cube, vars.local_wires[1])]
+    }
+
+    fn generators(&self, row: usize, _local_constants: &[F]) -> Vec<WitnessGeneratorRef<F, D>> {
+        vec![WitnessGeneratorRef::new(CubeGenerator { row }.adapter())]
+    }
+
+    fn num_wires(&self) -> usize {
+        2
+    }
+
+    fn num_constants(&self) -> usize {
+        0
+    }
+
+    fn degree(&self) -> usize {
+        3
+    }
+
+    fn num_constraints(&self) -> usize {
+        1
+    }
+}
+
+#[derive(Debug, Default)]
+struct CubeGenerator {
+    row: usize,
+}
+
+impl<F: RichField + Extendable<D>, const D: usize> SimpleGenerator<F, D> for CubeGenerator {
+    fn id(&self) -> String {
+        "CubeGenerator".into()
+    }
+
+    fn dependencies(&self) -> Vec<Target> {
+        vec![Target::wire(self.row, 0)]
+    }
+
+    fn run_once(&self, witness: &PartitionWitness<F>, out_buffer: &mut GeneratedValues<F>) -> Result<(), anyhow::Error> {
+        let x = witness.get_target(Target::wire(self.row, 0));
+        out_buffer.set_target(Target::wire(self.row, 1), x * x * x)
+    }
+
+    fn serialize(&self, dst: &mut Vec<u8>, _common_data: &CommonCircuitData<F, D>) -> IoResult<()> {
+        dst.write_usize(self.row)
+    }
+
+    fn deserialize(src: &mut Buffer, _common_data: &CommonCircuitData<F, D>) -> IoResult<Self> {
+        Ok(Self { row: src.read_usize()? })
+    }
+}
+
+#[derive(Debug)]
+struct CubeGateSerializer;
+
+impl<F: RichField + Extendable<D>, const D: usize> GateSerializer<F, D> for CubeGateSerializer {
+    impl_gate_serializer! { CubeGateSerializer, CubeGate }
+}
+
+/// Moves the state from `x` to `x^3`
+fn cube_circuit() -> ZKPCircuit {
+    ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 1, |builder, targets| {
+        let row = builder.add_gate(CubeGate, vec![]);
+        builder.connect(Target::wire(row, 0), targets[0]);
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(Target::wire(row, 1));
+    })
+    .with_gate_serializer("cube", Arc::new(WithCustomGates(CubeGateSerializer)))
+}
+
+#[test]
+fn custom_gates_verify_with_a_registered_serializer() -> Result<(), anyhow::Error> {
+    let circuit = cube_circuit();
+    assert_eq!(circuit.stats().gates.get("CubeGate"), Some(&1));
+    let tx = circuit.transaction(&circuit.prove(vec![2])?);
+    assert_eq!(tx.gate_serializer, "cube");
+    assert!(!tx.common.is_empty());
+
+    // A verifier that does not know the serializer cannot decode the circuit
+    let mut ledger = Ledger::new();
+    let vk_id = ledger.register(&Registration { vk: tx.vk.clone(), common: tx.common.clone() }, 2)?;
+    let receipt = ledger.apply(&tx);
+    assert!(format!("{:?}", receipt.status).contains("Unknown gate serializer cube"));
+
+    ledger.register_gate_serializer("cube", Arc::new(WithCustomGates(CubeGateSerializer)))?;
+    assert!(ledger.register_gate_serializer("cube", Arc::new(WithCustomGates(CubeGateSerializer))).is_err());
+    assert!(ledger.apply(&tx).is_applied());
+    assert_eq!(ledger.state(&vk_id), Some(8));
+    Ok(())
+}
+
+#[test]
+fn lookup_range_checks_bound_values() -> Result<(), anyhow::Error> {
+    let circuit = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, |builder, targets| {
+        for &target in targets.iter() {
+            lookup_range_check(builder, target, 12);
+        }
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+    });
+    assert!(circuit.stats().gates.keys().any(|gate| gate.starts_with("LookupGate")));
+
+    // Lookup gates are plonky2's own, so the default serializer handles them
+    let tx = circuit.transaction(&circuit.prove(vec![0, 4095])?);
+    let mut ledger = Ledger::new();
+    ledger.register(&Registration { vk: tx.vk.clone(), common: tx.common.clone() }, 0)?;
+    assert!(ledger.apply(&tx).is_applied());
+
+    assert!(circuit.prove(vec![0, 4096]).is_err());
+    assert!(circuit.prove(vec![1 << 20, 0]).is_err());
+    Ok(())
+}
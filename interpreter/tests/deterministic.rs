@@ -0,0 +1,58 @@
+#![cfg(feature = "deterministic-prover")]
+
+use std::path::PathBuf;
+
+use zk::compat::UPDATE_ENV;
+use zk::db::Ledger;
+use zk::deterministic::DeterministicProver;
+use zk::metrics::RejectReason;
+use zk::*;
+
+fn zk_counter_circuit() -> ZKPCircuit {
+    ZKPCircuit::new(CircuitConfig::standard_recursion_zk_config(), 2, |builder, targets| {
+        let one = builder.one();
+        let s: Target = builder.add(targets[0], one);
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+        builder.connect(s, targets[1]);
+    })
+}
+
+fn golden_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden/deterministic_counter.tx")
+}
+
+#[test]
+fn seeded_proofs_are_byte_identical() -> Result<(), anyhow::Error> {
+    let circuit = zk_counter_circuit();
+    // Production proofs of the zero-knowledge config are blinded afresh every time
+    assert_ne!(circuit.prove(vec![0, 1])?.to_bytes(), circuit.prove(vec![0, 1])?.to_bytes());
+
+    let prover = DeterministicProver::new(&circuit, 7)?;
+    let tx = prover.transaction(&prover.prove(vec![0, 1])?)?;
+    let again = DeterministicProver::new(&circuit, 7)?;
+    assert_eq!(again.transaction(&again.prove(vec![0, 1])?)?.serialize(), tx.serialize());
+    let other = DeterministicProver::new(&circuit, 8)?;
+    assert_ne!(other.transaction(&other.prove(vec![0, 1])?)?.serialize(), tx.serialize());
+
+    // The proof is of the circuit itself, but without hiding, so it only applies where the
+    // circuit was registered with the prover's common data
+    let mut ledger = Ledger::new();
+    ledger.register(&circuit.registration(), 0)?;
+    let receipt = ledger.apply(&tx);
+    assert!(!receipt.is_applied());
+    assert_eq!(ledger.metrics().rejected_count(RejectReason::CommonData), 1);
+
+    let mut ledger = Ledger::new();
+    let vk_id = ledger.register(&prover.registration()?, 0)?;
+    assert_eq!(tx.vk_id(), vk_id);
+    assert!(ledger.apply(&tx).is_applied());
+    assert_eq!(ledger.state(&vk_id), Some(1));
+
+    // The transaction stays the same across runs; verifier-only builds check it as a fixture
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        std::fs::write(golden_path(), tx.serialize())?;
+    }
+    assert!(std::fs::read(golden_path())? == tx.serialize(), "the golden transaction changed");
+    Ok(())
+}
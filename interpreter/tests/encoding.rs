@@ -0,0 +1,46 @@
+#![cfg(feature = "prover")]
+
+use plonky2::field::types::PrimeField64;
+use zk::encoding::{add_signed, decode_signed, encode_signed, mul_fixed, range_check_signed, sub_signed, FixedPoint};
+use zk::*;
+
+const BITS: usize = 30;
+
+/// `price * quantity - discount + adjustment`, all in cents
+fn pricing_circuit(cents: FixedPoint) -> ZKPCircuit {
+    ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 4, |builder, targets| {
+        for &target in targets.iter() {
+            range_check_signed(builder, target, BITS);
+        }
+        let gross = mul_fixed(builder, targets[0], targets[1], cents, BITS);
+        let net = sub_signed(builder, gross, targets[2], BITS);
+        let total = add_signed(builder, net, targets[3], BITS);
+        builder.register_public_input(total);
+    })
+}
+
+#[test]
+fn pricing_circuit_computes_in_fixed_point() -> Result<(), anyhow::Error> {
+    let cents = FixedPoint::new(2)?;
+    let circuit = pricing_circuit(cents);
+    let prove = |price: &str, quantity: &str, discount: &str, adjustment: i64| -> Result<String, anyhow::Error> {
+        let inputs = vec![cents.encode(price)?, cents.encode(quantity)?, cents.encode(discount)?, encode_signed(adjustment)?];
+        let proof = circuit.prove(inputs)?;
+        circuit.circuit_data.verify(proof.clone())?;
+        cents.decode(proof.public_inputs[0].to_canonical_u64())
+    };
+
+    assert_eq!(prove("19.99", "3", "5.00", 0)?, "54.97");
+    // 2.49 * 1.5 = 3.735, rounded down to 3.73
+    assert_eq!(prove("2.49", "1.50", "0", -1)?, "3.72");
+    // Negative deltas take the total below zero
+    assert_eq!(prove("1.00", "-2", "0.50", 0)?, "-2.50");
+    assert_eq!(prove("-0.01", "0.50", "0", 0)?, "-0.01");
+    assert_eq!(cents.mul(cents.to_units("-0.01")?, cents.to_units("0.50")?), -1);
+
+    // Values past the range checks cannot be proven
+    assert!(prove("10737418.24", "1", "0", 0).is_err());
+    assert!(prove("100000.00", "100000.00", "0", 0).is_err(), "the product overflows");
+    assert_eq!(decode_signed(GoldilocksField::NEG_ONE.to_canonical_u64())?, -1);
+    Ok(())
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "prover")]
+
+use std::sync::Arc;
+
+use zk::counter::counter_zkp_circuit;
+use zk::db::Ledger;
+use zk::history::HistoryCircuit;
+use zk::metrics::RejectReason;
+use zk::node::{Client, Node};
+use zk::txn::{Bundle, Registration};
+use zk::*;
+
+#[test]
+fn fresh_ledger_syncs_from_a_history_proof() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let history = Arc::new(HistoryCircuit::new(&circuit.circuit_data.common)?);
+
+    // Circuits of another shape can be registered, but not folded into the history
+    let other = ZKPCircuit::new(CircuitConfig::standard_ecc_config(), 2, |builder, targets| {
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+    });
+    let mut ledger = Ledger::new();
+    let vk_id = ledger.register(&circuit.registration(), 0)?;
+    ledger.register(&other.registration(), 0)?;
+    ledger.track_history(history.clone())?;
+    let genesis = ledger.state_root();
+    assert!(ledger.history().unwrap().proof().is_none());
+
+    // Registrations and transactions after genesis both extend the history
+    let later = Registration { vk: b"a circuit registered later".to_vec(), common: Vec::new() };
+    ledger.register(&later, 5)?;
+    let bundle = Bundle { transactions: vec![circuit.transaction(&circuit.prove(vec![0, 1])?), circuit.transaction(&circuit.prove(vec![1, 2])?)] };
+    assert!(ledger.apply_bundle(&bundle).is_applied());
+    let proof = ledger.history().unwrap().proof().unwrap().clone();
+    let statement = history.verify(&proof)?;
+    assert_eq!((statement.genesis, statement.state_root, statement.height), (genesis, ledger.state_root(), 2));
+
+    // A fresh node checks one proof against the snapshot instead of replaying the transactions
+    let mut synced = Ledger::from_history(ledger.snapshot(), history.clone(), proof.clone(), &genesis)?;
+    assert_eq!(synced.state(&vk_id), Some(2));
+    assert_eq!(synced.commitment(), ledger.commitment());
+    assert!(synced.apply(&circuit.transaction(&circuit.prove(vec![2, 3])?)).is_applied());
+    let statement = history.verify(synced.history().unwrap().proof().unwrap())?;
+    assert_eq!((statement.state_root, statement.height), (synced.state_root(), 3));
+
+    // The proof binds the genesis, the height and every state of the snapshot
+    assert!(Ledger::from_history(ledger.snapshot(), history.clone(), proof.clone(), &HashOut::ZERO).is_err());
+    assert!(Ledger::from_history(synced.snapshot(), history.clone(), proof.clone(), &genesis).is_err());
+    let mut forged = Ledger::new();
+    forged.register(&circuit.registration(), 7)?;
+    forged.register(&other.registration(), 0)?;
+    forged.register(&later, 5)?;
+    let mut snapshot = forged.snapshot();
+    snapshot.height = 2;
+    snapshot.commitment = zk::snapshot::commit(snapshot.height, &snapshot.last_tx, &snapshot.states, &snapshot.abis, &snapshot.commons);
+    assert!(Ledger::from_history(snapshot, history.clone(), proof.clone(), &genesis).is_err());
+    let mut tampered = proof;
+    tampered.public_inputs[8] = GoldilocksField::from_canonical_u64(3);
+    assert!(history.verify(&tampered).is_err());
+
+    let receipt = synced.apply(&other.transaction(&other.prove(vec![0, 9])?));
+    assert!(!receipt.is_applied(), "{:?}", receipt);
+    assert_eq!(synced.metrics().rejected_count(RejectReason::History), 1);
+    assert!(ledger.track_history(history).is_err(), "history started after genesis");
+    Ok(())
+}
+
+#[test]
+fn node_serves_its_history_to_a_syncing_node() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let history = Arc::new(HistoryCircuit::new(&circuit.circuit_data.common)?);
+    let mut ledger = Ledger::new();
+    let vk_id = digest_to_hex(&ledger.register(&circuit.registration(), 0)?);
+    ledger.track_history(history.clone())?;
+    let genesis = ledger.state_root();
+
+    let source = Node::bind("127.0.0.1:0", ledger)?.spawn()?;
+    let to_source = Client::new(source.local_addr());
+    assert_eq!(to_source.history()?, None);
+    assert!(to_source.submit(&circuit.transaction(&circuit.prove(vec![0, 1])?))?.is_applied());
+
+    // Another node starts from the snapshot and history proof of the first, without replaying
+    let proof = history.proof_from_bytes(to_source.history()?.expect("the history moved past genesis"))?;
+    let synced = Ledger::from_history(to_source.snapshot()?, history.clone(), proof, &genesis)?;
+    let target = Node::bind("127.0.0.1:0", synced)?.spawn()?;
+    let to_target = Client::new(target.local_addr());
+    assert_eq!(to_target.state(&vk_id)?, Some(1));
+    assert_eq!(to_target.state_root()?, to_source.state_root()?);
+
+    assert!(to_target.submit(&circuit.transaction(&circuit.prove(vec![1, 2])?))?.is_applied());
+    let proof = history.proof_from_bytes(to_target.history()?.expect("the history moved on"))?;
+    let statement = history.verify(&proof)?;
+    assert_eq!((statement.genesis, statement.state_root, statement.height), (genesis, to_target.state_root()?, 2));
+
+    source.shutdown()?;
+    target.shutdown()?;
+    Ok(())
+}
@@ -0,0 +1,168 @@
+#![cfg(feature = "prover")]
+
+use plonky2::util::serialization::GateSerializer;
+use zk::counter::counter_zkp_circuit;
+use zk::db::Ledger;
+use zk::gates::lookup_range_check;
+use zk::limits::Limits;
+use zk::metrics::RejectReason;
+use zk::txn::{Registration, Transaction};
+use zk::*;
+
+// Byte offsets into the common data of a `standard_recursion_config` circuit
+const NUM_WIRES: usize = 0;
+const SECURITY_BITS: usize = 24;
+const QUERY_ROUNDS: usize = 66;
+const POW_BITS: usize = 74;
+// The FRI config is repeated in the FRI parameters, which proofs are verified with
+const PARAMS_QUERY_ROUNDS: usize = 111;
+const PARAMS_POW_BITS: usize = 119;
+const ARITY_BITS_LEN: usize = 140;
+const DEGREE_BITS: usize = 148;
+const SELECTOR_INDICES_LEN: usize = 157;
+
+fn patch_u64(data: &[u8], offset: usize, value: u64) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    data
+}
+
+fn patch_u32(data: &[u8], offset: usize, value: u32) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    data
+}
+
+/// Offset of the payload of the last gate whose id starts with `id` in the circuit's common
+/// data, where the gates come last, each a u32 tag and its payload
+fn gate_payload(circuit: &ZKPCircuit, id: &str) -> usize {
+    let common = &circuit.circuit_data.common;
+    let mut end = circuit.get_common_circuit_data().len();
+    for gate in common.gates.iter().rev() {
+        let mut bytes = Vec::new();
+        DefaultGateSerializer.write_gate(&mut bytes, gate, common).expect("Failed to serialize gate");
+        end -= bytes.len();
+        if gate.0.id().starts_with(id) {
+            return end + 4;
+        }
+    }
+    panic!("No {} in the circuit", id)
+}
+
+#[test]
+fn malicious_circuit_data_is_rejected_before_allocation() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let vk = circuit.get_vk();
+    let common = circuit.get_common_circuit_data();
+
+    let vk_corpus: Vec<(&str, Vec<u8>)> = vec![
+        ("cap height 63", patch_u64(&vk, 0, 63)),
+        ("cap height beyond shift width", patch_u64(&vk, 0, 200)),
+        ("cap height not matching length", patch_u64(&vk, 0, 3)),
+        ("truncated", vk[..vk.len() - 1].to_vec()),
+    ];
+    for (name, data) in vk_corpus {
+        assert!(deserialize_vk_from_bytes(data).is_err(), "vk {} was accepted", name);
+    }
+
+    let common_corpus: Vec<(&str, Vec<u8>)> = vec![
+        ("huge number of wires", patch_u64(&common, NUM_WIRES, u64::MAX)),
+        ("huge arity vector", patch_u64(&common, ARITY_BITS_LEN, u64::MAX)),
+        ("arity vector longer than the data", patch_u64(&common, ARITY_BITS_LEN, 60_000)),
+        ("huge degree bits", patch_u64(&common, DEGREE_BITS, 1 << 40)),
+        ("huge selector vector", patch_u64(&common, SELECTOR_INDICES_LEN, 1 << 62)),
+        ("truncated header", common[..100].to_vec()),
+    ];
+    for (name, data) in common_corpus {
+        assert!(deserialize_common_from_bytes(data).is_err(), "common {} was accepted", name);
+    }
+
+    // Gates size themselves by counts they read, and lookup gates index the tables with one
+    let lookups = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 1, |builder, targets| {
+        lookup_range_check(builder, targets[0], 16);
+        builder.register_public_input(targets[0]);
+    });
+    let lookup_common = lookups.get_common_circuit_data();
+    deserialize_common_from_bytes(lookup_common.clone())?;
+    let gate_corpus: Vec<(&str, Vec<u8>, &str)> = vec![
+        ("lookup table index past the tables", patch_u64(&lookup_common, gate_payload(&lookups, "LookupGate") + 8, 1000), "cannot be read"),
+        ("lookup table gate index past the tables", patch_u64(&lookup_common, gate_payload(&lookups, "LookupTableGate") + 16, 1000), "cannot be read"),
+        ("lookup gate with huge slot count", patch_u64(&lookup_common, gate_payload(&lookups, "LookupGate"), u64::MAX), "wires"),
+        ("arithmetic gate with huge operation count", patch_u64(&lookup_common, gate_payload(&lookups, "ArithmeticGate"), u64::MAX), "wires"),
+        ("arithmetic gate wider than the circuit", patch_u64(&lookup_common, gate_payload(&lookups, "ArithmeticGate"), 1 << 20), "wires"),
+    ];
+    for (name, data, reason) in gate_corpus {
+        let error = deserialize_common_from_bytes(data).err().unwrap_or_else(|| panic!("common {} was accepted", name));
+        assert!(format!("{:#}", error).contains(reason), "{}: {:#}", name, error);
+    }
+
+    let common_data = deserialize_common_from_bytes(common)?;
+    let proof = circuit.prove(vec![0, 1])?.to_bytes();
+    let proof_corpus: Vec<(&str, Vec<u8>)> = vec![
+        ("empty", vec![]),
+        ("truncated", proof[..proof.len() / 2].to_vec()),
+        ("oversized", vec![0; Limits::default().max_proof_size + 1]),
+    ];
+    for (name, data) in proof_corpus {
+        assert!(deserialize_proof_from_bytes(data, common_data.clone()).is_err(), "proof {} was accepted", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn ledger_enforces_configured_limits() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let proof = circuit.prove(vec![0, 1])?;
+    let tx = circuit.transaction(&proof);
+
+    let mut strict = Ledger::with_limits(Limits { max_proof_size: 1024, ..Limits::default() });
+    strict.register(&Registration { vk: tx.vk.clone(), common: tx.common.clone() }, 0)?;
+    let receipt = strict.apply_bytes(&tx.serialize());
+    assert!(!receipt.is_applied());
+    assert!(format!("{:?}", receipt.status).contains("limit is 1024"));
+
+    let mut ledger = Ledger::new();
+    ledger.register(&Registration { vk: tx.vk.clone(), common: tx.common.clone() }, 0)?;
+    assert!(ledger.apply_bytes(&tx.serialize()).is_applied());
+
+    // A lying common data inside an otherwise well-formed transaction is rejected, not panicked on
+    let hostile = Transaction { common: patch_u64(&tx.common, ARITY_BITS_LEN, u64::MAX), ..tx };
+    let receipt = ledger.apply_bytes(&hostile.serialize());
+    assert!(format!("{:?}", receipt.status).contains("registered with"));
+    Ok(())
+}
+
+#[test]
+fn weakened_common_data_is_rejected() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let tx = circuit.transaction(&circuit.prove(vec![0, 1])?);
+
+    // With no query rounds, FRI checks nothing and any proof of the right shape verifies
+    let no_queries = patch_u64(&patch_u64(&tx.common, QUERY_ROUNDS, 0), PARAMS_QUERY_ROUNDS, 0);
+    let corpus: Vec<(&str, Vec<u8>)> = vec![
+        ("no query rounds", no_queries.clone()),
+        ("no query rounds in the FRI parameters", patch_u64(&tx.common, PARAMS_QUERY_ROUNDS, 0)),
+        ("no proof of work", patch_u32(&patch_u32(&tx.common, POW_BITS, 0), PARAMS_POW_BITS, 0)),
+        ("no security bits", patch_u64(&tx.common, SECURITY_BITS, 0)),
+    ];
+    for (name, data) in corpus {
+        let error = deserialize_common_from_bytes(data).err().unwrap_or_else(|| panic!("common {} was accepted", name));
+        assert!(error.to_string().contains("minimum"), "{}: {}", name, error);
+    }
+
+    // A circuit only accepts transactions carrying the common data it was registered with
+    let mut ledger = Ledger::new();
+    ledger.register(&circuit.registration(), 0)?;
+    let forged = Transaction { common: no_queries.clone(), ..circuit.transaction(&circuit.prove(vec![0, 1])?) };
+    let receipt = ledger.apply(&forged);
+    assert!(!receipt.is_applied(), "{:?}", receipt);
+    assert_eq!(ledger.metrics().rejected_count(RejectReason::CommonData), 1);
+    assert!(ledger.register(&Registration { vk: tx.vk.clone(), common: no_queries.clone() }, 0).is_err());
+
+    // Registered with weakened common data, the circuit still gets none of its transactions through
+    let mut weak = Ledger::new();
+    weak.register(&Registration { vk: tx.vk.clone(), common: no_queries.clone() }, 0)?;
+    let receipt = weak.apply(&forged);
+    assert!(format!("{:?}", receipt.status).contains("bits of security"), "{:?}", receipt);
+    Ok(())
+}
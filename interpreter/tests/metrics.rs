@@ -0,0 +1,98 @@
+#![cfg(feature = "prover")]
+
+use std::collections::HashMap;
+
+use zk::block::Chain;
+use zk::counter::counter_zkp_circuit;
+use zk::db::Ledger;
+use zk::metrics::{Metrics, RejectReason};
+use zk::node::{Client, Node};
+use zk::*;
+
+/// Parses the samples of a Prometheus text scrape, keyed by name and labels
+fn parse(scrape: &str) -> HashMap<String, f64> {
+    scrape
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let (key, value) = line.rsplit_once(' ').expect("sample has a value");
+            (key.to_string(), value.parse().expect("sample value is a number"))
+        })
+        .collect()
+}
+
+#[test]
+fn node_exports_verification_metrics() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let client = Client::new(handle.local_addr());
+    client.register(&circuit.registration())?;
+
+    for i in 0..2 {
+        assert!(client.submit(&circuit.transaction(&circuit.prove(vec![i, i + 1])?))?.is_applied());
+    }
+    let stale = circuit.transaction(&circuit.prove(vec![0, 1])?);
+    assert!(!client.submit(&stale)?.is_applied());
+    assert!(!client.submit_bytes(&[1, 2, 3])?.is_applied());
+    let unregistered = ZKPCircuit::new(CircuitConfig::standard_ecc_config(), 2, |builder, targets| {
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+    });
+    assert!(!client.submit(&unregistered.transaction(&unregistered.prove(vec![0, 0])?))?.is_applied());
+
+    let scrape = client.metrics()?;
+    assert!(scrape.contains("# TYPE zk_verify_seconds histogram"));
+    assert!(scrape.contains("# TYPE zk_pending_transactions gauge"));
+    let samples = parse(&scrape);
+    assert_eq!(samples["zk_transactions_accepted_total"], 2.0);
+    assert_eq!(samples["zk_transactions_rejected_total{reason=\"stale_state\"}"], 1.0);
+    assert_eq!(samples["zk_transactions_rejected_total{reason=\"decode\"}"], 1.0);
+    assert_eq!(samples["zk_transactions_rejected_total{reason=\"unknown_circuit\"}"], 1.0);
+    assert_eq!(samples["zk_transactions_rejected_total{reason=\"invalid_proof\"}"], 0.0);
+
+    // Only the transactions that got as far as proof verification are timed
+    assert_eq!(samples["zk_verify_seconds_count"], 2.0);
+    assert_eq!(samples["zk_verify_seconds_bucket{le=\"+Inf\"}"], 2.0);
+    assert!(samples["zk_verify_seconds_sum"] > 0.0);
+    assert_eq!(samples["zk_apply_seconds_count"], 4.0);
+    // Every submission is decoded, and the three of known circuits have their vk and proof decoded
+    assert_eq!(samples["zk_decode_seconds_count"], 5.0);
+    assert_eq!(samples["zk_deserialize_seconds_count"], 3.0);
+    assert_eq!(samples["zk_proof_size_bytes_count"], 4.0);
+
+    // Buckets are cumulative
+    let buckets: Vec<f64> = scrape.lines().filter(|line| line.starts_with("zk_apply_seconds_bucket")).map(|line| parse(line).into_values().next().unwrap()).collect();
+    assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    handle.shutdown()?;
+    Ok(())
+}
+
+#[test]
+fn blocks_count_transactions_once() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let mut genesis = Ledger::new();
+    genesis.register(&circuit.registration(), 0)?;
+    let metrics = genesis.metrics().clone();
+    let mut chain = Chain::new(genesis);
+
+    let mut builder = chain.builder();
+    for i in 0..2 {
+        builder.push(&circuit.transaction(&circuit.prove(vec![i, i + 1])?).serialize());
+    }
+    builder.push(&[0]);
+    // The block being built holds the two accepted transactions until it is sealed
+    let pending = |metrics: &Metrics| parse(&metrics.render())["zk_pending_transactions"];
+    assert!(metrics.render().contains("# TYPE zk_pending_transactions gauge"));
+    assert_eq!(pending(&metrics), 2.0);
+    let (block, _) = builder.build();
+    assert_eq!(pending(&metrics), 0.0);
+
+    // Appending and verifying the chain replay the block without counting it again
+    chain.append(block)?;
+    chain.verify()?;
+    assert_eq!(metrics.accepted_count(), 2);
+    assert_eq!(metrics.rejected_count(RejectReason::Decode), 1);
+    assert_eq!(chain.ledger().metrics().accepted_count(), 2);
+    Ok(())
+}
@@ -0,0 +1,185 @@
+#![cfg(feature = "prover")]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use zk::abi::Abi;
+use zk::counter::counter_zkp_circuit;
+use zk::db::{Ledger, ReceiptStatus};
+use zk::limits::Limits;
+use zk::node::{Client, Node};
+use zk::snapshot::Snapshot;
+use zk::txn::Transaction;
+use zk::*;
+
+fn counter_tx(circuit: &ZKPCircuit, current: u64) -> Result<Transaction, anyhow::Error> {
+    let proof = circuit.prove(vec![current, current + 1])?;
+    Ok(circuit.transaction(&proof))
+}
+
+#[test]
+fn node_applies_transactions_and_serves_state() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let client = Client::new(handle.local_addr());
+
+    let vk_id = client.register(&circuit.registration())?;
+    assert_eq!(client.state(&vk_id)?, Some(0));
+
+    let mut applied = Vec::new();
+    for i in 0..3 {
+        let tx = counter_tx(&circuit, i)?;
+        let receipt = client.submit(&tx)?;
+        assert_eq!(receipt.status, ReceiptStatus::Applied { old: i, new: i + 1 });
+        assert_eq!(client.receipt(&receipt.tx_hash)?, Some(receipt.clone()));
+        applied.push((tx, receipt));
+    }
+    assert_eq!(client.state(&vk_id)?, Some(3));
+
+    // Resubmitting an applied transaction is rejected, but its receipt still says it applied
+    let (tx, receipt) = &applied[0];
+    assert!(!client.submit(tx)?.is_applied());
+    assert_eq!(client.receipt(&receipt.tx_hash)?.as_ref(), Some(receipt));
+
+    // The reported state is proven under the state root, and a tampered value is caught
+    let root = client.state_root()?;
+    assert_eq!(client.verified_state(&vk_id, &root)?, Some(3));
+    let mut forged = client.state_with_proof(&vk_id)?.expect("state is known");
+    forged.value = 4;
+    assert!(forged.verify(&root).is_err());
+    assert!(client.verified_state(&vk_id, &HashOut::default()).is_err());
+
+    // Replaying an old transition is rejected and leaves the state untouched
+    let stale = client.submit(&counter_tx(&circuit, 0)?)?;
+    assert!(!stale.is_applied());
+    assert_eq!(client.state(&vk_id)?, Some(3));
+
+    // Garbage is rejected with a receipt instead of taking the node down
+    let garbage = client.submit_bytes(&[1, 2, 3])?;
+    assert!(!garbage.is_applied());
+
+    let ledger = handle.shutdown()?;
+    assert_eq!(ledger.state(&digest_from_hex(&vk_id)?), Some(3));
+    assert_eq!(ledger.state_root(), root);
+    Ok(())
+}
+
+#[test]
+fn rejected_receipts_are_capped() -> Result<(), anyhow::Error> {
+    let mut ledger = Ledger::with_limits(Limits { max_rejected_receipts: 2, ..Limits::default() });
+    let garbage: Vec<Vec<u8>> = (0..3).map(|i| vec![i; 3]).collect();
+    let receipts: Vec<_> = garbage.iter().map(|data| ledger.apply_bytes(data)).collect();
+    // The oldest rejection is dropped first
+    assert_eq!(ledger.receipt(&hash_bytes(&garbage[0])), None);
+    for (data, receipt) in garbage.iter().zip(&receipts).skip(1) {
+        assert_eq!(ledger.receipt(&hash_bytes(data)), Some(receipt));
+    }
+    Ok(())
+}
+
+#[test]
+fn node_rejects_unknown_circuits_and_shuts_down_on_request() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let client = Client::new(handle.local_addr());
+
+    let receipt = client.submit(&counter_tx(&circuit, 0)?)?;
+    assert_eq!(receipt.status, ReceiptStatus::Rejected { reason: "Unknown circuit".to_string() });
+    assert_eq!(client.state(&receipt.vk_id)?, None);
+    assert_eq!(client.receipt(&digest_to_hex(&HashOut::default()))?, None);
+
+    client.shutdown()?;
+    handle.shutdown()?;
+    Ok(())
+}
+
+#[test]
+fn node_restarts_from_snapshot() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let mut ledger = Ledger::new();
+    let vk_id = digest_to_hex(&ledger.register(&circuit.registration(), 0)?);
+    let handle = Node::bind("127.0.0.1:0", ledger)?.spawn()?;
+    let client = Client::new(handle.local_addr());
+
+    for i in 0..2 {
+        assert!(client.submit(&counter_tx(&circuit, i)?)?.is_applied());
+    }
+    let snapshot = client.snapshot()?;
+    let ledger = handle.shutdown()?;
+    assert_eq!(snapshot.height, 2);
+    assert_eq!(snapshot.commitment, ledger.commitment());
+
+    let path = std::env::temp_dir().join(format!("zk-node-snapshot-{}.bin", std::process::id()));
+    snapshot.write_to(&path)?;
+    let restored = Ledger::from_snapshot(Snapshot::read_from(&path)?, &ledger.commitment())?;
+    std::fs::remove_file(&path)?;
+
+    // The restarted node continues from the snapshot instead of genesis
+    let handle = Node::bind("127.0.0.1:0", restored)?.spawn()?;
+    let client = Client::new(handle.local_addr());
+    assert_eq!(client.state(&vk_id)?, Some(2));
+    assert!(client.submit(&counter_tx(&circuit, 2)?)?.is_applied());
+    assert_eq!(handle.shutdown()?.height(), 3);
+    Ok(())
+}
+
+#[test]
+fn node_answers_conflicting_registrations_with_409() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let client = Client::new(handle.local_addr());
+
+    let mut registration = circuit.registration();
+    let vk_id = client.register(&registration)?;
+    // Registering again is idempotent
+    assert_eq!(client.register(&registration)?, vk_id);
+    assert_eq!(client.register_with_abi(&registration, &Abi::default())?, vk_id);
+
+    // The same circuit with other common data conflicts on both routes alike
+    registration.common.push(0);
+    for result in [client.register(&registration), client.register_with_abi(&registration, &Abi::default())] {
+        let error = result.expect_err("conflicting registration").to_string();
+        assert!(error.starts_with("Node returned 409"), "{}", error);
+    }
+    assert_eq!(client.state(&vk_id)?, Some(0));
+    handle.shutdown()?;
+    Ok(())
+}
+
+#[test]
+fn node_serves_others_while_a_client_trickles_in_its_request() -> Result<(), anyhow::Error> {
+    let handle = Node::bind("127.0.0.1:0", Ledger::new())?.spawn()?;
+    let addr = handle.local_addr();
+    let client = Client::new(addr);
+
+    // A client trickling in its request does not hold up the others
+    let mut slow = TcpStream::connect(addr)?;
+    slow.write_all(b"GET /state_root HTTP/1.1\r\n")?;
+    let start = Instant::now();
+    client.state_root()?;
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // Nor can it keep its connection past the deadline by sending a byte now and then
+    let start = Instant::now();
+    let mut writer = slow.try_clone()?;
+    let trickle = std::thread::spawn(move || {
+        for _ in 0..20 {
+            if writer.write_all(b"X").is_err() {
+                break;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    });
+    slow.set_read_timeout(Some(Duration::from_secs(20)))?;
+    let mut response = String::new();
+    // The node may reset the connection on the bytes it left unread
+    let closed = slow.read_to_string(&mut response);
+    assert!(response.starts_with("HTTP/1.1 400") || closed.is_err(), "{:?}", response);
+    assert!(start.elapsed() < Duration::from_secs(15));
+    drop(slow);
+    trickle.join().expect("trickling thread panicked");
+
+    handle.shutdown()?;
+    Ok(())
+}
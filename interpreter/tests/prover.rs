@@ -0,0 +1,60 @@
+#![cfg(feature = "prover")]
+
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use zk::counter::counter_zkp_circuit;
+use zk::prover::{JobStatus, ProvingQueue};
+
+#[test]
+fn proofs_run_in_the_background() -> Result<(), anyhow::Error> {
+    let circuit = Arc::new(counter_zkp_circuit());
+    let queue = ProvingQueue::new(2, 8);
+
+    let handles = (0..3)
+        .map(|i| queue.prove(Arc::clone(&circuit), vec![i, i + 1], None))
+        .collect::<Result<Vec<_>, _>>()?;
+    let failing = queue.prove(Arc::clone(&circuit), vec![0, 2], None)?;
+    for (i, handle) in handles.into_iter().enumerate() {
+        let proof = handle.wait()?;
+        circuit.verify(&proof, vec![i as u64, i as u64 + 1])?;
+    }
+    assert!(failing.wait().is_err());
+    Ok(())
+}
+
+#[test]
+fn jobs_report_status_and_can_be_cancelled_or_time_out() -> Result<(), anyhow::Error> {
+    let circuit = Arc::new(counter_zkp_circuit());
+    let queue = ProvingQueue::new(1, 2);
+
+    // Hold the only worker until the test releases it
+    let (release, blocked) = mpsc::channel::<()>();
+    let (started, running) = mpsc::channel::<()>();
+    let worker_circuit = Arc::clone(&circuit);
+    let busy = queue.submit(
+        move || {
+            started.send(()).ok();
+            blocked.recv().ok();
+            worker_circuit.prove(vec![0, 1])
+        },
+        None,
+    )?;
+    running.recv()?;
+    assert_eq!(busy.status(), JobStatus::Running);
+
+    let cancelled = queue.prove(Arc::clone(&circuit), vec![1, 2], None)?;
+    let timed_out = queue.prove(Arc::clone(&circuit), vec![2, 3], Some(Duration::from_millis(50)))?;
+    assert_eq!(cancelled.status(), JobStatus::Queued);
+    assert!(queue.prove(Arc::clone(&circuit), vec![3, 4], None).is_err(), "queue accepted a job beyond its capacity");
+
+    assert!(cancelled.cancel());
+    assert_eq!(cancelled.status(), JobStatus::Cancelled);
+    assert!(timed_out.wait().unwrap_err().to_string().contains("TimedOut"));
+
+    release.send(())?;
+    assert!(busy.wait().is_ok());
+    assert!(cancelled.wait().unwrap_err().to_string().contains("Cancelled"));
+    Ok(())
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "prover")]
+
+use std::time::{Duration, Instant};
+
+use zk::counter::counter_zkp_circuit;
+use zk::db::Ledger;
+use zk::replication::{Replica, ReplicaClient, ReplicaStatus};
+
+/// Polls until `done` holds
+fn wait_until(done: impl Fn() -> Result<bool, anyhow::Error>) -> Result<(), anyhow::Error> {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !done()? {
+        if Instant::now() > deadline {
+            anyhow::bail!("Timed out waiting for replicas");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// Polls a replica until its status satisfies `done`
+fn wait_for(client: &ReplicaClient, done: impl Fn(&ReplicaStatus) -> bool) -> Result<ReplicaStatus, anyhow::Error> {
+    wait_until(|| Ok(done(&client.status()?)))?;
+    client.status()
+}
+
+#[test]
+fn replicas_follow_catch_up_and_fail_over() -> Result<(), anyhow::Error> {
+    let circuit = counter_zkp_circuit();
+    let txs = (0..4).map(|i| Ok(circuit.transaction(&circuit.prove(vec![i, i + 1])?))).collect::<Result<Vec<_>, anyhow::Error>>()?;
+    let mut genesis = Ledger::new();
+    let vk_id = genesis.register(&circuit.registration(), 0)?;
+
+    let leader = Replica::leader("127.0.0.1:0", genesis.clone())?.spawn()?;
+    let first = Replica::follower("127.0.0.1:0", genesis.clone(), leader.local_addr())?.spawn()?;
+    let (to_leader, to_first) = (ReplicaClient::new(leader.local_addr()), ReplicaClient::new(first.local_addr()));
+
+    // Only the leader takes transactions; followers replicate its log
+    for tx in &txs[..2] {
+        assert!(to_leader.submit(tx)?.is_applied());
+    }
+    assert!(to_first.submit(&txs[2]).is_err());
+    let root = to_leader.status()?.state_root;
+    wait_for(&to_first, |status| status.log_len == 2 && status.state_root == root)?;
+    assert_eq!(to_first.state(&vk_id)?, Some(2));
+
+    // A follower joining late catches up from the whole log
+    assert!(to_leader.submit(&txs[2])?.is_applied());
+    let second = Replica::follower("127.0.0.1:0", genesis.clone(), leader.local_addr())?.spawn()?;
+    let to_second = ReplicaClient::new(second.local_addr());
+    wait_for(&to_second, |status| status.log_len == 3)?;
+    assert_eq!(to_second.state(&vk_id)?, Some(3));
+    wait_for(&to_first, |status| status.log_len == 3)?;
+
+    // Fail over to the first follower in term 2, which makes the old leader follow it
+    assert_eq!(to_first.promote(2)?.term, 2);
+    assert!(to_first.promote(2).is_err(), "a term was reused");
+    let status = to_leader.status()?;
+    assert_eq!((status.leader, status.term, status.following), (false, 2, Some(first.local_addr())));
+    assert!(to_leader.submit(&txs[3]).is_err());
+    to_second.follow(first.local_addr(), 2)?;
+    assert!(to_first.submit(&txs[3])?.is_applied());
+    let status = wait_for(&to_second, |status| status.log_len == 4)?;
+    assert_eq!(status.state_root, to_first.status()?.state_root);
+    assert_eq!(to_first.log(3)?.entries[0].term, 2);
+    wait_for(&to_leader, |status| status.log_len == 4)?;
+    assert!(to_first.follow(leader.local_addr(), 1).is_err(), "the new leader followed an older term");
+
+    // A leader cut off from its successor keeps taking transactions, and drops them once it follows
+    let cut_off = Replica::leader("127.0.0.1:0", genesis.clone())?.spawn()?;
+    let to_cut_off = ReplicaClient::new(cut_off.local_addr());
+    for tx in &txs {
+        assert!(to_cut_off.submit(tx)?.is_applied());
+    }
+    to_cut_off.follow(first.local_addr(), 2)?;
+    // Both logs hold the same transactions, so only the terms of the last entry differ
+    wait_until(|| Ok(to_cut_off.log(3)?.entries.first().map(|entry| entry.term) == Some(2)))?;
+    assert_eq!(to_cut_off.log(0)?.entries, to_first.log(0)?.entries);
+    assert_eq!(to_cut_off.status()?.state_root, to_first.status()?.state_root);
+
+    // A leader of an older term steps down once a follower of a newer term reaches it
+    let stale = Replica::leader("127.0.0.1:0", genesis.clone())?.spawn()?;
+    let to_stale = ReplicaClient::new(stale.local_addr());
+    to_second.follow(stale.local_addr(), 2)?;
+    wait_for(&to_stale, |status| !status.leader && status.term == 2)?;
+    assert!(to_stale.submit(&txs[0]).is_err());
+    let status = wait_for(&to_second, |status| status.sync_error.is_some())?;
+    assert_eq!(status.log_len, 4);
+
+    // Each replica counts the transactions it applied once, replays included
+    for handle in [leader, first, cut_off] {
+        let (ledger, log) = handle.shutdown()?;
+        assert_eq!(ledger.metrics().accepted_count(), log.len() as u64);
+    }
+    for handle in [second, stale] {
+        handle.shutdown()?;
+    }
+    assert_eq!(genesis.metrics().accepted_count(), 0);
+    Ok(())
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "prover")]
+
+use zk::templates::{less_than, HashPreimage, MerkleSet, Membership, Range};
+use plonky2::field::types::PrimeField64;
+use zk::*;
+
+#[test]
+fn hash_preimage_template() -> Result<(), anyhow::Error> {
+    let template = HashPreimage::new(3);
+    let preimage = [7, 8, 9];
+    let hash = HashPreimage::hash(&preimage);
+    let proof = template.prove(&preimage)?;
+    template.verify(&proof, &hash)?;
+    assert!(template.verify(&proof, &HashPreimage::hash(&[7, 8, 10])).is_err());
+    assert!(template.prove(&[7, 8]).is_err());
+    Ok(())
+}
+
+#[test]
+fn range_template() -> Result<(), anyhow::Error> {
+    let template = Range::new(16);
+    for x in [10, 15, 20] {
+        let proof = template.prove(x, 10, 20)?;
+        template.verify(&proof, 10, 20)?;
+        assert!(template.verify(&proof, 11, 20).is_err());
+    }
+    assert!(template.prove(21, 10, 20).is_err());
+
+    // Bypassing the native check, the circuit itself refuses values outside the bounds
+    for x in [9, 21] {
+        assert!(template.circuit().prove(vec![x, 10, 20]).is_err(), "{} was proven in range", x);
+    }
+    Ok(())
+}
+
+#[test]
+fn less_than_matches_native_comparison() -> Result<(), anyhow::Error> {
+    let circuit = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, |builder, targets| {
+        let less = less_than(builder, targets[0], targets[1], 8);
+        builder.register_public_input(less.target);
+    });
+    for (a, b) in [(0, 0), (0, 1), (1, 0), (127, 128), (128, 127), (255, 255), (3, 200)] {
+        circuit.check_witness(vec![a, b])?;
+        let proof = circuit.prove(vec![a, b])?;
+        assert_eq!(proof.public_inputs[0], GoldilocksField::from_bool(a < b), "{} < {}", a, b);
+    }
+    Ok(())
+}
+
+#[test]
+fn membership_template() -> Result<(), anyhow::Error> {
+    let set = MerkleSet::new(&[3, 1, 4, 1, 5, 9], 3)?;
+    let template = Membership::new(3);
+    for value in [3, 4, 9] {
+        let proof = template.prove(&set, value)?;
+        template.verify(&proof, &set.root())?;
+    }
+    assert!(template.prove(&set, 2).is_err());
+    assert!(template.prove(&MerkleSet::new(&[3], 2)?, 3).is_err());
+
+    let other = MerkleSet::new(&[3, 1, 4, 1, 5, 8], 3)?;
+    let proof = template.prove(&set, 5)?;
+    assert!(template.verify(&proof, &other.root()).is_err());
+    assert!(MerkleSet::new(&[0; 9], 3).is_err());
+    Ok(())
+}
+
+#[test]
+fn membership_of_padding_is_refused() -> Result<(), anyhow::Error> {
+    let set = MerkleSet::new(&[1, 2, 3], 2)?;
+    let template = Membership::new(2);
+    assert!(template.prove(&set, 0).is_err());
+
+    // Bypassing the native check, 0 at a padded position does not lead to the root
+    let siblings = set.siblings(3).into_iter().flat_map(|sibling| sibling.elements.map(|e| e.to_canonical_u64()));
+    let forged = template.circuit().prove([0, 3].into_iter().chain(siblings).collect())?;
+    assert!(template.verify(&forged, &set.root()).is_err(), "0 was proven a member of {{1, 2, 3}}");
+    Ok(())
+}
+
+#[test]
+fn templates_are_zero_knowledge() -> Result<(), anyhow::Error> {
+    // The templates hide their witness, so their proofs must not leak it
+    for circuit in [HashPreimage::new(2).circuit(), Range::new(8).circuit(), Membership::new(2).circuit()] {
+        assert!(deserialize_common_from_bytes(circuit.get_common_circuit_data())?.config.zero_knowledge);
+    }
+    Ok(())
+}
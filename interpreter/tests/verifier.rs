@@ -0,0 +1,64 @@
+//! Runs with and without the `prover` feature, on a transaction proven ahead of time.
+
+use std::path::PathBuf;
+
+use zk::abi::Abi;
+use zk::db::Ledger;
+use zk::limits::Limits;
+use zk::metrics::RejectReason;
+use zk::txn::{Registration, Transaction};
+use zk::*;
+
+/// A proof of the counter moving from 0 to 1, written by the deterministic prover test, which
+/// runs with the `deterministic-prover` feature
+fn fixture() -> Result<Transaction, anyhow::Error> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden/deterministic_counter.tx");
+    Transaction::deserialize(&std::fs::read(path)?)
+}
+
+#[test]
+fn verifies_transactions_without_proving() -> Result<(), anyhow::Error> {
+    let tx = fixture()?;
+    let common = deserialize_common_from_bytes(tx.common.clone())?;
+    let proof = deserialize_proof_from_bytes(tx.proof_data.clone(), common.clone())?;
+    let vk = deserialize_vk_from_bytes(tx.vk.clone())?;
+    assert_eq!(proof.public_inputs, vec![GoldilocksField::ZERO, GoldilocksField::ONE]);
+    verify_circuit_data(proof.clone(), vk.clone(), common.clone())?;
+
+    let mut tampered = proof;
+    tampered.public_inputs[1] = GoldilocksField::TWO;
+    assert!(verify_circuit_data(tampered, vk, common).is_err());
+
+    // The fixture has no hiding, so the ledger registers the circuit with the deterministic prover's common data
+    let mut ledger = Ledger::new();
+    let vk_id = ledger.register(&Registration { vk: tx.vk.clone(), common: tx.common.clone() }, 0)?;
+    assert!(ledger.apply(&tx).is_applied());
+    assert_eq!(ledger.state(&vk_id), Some(1));
+    assert!(!ledger.apply(&tx).is_applied(), "a replayed transaction applied");
+    assert_eq!(ledger.metrics().rejected_count(RejectReason::StaleState), 1);
+    Ok(())
+}
+
+#[test]
+fn malicious_transaction_encodings_are_rejected() -> Result<(), anyhow::Error> {
+    let limits = Limits::default();
+    let tx = |vk: Vec<u8>| Transaction { vk, proof_data: vec![4, 5], common: vec![6], gate_serializer: "default".to_string(), abi: Abi::default() };
+    let mut claims_gigabytes = (1u64 << 32).to_le_bytes().to_vec();
+    claims_gigabytes.extend_from_slice(&[0; 16]);
+    let well_formed = tx(vec![1, 2, 3]).serialize();
+    Transaction::deserialize(&well_formed)?;
+
+    let corpus: Vec<(&str, Vec<u8>, &str)> = vec![
+        ("empty", vec![], "Failed to deserialize transaction"),
+        ("huge vk length prefix", u64::MAX.to_le_bytes().to_vec(), "Failed to deserialize transaction"),
+        ("vk length prefix claiming 4 GiB", claims_gigabytes, "Failed to deserialize transaction"),
+        ("oversized transaction", vec![0; limits.max_transaction_size + 1], "Transaction is"),
+        ("oversized vk field", tx(vec![0; limits.max_vk_size + 1]).serialize(), "Verifier key is"),
+        ("truncated", well_formed[..well_formed.len() - 1].to_vec(), "Failed to deserialize transaction"),
+    ];
+    for (name, data, reason) in corpus {
+        let error = Transaction::deserialize(&data).err().unwrap_or_else(|| panic!("{} was accepted", name));
+        assert!(error.to_string().contains(reason), "{}: {}", name, error);
+    }
+    Ok(())
+}
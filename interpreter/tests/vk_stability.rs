@@ -0,0 +1,78 @@
+#![cfg(feature = "prover")]
+
+use std::path::PathBuf;
+
+use zk::abi::Abi;
+use zk::compat::{library_circuits, Compatibility, GoldenDigests, UPDATE_ENV};
+use zk::db::Ledger;
+use zk::txn::{Registration, Transaction};
+use zk::*;
+
+fn golden_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden/vk_digests.json")
+}
+
+#[test]
+fn library_circuits_match_golden_digests() -> Result<(), anyhow::Error> {
+    let path = golden_path();
+    let mut golden = GoldenDigests::load(&path)?;
+    let circuits = library_circuits()?;
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        for (name, registration) in &circuits {
+            golden.record(name, registration);
+        }
+        golden.save(&path)?;
+    }
+    golden.check_all(&circuits)
+}
+
+#[test]
+fn changed_circuit_state_is_migrated() -> Result<(), anyhow::Error> {
+    let counter = counter::counter_zkp_circuit();
+    // The same counter with an added range check builds to a different vk
+    let rebuilt = ZKPCircuit::new(CircuitConfig::standard_recursion_config(), 2, |builder, targets| {
+        builder.range_check(targets[0], 32);
+        let one = builder.one();
+        let s = builder.add(targets[0], one);
+        builder.register_public_input(targets[0]);
+        builder.register_public_input(targets[1]);
+        builder.connect(s, targets[1]);
+    });
+
+    let mut golden = GoldenDigests::default();
+    assert!(matches!(golden.check("counter", &counter.registration()), Compatibility::Unrecorded { .. }));
+    golden.record("counter", &counter.registration());
+    assert_eq!(golden.check("counter", &counter.registration()), Compatibility::Unchanged);
+    assert!(matches!(golden.check("counter", &rebuilt.registration()), Compatibility::Changed { .. }));
+    let other_common = Registration { common: rebuilt.get_common_circuit_data(), ..counter.registration() };
+    assert!(matches!(golden.check("counter", &other_common), Compatibility::CommonChanged { .. }));
+    let err = golden.check_all(&[("counter", rebuilt.registration())]).unwrap_err();
+    assert!(err.to_string().contains(UPDATE_ENV));
+
+    // A node holding state under the old vk carries it over once the new digest is recorded,
+    // also where the circuit is registered with another ABI, which is part of its id
+    let abi: Abi = "parameter,new_state".parse()?;
+    let mut ledger = Ledger::new();
+    let old_id = ledger.register(&counter.registration(), 0)?;
+    let old_abi_id = ledger.register_with_abi(&counter.registration(), 5, abi.clone())?;
+    assert!(ledger.apply(&counter.transaction(&counter.prove(vec![0, 1])?)).is_applied());
+    let tx = Transaction { abi: abi.clone(), ..counter.transaction(&counter.prove(vec![5, 6])?) };
+    assert!(ledger.apply(&tx).is_applied());
+
+    golden.record("counter", &rebuilt.registration());
+    let golden = GoldenDigests::from_json(&golden.to_json())?;
+    assert_eq!(golden.get("counter").map(|e| e.previous.clone()), Some(vec![digest_to_hex(&old_id)]));
+    let migrations = golden.migrate_ledger(&mut ledger)?;
+    assert_eq!(migrations.len(), 2);
+    let (new_id, new_abi_id) = (circuit_id(&rebuilt.get_vk(), &Abi::default()), circuit_id(&rebuilt.get_vk(), &abi));
+    assert_eq!((ledger.state(&old_id), ledger.state(&old_abi_id)), (None, None));
+    assert_eq!((ledger.state(&new_id), ledger.state(&new_abi_id)), (Some(1), Some(6)));
+    assert_eq!(ledger.abi(&new_abi_id), Some(&abi));
+    assert!(golden.migrate_ledger(&mut ledger)?.is_empty());
+
+    assert!(ledger.apply(&rebuilt.transaction(&rebuilt.prove(vec![1, 2])?)).is_applied());
+    let tx = Transaction { abi, ..rebuilt.transaction(&rebuilt.prove(vec![6, 7])?) };
+    assert!(ledger.apply(&tx).is_applied());
+    assert_eq!((ledger.state(&new_id), ledger.state(&new_abi_id)), (Some(2), Some(7)));
+    Ok(())
+}